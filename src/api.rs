@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use axum::extract::{Request, State as AxumState};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use tokio::net::TcpListener;
+
+use crate::config::ApiConfig;
+use crate::secret::Secret;
+use crate::state::State;
+
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+struct ApiState {
+    token: Secret,
+    state: Arc<State>,
+}
+
+async fn authorize(
+    AxumState(api): AxumState<Arc<ApiState>>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorized = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|token| api.token.matches(token));
+    if !authorized {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(request).await
+}
+
+async fn dashboard() -> Html<&'static str> {
+    Html(DASHBOARD)
+}
+
+async fn status(AxumState(api): AxumState<Arc<ApiState>>) -> impl IntoResponse {
+    Json(api.state.status())
+}
+
+async fn history(AxumState(api): AxumState<Arc<ApiState>>) -> impl IntoResponse {
+    Json(api.state.history())
+}
+
+async fn update(AxumState(api): AxumState<Arc<ApiState>>) -> impl IntoResponse {
+    api.state.trigger_update();
+    StatusCode::ACCEPTED
+}
+
+async fn pause(AxumState(api): AxumState<Arc<ApiState>>) -> impl IntoResponse {
+    api.state.set_paused(true);
+    StatusCode::NO_CONTENT
+}
+
+async fn resume(AxumState(api): AxumState<Arc<ApiState>>) -> impl IntoResponse {
+    api.state.set_paused(false);
+    StatusCode::NO_CONTENT
+}
+
+pub async fn serve(config: ApiConfig, state: Arc<State>) -> Result<()> {
+    let api = Arc::new(ApiState {
+        token: config.token,
+        state,
+    });
+    let app = Router::new()
+        .route("/status", get(status))
+        .route("/history", get(history))
+        .route("/update", post(update))
+        .route("/pause", post(pause))
+        .route("/resume", post(resume))
+        .route_layer(middleware::from_fn_with_state(Arc::clone(&api), authorize))
+        // The page itself is public; it asks for the token before calling the API.
+        .route("/", get(dashboard))
+        .with_state(api);
+    let listener = TcpListener::bind(&config.listen).await?;
+    log::info!("Control API listening on {}", config.listen);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
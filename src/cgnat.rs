@@ -0,0 +1,80 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+
+use crate::route;
+
+/// Where routers answer NAT-PMP (RFC 6886) requests.
+const NAT_PMP_PORT: u16 = 5351;
+/// NAT-PMP's "external address" request: version 0, opcode 0.
+const EXTERNAL_ADDRESS_REQUEST: [u8; 2] = [0, 0];
+const NAT_PMP_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Whether `ip` is in 100.64.0.0/10, the range carriers use between their
+/// NAT and their customers (RFC 6598).
+pub fn is_shared(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    a == 100 && (64..128).contains(&b)
+}
+
+/// The router's WAN address, if it answers NAT-PMP.
+async fn router_wan(gateway: Ipv4Addr) -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await.ok()?;
+    let router = SocketAddr::new(IpAddr::V4(gateway), NAT_PMP_PORT);
+    socket
+        .send_to(&EXTERNAL_ADDRESS_REQUEST, router)
+        .await
+        .ok()?;
+    let mut response = [0u8; 16];
+    let (len, from) = tokio::time::timeout(NAT_PMP_TIMEOUT, socket.recv_from(&mut response))
+        .await
+        .ok()?
+        .ok()?;
+    // Version 0, opcode 128, result 0 (success), 4 bytes epoch, the address.
+    if from != router || len < 12 || response[..4] != [0, 128, 0, 0] {
+        return None;
+    }
+    Some(Ipv4Addr::new(
+        response[8],
+        response[9],
+        response[10],
+        response[11],
+    ))
+}
+
+/// A warning when the public IP `detected` belongs to a carrier-grade NAT
+/// rather than to this network, so inbound connections to it can't work:
+/// this host or the router has an address from 100.64.0.0/10, or the
+/// router's WAN address (asked over NAT-PMP) is not the detected one.
+pub async fn check(detected: IpAddr) -> Option<String> {
+    let IpAddr::V4(detected) = detected else {
+        return None;
+    };
+    let route = route::get(route::INTERNET_V4).await?;
+    if let Some(IpAddr::V4(source)) = route.source {
+        if is_shared(source) {
+            return Some(format!(
+                "This host's address {} is in 100.64.0.0/10: it is behind carrier-grade NAT, so connections to {} won't reach it",
+                source, detected
+            ));
+        }
+    }
+    let Some(IpAddr::V4(gateway)) = route.gateway else {
+        return None;
+    };
+    let wan = router_wan(gateway).await?;
+    if is_shared(wan) {
+        return Some(format!(
+            "The router's WAN address {} is in 100.64.0.0/10: it is behind carrier-grade NAT, so connections to {} won't reach this network",
+            wan, detected
+        ));
+    }
+    if wan != detected {
+        return Some(format!(
+            "The router's WAN address {} is not the public IP {}: another NAT sits in between (likely carrier-grade NAT), so connections to {} won't reach this network",
+            wan, detected, detected
+        ));
+    }
+    None
+}
@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::config::{Config, ProviderKind};
+use crate::output::{self, Format};
+use crate::provider::{DnsProvider, Providers, RecordContent, RecordSpec, RecordType};
+use crate::verify;
+
+const CHALLENGE_LABEL: &str = "_acme-challenge.";
+
+#[derive(Debug, Serialize)]
+struct Challenge {
+    name: String,
+    value: Option<String>,
+    /// Records added or removed.
+    changed: usize,
+    /// Whether every authoritative nameserver served the record in time.
+    propagated: Option<bool>,
+}
+
+/// Where the DNS-01 challenge for `domain` lives. A wildcard shares the
+/// name of its base domain. lego passes the challenge name itself, as an
+/// absolute name.
+fn challenge_name(domain: &str) -> String {
+    let domain = domain.trim_end_matches('.');
+    if domain.starts_with(CHALLENGE_LABEL) {
+        return domain.to_string();
+    }
+    let domain = domain.strip_prefix("*.").unwrap_or(domain);
+    format!("{}{}", CHALLENGE_LABEL, domain)
+}
+
+/// The provider of the configured domain `domain` falls under, else Cloudflare.
+async fn provider_for(config: &Config, domain: &str) -> Result<std::sync::Arc<dyn DnsProvider>> {
+    let domain = domain.trim_end_matches('.');
+    let domain = domain.strip_prefix(CHALLENGE_LABEL).unwrap_or(domain);
+    let domain = domain.strip_prefix("*.").unwrap_or(domain);
+    let kind = config
+        .domains
+        .iter()
+        .filter(|d| domain == d.name || domain.ends_with(&format!(".{}", d.name)))
+        .max_by_key(|d| d.name.len())
+        .map_or(ProviderKind::Cloudflare, |d| d.provider);
+    Providers::default().get(kind, config).await
+}
+
+fn print(format: Format, challenge: &Challenge, text: String) -> Result<()> {
+    if format.is_text() {
+        println!("{}", text);
+        Ok(())
+    } else {
+        output::print(format, challenge)
+    }
+}
+
+/// Adds `value` as a TXT record at `_acme-challenge.<domain>`, next to any
+/// other pending challenge there, and waits up to `timeout` until each
+/// authoritative nameserver serves it.
+pub async fn set_txt(
+    config: &Config,
+    domain: &str,
+    value: &str,
+    timeout: Duration,
+    format: Format,
+) -> Result<()> {
+    let provider = provider_for(config, domain).await?;
+    let name = challenge_name(domain);
+    let content = RecordContent::Txt(value.to_string());
+    let existing = provider.list_records(&name, RecordType::Txt).await?;
+    let mut challenge = Challenge {
+        name: name.clone(),
+        value: Some(value.to_string()),
+        changed: 0,
+        propagated: None,
+    };
+    if !existing.iter().any(|r| r.content == content) {
+        let spec = RecordSpec {
+            name: name.clone(),
+            content: content.clone(),
+            ttl: None,
+            proxied: false,
+            comment: None,
+        };
+        // A certificate for example.com and *.example.com has two challenges
+        // at the same name, so an existing one is kept.
+        if existing.is_empty() {
+            provider.upsert_record(&spec).await?;
+        } else {
+            provider.create_record(&spec).await?;
+        }
+        challenge.changed = 1;
+    }
+
+    let nameservers = provider.nameservers(&name).await?;
+    if nameservers.is_empty() {
+        log::warn!(
+            "The nameservers of {} are unknown, not waiting for propagation",
+            name
+        );
+    } else {
+        let served = verify::authoritative(&nameservers, &name, &content, timeout).await?;
+        challenge.propagated = Some(served);
+        if !served {
+            log::warn!(
+                "{} is not served by every nameserver after {}s",
+                name,
+                timeout.as_secs()
+            );
+        }
+    }
+    print(format, &challenge, format!("set {} TXT {}", name, value))
+}
+
+/// Removes the TXT records at `_acme-challenge.<domain>`: the one holding
+/// `value`, or all of them when no value is given.
+pub async fn clear_txt(
+    config: &Config,
+    domain: &str,
+    value: Option<&str>,
+    format: Format,
+) -> Result<()> {
+    let provider = provider_for(config, domain).await?;
+    let name = challenge_name(domain);
+    let mut challenge = Challenge {
+        name: name.clone(),
+        value: value.map(str::to_string),
+        changed: 0,
+        propagated: None,
+    };
+    for record in provider.list_records(&name, RecordType::Txt).await? {
+        if value.is_some_and(|v| record.content != RecordContent::Txt(v.to_string())) {
+            continue;
+        }
+        provider
+            .delete_record(&record)
+            .await
+            .with_context(|| format!("Failed to delete {} TXT {}", name, record.content))?;
+        challenge.changed += 1;
+    }
+    print(
+        format,
+        &challenge,
+        format!("cleared {} TXT record(s) at {}", challenge.changed, name),
+    )
+}
@@ -0,0 +1,117 @@
+use std::net::IpAddr;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use reqwest::{header, StatusCode};
+
+use crate::config::{self, Config};
+use crate::controller::{Report, ReportResult, REPORT_PATH};
+use crate::exit::Status;
+use crate::http;
+use crate::ip;
+use crate::output::{self, Format};
+use crate::secret::{self, Secret};
+use crate::updater::Outcome;
+
+/// Sends `ip` to the controller at `base`.
+async fn push(base: &str, token: &Secret, ip: IpAddr) -> Result<ReportResult> {
+    let url = format!("{}{}", base.trim_end_matches('/'), REPORT_PATH);
+    let response = http::client()
+        .post(&url)
+        .bearer_auth(token.expose())
+        .json(&Report { ip: Some(ip) })
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach the controller at {}", base))?;
+    match response.status() {
+        StatusCode::UNAUTHORIZED => bail!("The controller rejected the agent token"),
+        StatusCode::SERVICE_UNAVAILABLE => bail!("The controller has paused updates"),
+        StatusCode::TOO_MANY_REQUESTS => {
+            let wait = response
+                .headers()
+                .get(header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("?");
+            bail!(
+                "The controller asked to wait {}s before reporting again",
+                wait
+            )
+        }
+        status if !status.is_success() => bail!("The controller answered {}", status),
+        _ => Ok(response.json().await?),
+    }
+}
+
+fn log_result(result: &ReportResult) {
+    for host in &result.hosts {
+        match (&host.outcome, &host.error) {
+            (Some(Outcome::Updated), _) => log::info!("{} updated to {}", host.name, result.ip),
+            (_, Some(error)) => log::error!("{}: {}", host.name, error),
+            _ => log::debug!("{} unchanged", host.name),
+        }
+    }
+}
+
+/// Detects the public IP and reports it to the controller, which holds the
+/// provider credentials and writes this agent's records. With `once`, reports
+/// a single time and exits 0 when nothing changed, 2 when a record was written,
+/// or 1 when the controller failed to write one; otherwise reports each
+/// `interval` in which the address differs from the one last applied.
+pub async fn run(
+    config: &Config,
+    controller: &str,
+    token: Secret,
+    once: bool,
+    format: Format,
+) -> Result<ExitCode> {
+    if once {
+        let ip = ip::detect().await?;
+        let result = push(controller, &token, ip).await?;
+        if !format.is_text() {
+            output::print(format, &result)?;
+        } else {
+            for host in &result.hosts {
+                match (&host.outcome, &host.error) {
+                    (Some(Outcome::Updated), _) => println!("updated   {}", host.name),
+                    (Some(Outcome::WouldUpdate), _) => println!("would update {}", host.name),
+                    (_, Some(error)) => println!("failed    {}: {}", host.name, error),
+                    _ => println!("unchanged {}", host.name),
+                }
+            }
+        }
+        let status = if result.hosts.iter().any(|h| h.error.is_some()) {
+            Status::Failure
+        } else if result
+            .hosts
+            .iter()
+            .any(|h| h.outcome == Some(Outcome::Updated))
+        {
+            Status::Updated
+        } else {
+            Status::Success
+        };
+        return Ok(status.into());
+    }
+
+    let interval = Duration::from_secs(config.interval.unwrap_or(config::DEFAULT_INTERVAL));
+    let mut accepted = None;
+    loop {
+        match ip::detect().await {
+            Ok(ip) if accepted == Some(ip) => log::debug!("{} unchanged", ip),
+            Ok(ip) => match push(controller, &token, ip).await {
+                Ok(result) => {
+                    log_result(&result);
+                    // A failed host is retried with the next report.
+                    if result.hosts.iter().all(|h| h.error.is_none()) {
+                        accepted = Some(ip);
+                    }
+                }
+                Err(e) => log::error!("{}", secret::describe(&e)),
+            },
+            Err(e) if e.is::<ip::VpnActive>() => log::info!("{}", e),
+            Err(e) => log::error!("{}", secret::describe(&e)),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,158 @@
+use std::net::IpAddr;
+use std::process::ExitCode;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::cgnat;
+use crate::config::{Config, DomainConfig};
+use crate::exit::{self, Status};
+use crate::ip;
+use crate::output::{self, Format};
+use crate::provider::{Providers, RecordContent};
+use crate::secret;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Method {
+    /// Resolved through the system resolver.
+    Dns,
+    /// Proxied records resolve to the provider's edge, so the API is asked instead.
+    Api,
+}
+
+#[derive(Debug, Serialize)]
+struct DomainCheck {
+    name: String,
+    method: Method,
+    answers: Vec<String>,
+    matches: bool,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct CheckReport {
+    ip: Option<IpAddr>,
+    /// Why inbound connections to `ip` won't work, when behind carrier-grade NAT.
+    cgnat: Option<String>,
+    ok: bool,
+    error: Option<String>,
+    domains: Vec<DomainCheck>,
+}
+
+/// Resolves `name` through the system resolver.
+async fn resolve(name: &str) -> Result<Vec<IpAddr>> {
+    let addrs = tokio::net::lookup_host((name, 0)).await?;
+    Ok(addrs.map(|addr| addr.ip()).collect())
+}
+
+async fn check_domain(
+    config: &Config,
+    providers: &mut Providers,
+    domain: &DomainConfig,
+    ip: IpAddr,
+    check: &mut DomainCheck,
+) -> Result<()> {
+    let provider = providers.get(domain.provider, config).await?;
+    let expected = domain
+        .content
+        .as_deref()
+        .map_or(RecordContent::from(ip), RecordContent::infer);
+    // Only addresses can be checked with a plain lookup.
+    if (domain.proxied && provider.supports_proxy()) || expected.ip().is_none() {
+        check.method = Method::Api;
+        let record = provider
+            .get_record(&domain.name, expected.record_type())
+            .await?;
+        check.answers = record.map(|r| r.content.to_string()).into_iter().collect();
+    } else {
+        check.answers = resolve(&domain.name)
+            .await?
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+    }
+    check.matches = check.answers.contains(&expected.to_string());
+    Ok(())
+}
+
+async fn collect(config: &Config) -> (CheckReport, Status) {
+    let mut report = CheckReport {
+        ip: None,
+        cgnat: None,
+        ok: false,
+        error: None,
+        domains: Vec::new(),
+    };
+    let ip = match ip::detect().await {
+        Ok(ip) => ip,
+        Err(e) => {
+            report.error = Some(format!(
+                "Failed to detect the public IP: {}",
+                secret::describe(&e)
+            ));
+            return (report, exit::classify(&e));
+        }
+    };
+    report.ip = Some(ip);
+    report.cgnat = cgnat::check(ip).await;
+    let mut providers = Providers::default();
+    let mut status = Status::Success;
+    for domain in &config.domains {
+        let mut check = DomainCheck {
+            name: domain.name.clone(),
+            method: Method::Dns,
+            answers: Vec::new(),
+            matches: false,
+            error: None,
+        };
+        if let Err(e) = check_domain(config, &mut providers, domain, ip, &mut check).await {
+            check.error = Some(secret::describe(&e));
+            if status == Status::Success {
+                status = exit::classify(&e);
+            }
+        }
+        report.domains.push(check);
+    }
+    report.ok = report.domains.iter().all(|d| d.matches);
+    if status == Status::Success && !report.ok {
+        status = Status::Failure;
+    }
+    (report, status)
+}
+
+/// Exits 0 when every domain resolves to the detected IP, 1 on a mismatch
+/// and with the error's class when the check itself could not be carried out.
+pub async fn run(config: &Config, format: Format) -> Result<ExitCode> {
+    let (report, status) = collect(config).await;
+    let code = ExitCode::from(status);
+    if !format.is_text() {
+        output::print(format, &report)?;
+        return Ok(code);
+    }
+    if let Some(error) = &report.error {
+        eprintln!("{}", error);
+    }
+    if let Some(warning) = &report.cgnat {
+        eprintln!("Warning: {}", warning);
+    }
+    let expected = report.ip.map(|ip| ip.to_string()).unwrap_or_default();
+    for check in &report.domains {
+        match &check.error {
+            Some(e) => eprintln!("ERROR    {}: {}", check.name, e),
+            None => println!(
+                "{} {} {:?}=[{}] expected={}",
+                if check.matches {
+                    "OK      "
+                } else {
+                    "MISMATCH"
+                },
+                check.name,
+                check.method,
+                check.answers.join(", "),
+                expected
+            ),
+        }
+    }
+    Ok(code)
+}
@@ -0,0 +1,333 @@
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::process::ExitCode;
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+
+use crate::config::{self, Config, DomainConfig, IpFamily, DEFAULT_INTERVAL};
+use crate::encrypted;
+use crate::exit::Status;
+use crate::http;
+use crate::output::{self, Format};
+use crate::provider::{self, Providers, RecordType};
+use crate::secret::Secret;
+use crate::template;
+
+pub fn ensure_writable(path: &Path, force: bool) -> Result<()> {
+    if path.exists() && !force {
+        bail!(
+            "{} already exists, pass --force to overwrite it",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Writes `contents` to `path`, refusing to replace an existing file unless
+/// `force`. A new file is only readable by its owner, as it holds the token.
+pub fn write(path: &Path, contents: &str, force: bool) -> Result<()> {
+    ensure_writable(path, force)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
+
+pub fn init(
+    path: &Path,
+    domain: Option<&str>,
+    api_key: Option<&str>,
+    proxied: bool,
+    force: bool,
+) -> Result<()> {
+    let contents = config::example(domain, api_key, proxied, DEFAULT_INTERVAL);
+    write(path, &contents, force)
+}
+
+#[derive(Debug, Serialize)]
+struct Problem {
+    /// 1-based line the problem was traced to, when it could be.
+    line: Option<usize>,
+    message: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Validation {
+    file: String,
+    ok: bool,
+    problems: Vec<Problem>,
+}
+
+/// Collects problems, pointing each at the line that mentions its subject.
+struct Problems<'a> {
+    raw: &'a str,
+    found: Vec<Problem>,
+}
+
+impl Problems<'_> {
+    fn line_of_offset(&self, offset: usize) -> usize {
+        self.raw[..offset.min(self.raw.len())]
+            .lines()
+            .count()
+            .max(1)
+    }
+
+    /// First uncommented line containing `needle`, e.g. a quoted domain name or a `[section]`.
+    fn line_of(&self, needle: &str) -> Option<usize> {
+        self.raw
+            .lines()
+            .position(|l| !l.trim_start().starts_with('#') && l.contains(needle))
+            .map(|i| i + 1)
+    }
+
+    fn add(&mut self, needle: &str, message: String) {
+        let line = self.line_of(needle);
+        self.found.push(Problem { line, message });
+    }
+}
+
+fn check_fields(config: &Config, problems: &mut Problems) {
+    if config.interval == Some(0) {
+        problems.add(
+            "interval =",
+            "interval must be at least 1 second".to_string(),
+        );
+    }
+    if config.concurrency == Some(0) {
+        problems.add(
+            "concurrency =",
+            "concurrency must be at least 1".to_string(),
+        );
+    }
+    if config.http.connect_timeout == 0 {
+        problems.add(
+            "connect_timeout =",
+            "http.connect_timeout must be at least 1 second".to_string(),
+        );
+    }
+    if config.http.timeout == 0 {
+        problems.add(
+            "[http]",
+            "http.timeout must be at least 1 second".to_string(),
+        );
+    }
+    if config.ip.timeout == 0 {
+        problems.add("[ip]", "ip.timeout must be at least 1 second".to_string());
+    }
+    if let (Some(address), Some(family)) = (config.http.bind_address, config.ip_family) {
+        if address.is_ipv4() != (family == IpFamily::V4) {
+            problems.add(
+                "bind_address =",
+                format!(
+                    "bind_address {} can't detect the ip_family address",
+                    address
+                ),
+            );
+        }
+    }
+    let sources = [
+        ("sources =", &config.ip.sources),
+        ("sources_v4 =", &config.ip.sources_v4),
+        ("sources_v6 =", &config.ip.sources_v6),
+    ];
+    for (needle, sources) in sources {
+        for source in sources {
+            if url::Url::parse(source).is_err() {
+                problems.add(needle, format!("IP source {:?} is not a URL", source));
+            }
+        }
+    }
+    if let Some(Err(e)) = config.comment.as_deref().map(template::check_comment) {
+        problems.add("comment =", format!("{:#}", e));
+    }
+    if let Err(e) = http::init(&config.http) {
+        problems.add("[http]", format!("{:#}", e));
+    }
+    if config.domains.is_empty()
+        && config.zones.is_empty()
+        && config.dyndns_server.is_none()
+        && config.controller.is_none()
+    {
+        problems.found.push(Problem {
+            line: None,
+            message: "No domains configured".to_string(),
+        });
+    }
+}
+
+/// Every name the config manages, as `load_config` expands them.
+fn check_names(config: &Config, problems: &mut Problems) -> Vec<DomainConfig> {
+    let mut names = Vec::new();
+    for domain in &config.domains {
+        if !domain.via.is_empty() && !domain.aliases.is_empty() {
+            let message = format!("{}: aliases can't be combined with via", domain.name);
+            problems.add(&format!("\"{}\"", domain.name), message);
+        }
+        if domain.content.is_some() && (!domain.via.is_empty() || !domain.aliases.is_empty()) {
+            let message = format!(
+                "{}: content can't be combined with via or aliases",
+                domain.name
+            );
+            problems.add(&format!("\"{}\"", domain.name), message);
+        }
+        if let Err(e) = domain.check_record_id() {
+            problems.add(&format!("\"{}\"", domain.name), format!("{:#}", e));
+        }
+        if domain.aliases.is_empty() {
+            names.push(domain.clone());
+        } else {
+            names.extend(domain.clone().into_zone().domains());
+        }
+    }
+    for zone in &config.zones {
+        if zone.hosts.is_empty() {
+            let message = format!("Zone {} lists no hosts", zone.name);
+            problems.add(&format!("\"{}\"", zone.name), message);
+        }
+        names.extend(zone.domains());
+    }
+    let hosts = config.dyndns_server.iter().flat_map(|s| &s.hosts);
+    let agents = config.controller.iter().flat_map(|c| &c.agents);
+    let mut tokens = HashSet::new();
+    for agent in agents.clone() {
+        if !tokens.insert(agent.token.expose()) {
+            let message = format!("Agent {} shares its token with another agent", agent.name);
+            problems.add(&format!("\"{}\"", agent.name), message);
+        }
+    }
+    let agent_hosts = agents.flat_map(|a| &a.hosts);
+    let mut seen = HashSet::new();
+    for domain in names.iter().chain(hosts).chain(agent_hosts) {
+        let needle = format!("\"{}\"", domain.name);
+        // Checked the way this machine would claim it.
+        let name = match template::expand(&domain.name) {
+            Ok(name) => name,
+            Err(e) => {
+                problems.add(&needle, format!("{:#}", e));
+                continue;
+            }
+        };
+        if let Err(e) = provider::validate_name(&name) {
+            problems.add(&needle, format!("{:#}", e));
+        }
+        if !seen.insert(name.clone()) {
+            problems.add(&needle, format!("{} is listed more than once", name));
+        }
+    }
+    names
+}
+
+/// Builds each provider in use and, unless `offline`, looks every name up
+/// through it so a missing zone or a token without access shows up here.
+async fn check_providers(
+    config: &Config,
+    names: &[DomainConfig],
+    offline: bool,
+    problems: &mut Problems<'_>,
+) {
+    let mut providers = Providers::default();
+    let mut failed = HashSet::new();
+    for domain in names {
+        if failed.contains(&domain.provider) {
+            continue;
+        }
+        let needle = format!("\"{}\"", domain.name);
+        let provider = match providers.get(domain.provider, config).await {
+            Ok(provider) => provider,
+            Err(e) => {
+                problems.add(&needle, format!("{}: {:#}", domain.name, e));
+                failed.insert(domain.provider);
+                continue;
+            }
+        };
+        if offline {
+            continue;
+        }
+        // A name that doesn't expand was reported already.
+        let Ok(name) = template::expand(&domain.name) else {
+            continue;
+        };
+        if let Err(e) = provider.get_record(&name, RecordType::A).await {
+            problems.add(&needle, format!("{}: {:#}", domain.name, e));
+        }
+    }
+}
+
+/// Reports every problem in the config file at once instead of stopping at
+/// the first, for linting configs before they are deployed.
+pub async fn validate(
+    path: &Path,
+    api_key: Option<&str>,
+    offline: bool,
+    format: Format,
+) -> Result<ExitCode> {
+    let raw = encrypted::read(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    let mut problems = Problems {
+        raw: &raw,
+        found: Vec::new(),
+    };
+    match toml::from_str::<Config>(&raw) {
+        Ok(mut config) => {
+            if let Some(api_key) = api_key {
+                config
+                    .cloudflare
+                    .get_or_insert_with(Default::default)
+                    .api_key = Some(Secret::new(api_key));
+            }
+            check_fields(&config, &mut problems);
+            let names = check_names(&config, &mut problems);
+            check_providers(&config, &names, offline, &mut problems).await;
+        }
+        // Nothing else can be checked without a parsed config.
+        Err(e) => {
+            let line = e.span().map(|span| problems.line_of_offset(span.start));
+            let message = e.message().to_string();
+            problems.found.push(Problem { line, message });
+        }
+    }
+    let mut found = problems.found;
+    found.sort_by_key(|p| p.line);
+
+    let status = if found.is_empty() {
+        Status::Success
+    } else {
+        Status::Config
+    };
+    if !format.is_text() {
+        let validation = Validation {
+            file: path.display().to_string(),
+            ok: found.is_empty(),
+            problems: found,
+        };
+        output::print(format, &validation)?;
+        return Ok(status.into());
+    }
+    for problem in &found {
+        match problem.line {
+            Some(line) => println!("{}:{}: {}", path.display(), line, problem.message),
+            None => println!("{}: {}", path.display(), problem.message),
+        }
+    }
+    if found.is_empty() {
+        println!("{} is valid", path.display());
+    } else {
+        println!("{} problem(s) found", found.len());
+    }
+    Ok(status.into())
+}
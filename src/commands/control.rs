@@ -0,0 +1,98 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use anyhow::{bail, Context, Result};
+use reqwest::StatusCode;
+use serde::Serialize;
+
+use crate::config::{ApiConfig, Config, ControlConfig};
+#[cfg(windows)]
+use crate::control_pipe;
+#[cfg(unix)]
+use crate::control_socket;
+use crate::control_socket::Reply;
+use crate::output::{self, Format};
+
+#[derive(Debug, Serialize)]
+struct Control {
+    paused: bool,
+}
+
+fn report(paused: bool, format: Format) -> Result<()> {
+    if !format.is_text() {
+        return output::print(format, &Control { paused });
+    }
+    println!("{}", if paused { "paused" } else { "resumed" });
+    Ok(())
+}
+
+/// Where the local API answers: its listen address, with a wildcard host
+/// replaced by loopback.
+fn base(api: &ApiConfig) -> String {
+    match api.listen.parse::<SocketAddr>() {
+        Ok(mut addr) => {
+            if addr.ip().is_unspecified() {
+                addr.set_ip(match addr.ip() {
+                    IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                });
+            }
+            format!("http://{}", addr)
+        }
+        Err(_) => format!("http://{}", api.listen),
+    }
+}
+
+/// Sends `command` over this platform's control channel, if `control` sets one.
+#[cfg(unix)]
+async fn send(control: &ControlConfig, command: &str) -> Result<Option<Reply>> {
+    match &control.socket {
+        Some(socket) => Ok(Some(control_socket::send(socket, command).await?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(windows)]
+async fn send(control: &ControlConfig, command: &str) -> Result<Option<Reply>> {
+    match &control.pipe {
+        Some(pipe) => Ok(Some(control_pipe::send(pipe, command).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Pauses or resumes the running instance through its control socket or
+/// pipe, or else its `[api]`.
+pub async fn set_paused(config: &Config, paused: bool, format: Format) -> Result<()> {
+    let command = if paused { "pause" } else { "resume" };
+    let reply = match &config.control {
+        Some(control) => send(control, command).await?,
+        None => None,
+    };
+    if let Some(reply) = reply {
+        if !reply.ok {
+            bail!("{}", reply.error.unwrap_or_default());
+        }
+        return report(paused, format);
+    }
+    let Some(api) = &config.api else {
+        bail!("No control channel or [api] is configured; send SIGUSR1 (pause) or SIGUSR2 (resume) to cfbind instead");
+    };
+    let url = format!("{}/{}", base(api), command);
+    // The API listens on this host; a proxy or binding meant for outbound
+    // calls would only send the request astray.
+    let client = reqwest::Client::builder()
+        .no_proxy()
+        .build()
+        .context("Failed to build an HTTP client")?;
+    let response = client
+        .post(&url)
+        .bearer_auth(api.token.expose())
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach cfbind at {}", url))?;
+    match response.status() {
+        StatusCode::UNAUTHORIZED => bail!("The API rejected the token"),
+        status if !status.is_success() => bail!("The API answered {}", status),
+        _ => {}
+    }
+    report(paused, format)
+}
@@ -0,0 +1,262 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::config::{Config, ProviderKind};
+use crate::ip;
+use crate::output::{self, Format};
+use crate::provider::cloudflare::{get_zones, Cloudflare, TokenStatus};
+use crate::provider::{Providers, Record, RecordType};
+use crate::secret::{self, REDACTED};
+use crate::state;
+use crate::store::{self, IpChange, Store, Write};
+
+/// Config keys whose values are credentials.
+const SECRET_KEYS: [&str; 8] = [
+    "api_key",
+    "api_token",
+    "key_secret",
+    "secret_api_key",
+    "password",
+    "token",
+    "secret_id",
+    "dsn",
+];
+
+/// Most history entries of each kind included.
+const HISTORY_TAIL: usize = 50;
+
+#[derive(Debug, Serialize)]
+struct SourceAnswer {
+    source: String,
+    ip: Option<IpAddr>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ZoneSnapshot {
+    name: String,
+    id: String,
+    status: String,
+    plan: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct RecordSnapshot {
+    name: String,
+    #[serde(rename = "type")]
+    record_type: String,
+    record: Option<Record>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct RecentHistory {
+    ip_changes: Vec<IpChange>,
+    writes: Vec<Write>,
+}
+
+/// Everything a bug report usually needs, with credentials blanked out.
+/// Parts that could not be collected are listed in `errors` instead.
+#[derive(Debug, Serialize)]
+struct Bundle {
+    generated: DateTime<Utc>,
+    version: &'static str,
+    platform: String,
+    config: Value,
+    ip_sources: Vec<SourceAnswer>,
+    token: Option<TokenStatus>,
+    zones: Option<Vec<ZoneSnapshot>>,
+    records: Vec<RecordSnapshot>,
+    state: Option<Value>,
+    history: Option<RecentHistory>,
+    errors: BTreeMap<&'static str, String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Dumped {
+    path: PathBuf,
+}
+
+/// Blanks out credentials, and passwords in URLs such as a proxy's.
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if SECRET_KEYS.contains(&key.as_str()) && !value.is_null() {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        Value::String(text) => *text = secret::scrub(text),
+        _ => {}
+    }
+}
+
+async fn cloudflare_snapshot(config: &Config, bundle: &mut Bundle) {
+    let cloudflare = match Cloudflare::from_config(config).await {
+        Ok(cloudflare) => cloudflare,
+        Err(e) => {
+            bundle.errors.insert("cloudflare", secret::describe(&e));
+            return;
+        }
+    };
+    match cloudflare.verify_token().await {
+        Ok(token) => bundle.token = Some(token),
+        Err(e) => {
+            bundle.errors.insert("token", secret::describe(&e));
+        }
+    }
+    match get_zones(&cloudflare.client()).await {
+        Ok(zones) => {
+            let mut zones: Vec<ZoneSnapshot> = zones
+                .into_values()
+                .map(|zone| ZoneSnapshot {
+                    name: zone.name,
+                    id: zone.id,
+                    status: format!("{:?}", zone.status).to_lowercase(),
+                    plan: zone.plan.map(|plan| plan.name),
+                })
+                .collect();
+            zones.sort_by(|a, b| a.name.cmp(&b.name));
+            bundle.zones = Some(zones);
+        }
+        Err(e) => {
+            bundle.errors.insert("zones", secret::describe(&e));
+        }
+    }
+}
+
+async fn record_snapshots(config: &Config) -> Vec<RecordSnapshot> {
+    let mut providers = Providers::default();
+    let mut snapshots = Vec::new();
+    for domain in &config.domains {
+        for record_type in [RecordType::A, RecordType::Aaaa] {
+            let mut snapshot = RecordSnapshot {
+                name: domain.name.clone(),
+                record_type: record_type.to_string(),
+                record: None,
+                error: None,
+            };
+            let record = match providers.get(domain.provider, config).await {
+                Ok(provider) => provider.get_record(&domain.name, record_type).await,
+                Err(e) => Err(e),
+            };
+            match record {
+                Ok(record) => snapshot.record = record,
+                Err(e) => snapshot.error = Some(secret::describe(&e)),
+            }
+            snapshots.push(snapshot);
+        }
+    }
+    snapshots
+}
+
+fn recent_history(config: &Config) -> Result<Option<RecentHistory>> {
+    if config.history.is_none() {
+        return Ok(None);
+    }
+    let store = Store::open(&store::path(config.history.as_ref())?)?;
+    let tail = |mut items: Vec<_>| {
+        let skip = items.len().saturating_sub(HISTORY_TAIL);
+        items.drain(..skip);
+        items
+    };
+    Ok(Some(RecentHistory {
+        ip_changes: tail(store.ip_changes(None)?),
+        writes: tail(store.writes(None, None)?),
+    }))
+}
+
+fn saved_state(config: &Config) -> Result<Option<Value>> {
+    let Some(path) = config.state_file.clone().or_else(state::default_path) else {
+        return Ok(None);
+    };
+    if !path.exists() {
+        return Ok(None);
+    }
+    let raw = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read state file {}", path.display()))?;
+    Ok(Some(serde_json::from_str(&raw)?))
+}
+
+/// Writes the effective config, what each IP source answers, the token's
+/// status, the zones and configured records, the saved state and the
+/// recent history into one JSON file, for attaching to a bug report.
+pub async fn run(config: &Config, out: Option<PathBuf>, format: Format) -> Result<()> {
+    let mut config_value = serde_json::to_value(config)?;
+    redact(&mut config_value);
+    let mut bundle = Bundle {
+        generated: Utc::now(),
+        version: env!("CARGO_PKG_VERSION"),
+        platform: format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH),
+        config: config_value,
+        ip_sources: Vec::new(),
+        token: None,
+        zones: None,
+        records: Vec::new(),
+        state: None,
+        history: None,
+        errors: BTreeMap::new(),
+    };
+
+    match ip::probe().await {
+        Ok(answers) => {
+            for (source, answer) in answers {
+                let (ip, error) = match answer {
+                    Ok(ip) => (Some(ip), None),
+                    Err(e) => (None, Some(e)),
+                };
+                bundle.ip_sources.push(SourceAnswer { source, ip, error });
+            }
+        }
+        Err(e) => {
+            bundle.errors.insert("ip_sources", secret::describe(&e));
+        }
+    }
+    let uses_cloudflare = config.cloudflare.is_some()
+        || config
+            .domains
+            .iter()
+            .any(|d| d.provider == ProviderKind::Cloudflare);
+    if uses_cloudflare {
+        cloudflare_snapshot(config, &mut bundle).await;
+    }
+    bundle.records = record_snapshots(config).await;
+    match saved_state(config) {
+        Ok(state) => bundle.state = state,
+        Err(e) => {
+            bundle.errors.insert("state", secret::describe(&e));
+        }
+    }
+    match recent_history(config) {
+        Ok(history) => bundle.history = history,
+        Err(e) => {
+            bundle.errors.insert("history", secret::describe(&e));
+        }
+    }
+
+    let path = out.unwrap_or_else(|| {
+        let time = bundle.generated.format("%Y%m%dT%H%M%SZ");
+        PathBuf::from(format!("cfbind-debug-{}.json", time))
+    });
+    std::fs::write(&path, serde_json::to_vec_pretty(&bundle)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    if !format.is_text() {
+        return output::print(format, &Dumped { path });
+    }
+    println!("Wrote {}", path.display());
+    if !bundle.errors.is_empty() {
+        println!("Some parts could not be collected, see \"errors\" in the file");
+    }
+    Ok(())
+}
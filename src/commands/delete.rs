@@ -0,0 +1,108 @@
+use std::io::{self, BufRead, Write};
+
+use ::cloudflare::endpoints::dns::DnsContent;
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use crate::commands::{prompt, records};
+use crate::config::{Config, ProviderKind};
+use crate::output::{self, Format};
+use crate::provider::cloudflare::{list_zone_records, Cloudflare};
+use crate::provider::{Providers, RecordType};
+
+#[derive(Debug, Serialize)]
+struct Deletion {
+    name: String,
+    #[serde(rename = "type")]
+    record_type: String,
+    content: String,
+    deleted: bool,
+}
+
+/// Prompts on stderr so stdout stays clean for structured output.
+fn confirm(prompt: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", prompt);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Asks for a zone and then one of its A/AAAA record names on Cloudflare.
+pub async fn pick_name(config: &Config) -> Result<String> {
+    if !prompt::is_interactive() {
+        bail!("No record name given");
+    }
+    let cloudflare = Cloudflare::from_config(config).await?;
+    let zone = records::zone_for(&cloudflare.client(), None).await?;
+    let mut names: Vec<String> = list_zone_records(&cloudflare.client(), &zone, None)
+        .await?
+        .into_iter()
+        .filter(|record| {
+            matches!(
+                record.content,
+                DnsContent::A { .. } | DnsContent::AAAA { .. }
+            )
+        })
+        .map(|record| record.name)
+        .collect();
+    names.sort();
+    names.dedup();
+    let picked = prompt::pick("Record", &names, "")?;
+    Ok(names.swap_remove(picked))
+}
+
+pub async fn run(config: &Config, name: Option<&str>, yes: bool, format: Format) -> Result<()> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => pick_name(config).await?,
+    };
+    let name = name.as_str();
+    let kind = config
+        .domains
+        .iter()
+        .find(|d| d.name == name)
+        .map_or(ProviderKind::Cloudflare, |d| d.provider);
+    let provider = Providers::default().get(kind, config).await?;
+
+    let mut deletions = Vec::new();
+    for record_type in [RecordType::A, RecordType::Aaaa] {
+        let Some(record) = provider.get_record(name, record_type).await? else {
+            continue;
+        };
+        let prompt = format!(
+            "Delete {} record {} ({})?",
+            record_type, record.name, record.content
+        );
+        let deleted = yes || confirm(&prompt)?;
+        if deleted {
+            provider.delete_record(&record).await?;
+        }
+        deletions.push(Deletion {
+            name: record.name,
+            record_type: record_type.to_string(),
+            content: record.content.to_string(),
+            deleted,
+        });
+    }
+
+    if !format.is_text() {
+        return output::print(format, &deletions);
+    }
+    if deletions.is_empty() {
+        println!("No A or AAAA record found for {}", name);
+    }
+    for deletion in &deletions {
+        println!(
+            "{} {} record {}",
+            if deletion.deleted {
+                "Deleted"
+            } else {
+                "Skipped"
+            },
+            deletion.record_type,
+            deletion.name
+        );
+    }
+    Ok(())
+}
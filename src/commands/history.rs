@@ -0,0 +1,183 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::output::{self, Format};
+use crate::store::{self, IpChange, Store, Write};
+
+#[derive(Debug, Serialize)]
+struct History {
+    ip_changes: Vec<IpChange>,
+    writes: Vec<Write>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+/// One exported row; `event` is `ip_change` or the write's result.
+#[derive(Debug, Serialize)]
+struct Row<'a> {
+    timestamp: &'a str,
+    event: &'a str,
+    domain: Option<&'a str>,
+    old: Option<&'a str>,
+    new: &'a str,
+    error: Option<&'a str>,
+}
+
+fn csv_field(value: Option<&str>) -> String {
+    let value = value.unwrap_or("");
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Parses `--since`: a relative age such as `30m`, `12h`, `7d` or `2w`, or
+/// an absolute `YYYY-MM-DD` date or RFC 3339 timestamp.
+pub fn parse_since(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+    let split = value.len().saturating_sub(1);
+    let (amount, unit) = value.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .with_context(|| format!("Invalid --since value {:?}", value))?;
+    let age = match unit {
+        "s" => Duration::seconds(amount),
+        "m" => Duration::minutes(amount),
+        "h" => Duration::hours(amount),
+        "d" => Duration::days(amount),
+        "w" => Duration::weeks(amount),
+        _ => bail!("Invalid --since unit in {:?}, use s, m, h, d or w", value),
+    };
+    Ok(Utc::now() - age)
+}
+
+/// Opens the history database for reading; it only exists once `[history]`
+/// has been enabled for a running instance.
+pub fn open(config: &Config) -> Result<Store> {
+    let path = store::path(config.history.as_ref())?;
+    if !path.exists() {
+        bail!(
+            "No history database at {}, enable [history] in the config file",
+            path.display()
+        );
+    }
+    Store::open(&path)
+}
+
+pub fn show(
+    config: &Config,
+    since: Option<&str>,
+    domain: Option<&str>,
+    format: Format,
+) -> Result<()> {
+    let since = since.map(parse_since).transpose()?;
+    let store = open(config)?;
+    let history = History {
+        ip_changes: store.ip_changes(since)?,
+        writes: store.writes(since, domain)?,
+    };
+
+    if !format.is_text() {
+        return output::print(format, &history);
+    }
+    // One timeline, with IP changes interleaved with the writes they caused.
+    let mut lines: Vec<(&str, String)> = Vec::new();
+    for change in &history.ip_changes {
+        lines.push((
+            &change.timestamp,
+            format!(
+                "{:<12} {:<32} {} -> {}",
+                "ip",
+                "-",
+                change.old.as_deref().unwrap_or("-"),
+                change.new
+            ),
+        ));
+    }
+    for write in &history.writes {
+        let mut line = format!(
+            "{:<12} {:<32} {} -> {}",
+            write.result,
+            write.domain,
+            write.old.as_deref().unwrap_or("-"),
+            write.new
+        );
+        if let Some(error) = &write.error {
+            line.push_str(&format!(" ({})", error));
+        }
+        lines.push((&write.timestamp, line));
+    }
+    lines.sort_by(|a, b| a.0.cmp(b.0));
+    println!(
+        "{:<21} {:<12} {:<32} {}",
+        "TIME", "EVENT", "DOMAIN", "CHANGE"
+    );
+    for (timestamp, line) in &lines {
+        println!("{:<21} {}", timestamp, line);
+    }
+    Ok(())
+}
+
+pub fn export(
+    config: &Config,
+    since: Option<&str>,
+    domain: Option<&str>,
+    format: ExportFormat,
+) -> Result<()> {
+    let since = since.map(parse_since).transpose()?;
+    let store = open(config)?;
+    let ip_changes = store.ip_changes(since)?;
+    let writes = store.writes(since, domain)?;
+    let mut rows: Vec<Row> = ip_changes
+        .iter()
+        .map(|change| Row {
+            timestamp: &change.timestamp,
+            event: "ip_change",
+            domain: None,
+            old: change.old.as_deref(),
+            new: &change.new,
+            error: None,
+        })
+        .chain(writes.iter().map(|write| Row {
+            timestamp: &write.timestamp,
+            event: &write.result,
+            domain: Some(&write.domain),
+            old: write.old.as_deref(),
+            new: &write.new,
+            error: write.error.as_deref(),
+        }))
+        .collect();
+    rows.sort_by(|a, b| a.timestamp.cmp(b.timestamp));
+
+    match format {
+        ExportFormat::Json => println!("{}", serde_json::to_string_pretty(&rows)?),
+        ExportFormat::Csv => {
+            println!("timestamp,event,domain,old,new,error");
+            for row in &rows {
+                println!(
+                    "{},{},{},{},{},{}",
+                    row.timestamp,
+                    row.event,
+                    csv_field(row.domain),
+                    csv_field(row.old),
+                    row.new,
+                    csv_field(row.error)
+                );
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use crate::commands;
+use crate::commands::prompt::{ask, ask_yes_no, pick};
+use crate::config::{self, DEFAULT_INTERVAL};
+use crate::provider::cloudflare::{get_zones, Cloudflare};
+use crate::provider::validate_name;
+use crate::secret::{self, Secret};
+
+/// Walks through the settings a first config needs, checking the token and
+/// names against the Cloudflare API as it goes, then writes the file.
+pub async fn run(path: &Path, api_key: Option<&str>, proxied: bool, force: bool) -> Result<()> {
+    commands::config::ensure_writable(path, force)?;
+
+    let mut api_key = api_key.map(str::to_string);
+    let (api_key, zones) = loop {
+        let token = match api_key.take() {
+            Some(token) => token,
+            None => ask("Cloudflare API token with Zone:DNS:Edit permission", None)?,
+        };
+        if token.is_empty() {
+            continue;
+        }
+        match get_zones(&Cloudflare::new(&Secret::new(token.as_str()))?.client()).await {
+            Ok(zones) => break (token, zones),
+            Err(e) => eprintln!("The token was rejected: {}", secret::describe(&e)),
+        }
+    };
+    let mut zones: Vec<String> = zones.into_keys().collect();
+    zones.sort();
+
+    let zone = match zones.as_slice() {
+        [] => bail!("The token can't see any zones, grant it Zone:DNS:Edit on at least one"),
+        [zone] => {
+            eprintln!("Using zone {}", zone);
+            zone.clone()
+        }
+        _ => zones[pick("Zone", &zones, "")?].clone(),
+    };
+
+    let name = loop {
+        let host = ask(
+            &format!("Hostname in {} (@ for {} itself)", zone, zone),
+            Some("home"),
+        )?;
+        let name = if host == "@" || host == zone {
+            zone.clone()
+        } else if host.ends_with(&format!(".{}", zone)) {
+            host
+        } else {
+            format!("{}.{}", host, zone)
+        };
+        match validate_name(&name) {
+            Ok(()) => break name,
+            Err(e) => eprintln!("{:#}", e),
+        }
+    };
+
+    let proxied = ask_yes_no("Proxy traffic through Cloudflare?", proxied)?;
+    let interval = loop {
+        let answer = ask(
+            "Seconds between checks",
+            Some(&DEFAULT_INTERVAL.to_string()),
+        )?;
+        match answer.parse::<u64>() {
+            Ok(interval) if interval > 0 => break interval,
+            _ => eprintln!("Enter a whole number of seconds"),
+        }
+    };
+
+    let contents = config::example(Some(&name), Some(&api_key), proxied, interval);
+    commands::config::write(path, &contents, force)?;
+    println!(
+        "Run `cfbind --config {} update` to sync {} once, or drop `update` to keep it in sync",
+        path.display(),
+        name
+    );
+    Ok(())
+}
@@ -0,0 +1,31 @@
+use std::io;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap_mangen::Man;
+
+fn write_pages(cmd: &clap::Command, name: &str, dir: &Path) -> Result<()> {
+    let path = dir.join(format!("{}.1", name));
+    let mut file = std::fs::File::create(&path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    Man::new(cmd.clone()).title(name).render(&mut file)?;
+    for sub in cmd.get_subcommands() {
+        if sub.is_hide_set() || sub.get_name() == "help" {
+            continue;
+        }
+        write_pages(sub, &format!("{}-{}", name, sub.get_name()), dir)?;
+    }
+    Ok(())
+}
+
+/// Writes `cfbind.1` plus one page per subcommand into `out_dir`, or the
+/// top-level page to stdout.
+pub fn run(cmd: clap::Command, out_dir: Option<&Path>) -> Result<()> {
+    match out_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)?;
+            write_pages(&cmd, "cfbind", dir)
+        }
+        None => Ok(Man::new(cmd).render(&mut io::stdout())?),
+    }
+}
@@ -0,0 +1,16 @@
+pub mod acme;
+pub mod agent;
+pub mod check;
+pub mod config;
+pub mod control;
+pub mod debug_dump;
+pub mod delete;
+pub mod history;
+pub mod init;
+pub mod mangen;
+pub mod prompt;
+pub mod proxy;
+pub mod records;
+pub mod status;
+pub mod update;
+pub mod zones;
@@ -0,0 +1,84 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use anyhow::{bail, Result};
+
+/// Most entries the picker lists at once; narrow the search to see the rest.
+const PICK_SHOWN: usize = 20;
+
+/// Whether prompts can be answered, i.e. stdin and stderr are a terminal.
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal() && io::stderr().is_terminal()
+}
+
+/// Asks on stderr and reads one trimmed line, or `default` for an empty one.
+pub fn ask(question: &str, default: Option<&str>) -> Result<String> {
+    match default {
+        Some(default) => eprint!("{} [{}]: ", question, default),
+        None => eprint!("{}: ", question),
+    }
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer)? == 0 {
+        bail!("Input closed before a choice was made");
+    }
+    let answer = answer.trim();
+    Ok(match default {
+        Some(default) if answer.is_empty() => default.to_string(),
+        _ => answer.to_string(),
+    })
+}
+
+pub fn ask_yes_no(question: &str, default: bool) -> Result<bool> {
+    loop {
+        let answer = ask(question, Some(if default { "y" } else { "n" }))?;
+        match answer.to_lowercase().as_str() {
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => eprintln!("Please answer y or n"),
+        }
+    }
+}
+
+/// Whether the characters of `query` appear in `item` in order, ignoring case.
+fn fuzzy_match(item: &str, query: &str) -> bool {
+    let mut chars = item.chars().flat_map(char::to_lowercase);
+    query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|q| chars.any(|c| c == q))
+}
+
+/// Lets the user choose one of `items` by number, narrowing the list with a
+/// fuzzy search first if it is long. Returns the index of the choice.
+pub fn pick(label: &str, items: &[String], query: &str) -> Result<usize> {
+    if items.is_empty() {
+        bail!("No {} to choose from", label);
+    }
+    let mut query = query.to_string();
+    loop {
+        let mut matches: Vec<usize> = (0..items.len())
+            .filter(|&i| fuzzy_match(&items[i], &query))
+            .collect();
+        // Plain substring hits first, then shorter names.
+        let lower = query.to_lowercase();
+        matches.sort_by_key(|&i| (!items[i].to_lowercase().contains(&lower), items[i].len()));
+        if matches.is_empty() {
+            eprintln!("Nothing matches {:?}", query);
+        }
+        for (n, &i) in matches.iter().take(PICK_SHOWN).enumerate() {
+            eprintln!("{:>3}) {}", n + 1, items[i]);
+        }
+        if matches.len() > PICK_SHOWN {
+            eprintln!(
+                "     ... {} more, type to narrow",
+                matches.len() - PICK_SHOWN
+            );
+        }
+        let default = (matches.len() == 1).then_some("1");
+        let answer = ask(&format!("{} (number or search)", label), default)?;
+        match answer.parse::<usize>() {
+            Ok(n) if (1..=matches.len().min(PICK_SHOWN)).contains(&n) => return Ok(matches[n - 1]),
+            _ => query = answer,
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use crate::commands::delete;
+use crate::config::{Config, ProviderKind};
+use crate::output::{self, Format};
+use crate::provider::{Providers, RecordSpec, RecordType};
+
+#[derive(Debug, Serialize)]
+struct Toggle {
+    name: String,
+    #[serde(rename = "type")]
+    record_type: String,
+    proxied: bool,
+    changed: bool,
+}
+
+/// Turns Cloudflare's proxy on or off for the A/AAAA records at a name,
+/// keeping their content and TTL as they are.
+pub async fn run(config: &Config, name: Option<&str>, proxied: bool, format: Format) -> Result<()> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => delete::pick_name(config).await?,
+    };
+    let name = name.as_str();
+    let domain = config.domains.iter().find(|d| d.name == name);
+    let kind = domain.map_or(ProviderKind::Cloudflare, |d| d.provider);
+    let provider = Providers::default().get(kind, config).await?;
+    if !provider.supports_proxy() {
+        bail!("{} is not on a provider that can proxy records", name);
+    }
+
+    let mut toggles = Vec::new();
+    for record_type in [RecordType::A, RecordType::Aaaa] {
+        let Some(record) = provider.get_record(name, record_type).await? else {
+            continue;
+        };
+        let changed = record.proxied != proxied;
+        if changed && !config.dry_run {
+            provider
+                .upsert_record(&RecordSpec {
+                    name: record.name.clone(),
+                    content: record.content.clone(),
+                    ttl: Some(record.ttl),
+                    proxied,
+                    comment: None,
+                })
+                .await?;
+        }
+        toggles.push(Toggle {
+            name: record.name,
+            record_type: record_type.to_string(),
+            proxied,
+            changed,
+        });
+    }
+    if let Some(domain) = domain {
+        if domain.proxied != proxied && toggles.iter().any(|t| t.changed) {
+            log::warn!(
+                "{} has proxied = {} in the config, a running cfbind will put that back on its next sync",
+                name,
+                domain.proxied
+            );
+        }
+    }
+
+    if !format.is_text() {
+        return output::print(format, &toggles);
+    }
+    if toggles.is_empty() {
+        println!("No A or AAAA record found for {}", name);
+    }
+    let state = if proxied { "on" } else { "off" };
+    for toggle in &toggles {
+        match (toggle.changed, config.dry_run) {
+            (false, _) => println!(
+                "Proxy already {} for {} record {}",
+                state, toggle.record_type, toggle.name
+            ),
+            (true, true) => println!(
+                "Would turn proxy {} for {} record {}",
+                state, toggle.record_type, toggle.name
+            ),
+            (true, false) => println!(
+                "Turned proxy {} for {} record {}",
+                state, toggle.record_type, toggle.name
+            ),
+        }
+    }
+    Ok(())
+}
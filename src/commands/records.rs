@@ -0,0 +1,91 @@
+use ::cloudflare::endpoints::zone::Zone;
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use crate::commands::prompt;
+use crate::config::Config;
+use crate::error::CfbindError;
+use crate::output::{self, Format};
+use crate::provider::cloudflare::{
+    describe_content, get_zones, list_zone_records, Cloudflare, CloudflareApi,
+};
+
+#[derive(Debug, Serialize)]
+struct RecordInfo {
+    id: String,
+    name: String,
+    #[serde(rename = "type")]
+    record_type: String,
+    content: String,
+    proxied: bool,
+    ttl: u32,
+}
+
+/// The zone called `name`, or one picked from the zones the token can see
+/// when `name` is missing or matches none of them and there is a terminal
+/// to ask on.
+pub async fn zone_for(client: &dyn CloudflareApi, name: Option<&str>) -> Result<Zone> {
+    let mut zones = get_zones(client).await?;
+    if let Some(zone) = name.and_then(|name| zones.remove(name)) {
+        return Ok(zone);
+    }
+    if !prompt::is_interactive() {
+        match name {
+            Some(name) => return Err(CfbindError::ZoneNotFound(name.to_string()).into()),
+            None => bail!("No zone given, pass --zone"),
+        }
+    }
+    let mut names: Vec<String> = zones.keys().cloned().collect();
+    names.sort();
+    let picked = prompt::pick("Zone", &names, name.unwrap_or_default())?;
+    Ok(zones
+        .remove(&names[picked])
+        .expect("picked from the listed zones"))
+}
+
+pub async fn list(
+    config: &Config,
+    zone: Option<&str>,
+    name: Option<&str>,
+    record_type: Option<&str>,
+    format: Format,
+) -> Result<()> {
+    let cloudflare = Cloudflare::from_config(config).await?;
+    let zone = zone_for(&cloudflare.client(), zone).await?;
+    let records: Vec<RecordInfo> = list_zone_records(&cloudflare.client(), &zone, name)
+        .await?
+        .into_iter()
+        .map(|record| {
+            let (kind, content) = describe_content(&record.content);
+            RecordInfo {
+                id: record.id,
+                name: record.name,
+                record_type: kind.to_string(),
+                content,
+                proxied: record.proxied,
+                ttl: record.ttl,
+            }
+        })
+        .filter(|record| record_type.is_none_or(|t| record.record_type.eq_ignore_ascii_case(t)))
+        .collect();
+
+    if !format.is_text() {
+        return output::print(format, &records);
+    }
+    println!(
+        "{:<34} {:<6} {:<32} {:<40} {:<8} {}",
+        "ID", "TYPE", "NAME", "CONTENT", "PROXIED", "TTL"
+    );
+    for record in &records {
+        let ttl = if record.ttl == 1 {
+            "auto".to_string()
+        } else {
+            record.ttl.to_string()
+        };
+        println!(
+            "{:<34} {:<6} {:<32} {:<40} {:<8} {}",
+            record.id, record.record_type, record.name, record.content, record.proxied, ttl
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::cgnat;
+use crate::config::{Config, ProviderKind};
+use crate::ip;
+use crate::output::{self, Format};
+use crate::provider::{Providers, RecordContent, RecordType};
+use crate::secret;
+use crate::verify;
+
+#[derive(Debug, Serialize)]
+struct RecordStatus {
+    name: String,
+    provider: ProviderKind,
+    content: Option<String>,
+    proxied: Option<bool>,
+    ttl: Option<u32>,
+    in_sync: bool,
+    /// Whether the record resolves over DoH; only checked when `verify.doh` is on.
+    verified: Option<bool>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct StatusReport {
+    ip: IpAddr,
+    /// Why inbound connections to `ip` won't work, when behind carrier-grade NAT.
+    cgnat: Option<String>,
+    records: Vec<RecordStatus>,
+}
+
+async fn collect(config: &Config) -> Result<StatusReport> {
+    let ip = ip::detect().await?;
+    let mut providers = Providers::default();
+    let mut records = Vec::new();
+    for domain in &config.domains {
+        let expected = domain
+            .content
+            .as_deref()
+            .map_or(RecordContent::from(ip), RecordContent::infer);
+        let mut status = RecordStatus {
+            name: domain.name.clone(),
+            provider: domain.provider,
+            content: None,
+            proxied: None,
+            ttl: None,
+            in_sync: false,
+            verified: None,
+            error: None,
+        };
+        let provider = providers.get(domain.provider, config).await?;
+        match provider
+            .get_record(&domain.name, expected.record_type())
+            .await
+        {
+            Ok(Some(record)) => {
+                let proxied = domain.proxied
+                    && provider.supports_proxy()
+                    && expected.record_type() != RecordType::Txt;
+                status.in_sync = record.content == expected && record.proxied == proxied;
+                status.content = Some(record.content.to_string());
+                status.proxied = Some(record.proxied);
+                status.ttl = Some(record.ttl);
+                if config.verify.doh && !record.proxied && record.content.ip().is_some() {
+                    let url = config
+                        .verify
+                        .doh_url
+                        .as_deref()
+                        .unwrap_or(verify::DEFAULT_DOH_URL);
+                    status.verified =
+                        verify::doh(url, &domain.name, &record.content, Duration::ZERO)
+                            .await
+                            .ok();
+                }
+            }
+            Ok(None) => {}
+            Err(e) => status.error = Some(secret::describe(&e)),
+        }
+        records.push(status);
+    }
+    Ok(StatusReport {
+        ip,
+        cgnat: cgnat::check(ip).await,
+        records,
+    })
+}
+
+pub async fn run(config: &Config, format: Format) -> Result<()> {
+    let report = collect(config).await?;
+    if !format.is_text() {
+        return output::print(format, &report);
+    }
+    println!("Detected IP: {}", report.ip);
+    if let Some(warning) = &report.cgnat {
+        println!("Warning: {}", warning);
+    }
+    println!();
+    println!(
+        "{:<32} {:<40} {:<8} {:<6} {:<9} {}",
+        "NAME", "CONTENT", "PROXIED", "TTL", "VERIFIED", "STATE"
+    );
+    for record in &report.records {
+        let state = match (&record.error, &record.content, record.in_sync) {
+            (Some(e), _, _) => format!("error: {}", e),
+            (None, None, _) => "missing".to_string(),
+            (None, Some(_), true) => "in sync".to_string(),
+            (None, Some(_), false) => "out of date".to_string(),
+        };
+        println!(
+            "{:<32} {:<40} {:<8} {:<6} {:<9} {}",
+            record.name,
+            record.content.as_deref().unwrap_or("-"),
+            record.proxied.map_or("-".to_string(), |p| p.to_string()),
+            record.ttl.map_or("-".to_string(), |t| t.to_string()),
+            record.verified.map_or("-".to_string(), |v| v.to_string()),
+            state
+        );
+    }
+    Ok(())
+}
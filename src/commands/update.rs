@@ -0,0 +1,347 @@
+use std::fmt;
+use std::io::IsTerminal;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use tokio::task::JoinSet;
+
+use crate::config::{Config, ProviderKind};
+use crate::exit::{self, Status};
+use crate::metrics;
+use crate::output::{self, Format};
+use crate::provider::{Providers, RecordContent};
+use crate::rules::Rules;
+use crate::secret;
+use crate::state::State;
+use crate::updater::{Outcome, Pending, Updater};
+use crate::window::Windows;
+
+#[derive(Debug, Serialize)]
+struct UpdateResult {
+    name: String,
+    outcome: Option<Outcome>,
+    error: Option<String>,
+}
+
+/// Marks a write undone because another one of the same update failed;
+/// the exit code comes from that failure instead.
+#[derive(Debug)]
+struct RolledBack;
+
+impl fmt::Display for RolledBack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Rolled back because another write of this update failed")
+    }
+}
+
+impl std::error::Error for RolledBack {}
+
+/// Syncs every domain once, checking up to `concurrency` at a time and
+/// handing each provider all of its pending writes together so it can batch
+/// them. The writes succeed or fail together: when one fails, the others are
+/// rolled back. Exits 0 when nothing changed, 2 when a record was written, or
+/// with the class of the first error.
+pub async fn run(config: &Config, format: Format) -> Result<ExitCode> {
+    let started = Instant::now();
+    let state = State::open(config)?;
+    let mut providers = Providers::default();
+    let limit = config.sync_limit();
+    let rules = Rules::from_config(config)?;
+    let mut tasks = JoinSet::new();
+    let mut sets = JoinSet::new();
+    for (index, domain) in config.domains.iter().enumerate() {
+        let provider = providers.get(domain.provider, config).await?;
+        let updater = Updater::new(
+            provider,
+            domain.name.clone(),
+            domain.proxied,
+            Duration::ZERO,
+            Arc::clone(&state),
+        )
+        .dry_run(config.dry_run)
+        .ttl(config.ttl)
+        .comment(config.comment.clone())
+        .verify(config.verify.clone())
+        .rules(rules.clone())
+        .windows(Windows::new(config.windows_for(domain))?)
+        .content(domain.content.as_deref().map(RecordContent::infer))
+        .via(domain.via.clone());
+        let limit = Arc::clone(&limit);
+        if updater.is_set() {
+            // Record sets are reconciled on their own, outside the batching.
+            sets.spawn(async move {
+                let _permit = limit.acquire_owned().await;
+                (index, updater.sync().await)
+            });
+            continue;
+        }
+        tasks.spawn(async move {
+            let _permit = limit.acquire_owned().await;
+            let pending = updater.check().await;
+            (index, updater, pending)
+        });
+    }
+    let mut checked = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        checked.push(joined?);
+    }
+    // Hand writes to the providers in config order, whichever check finished first.
+    checked.sort_by_key(|(index, _, _)| *index);
+
+    let mut outcomes: Vec<Option<Result<Outcome>>> = config.domains.iter().map(|_| None).collect();
+    // What a dry run would have written, shown with the text output under -v.
+    let mut diffs: Vec<Option<String>> = config.domains.iter().map(|_| None).collect();
+    let color = std::io::stdout().is_terminal();
+    let mut writes: Vec<(ProviderKind, Vec<(usize, Updater, Pending)>)> = Vec::new();
+    for (index, updater, pending) in checked {
+        let kind = config.domains[index].provider;
+        outcomes[index] = match pending {
+            Err(e) => Some(Err(e)),
+            Ok(None) => Some(Ok(Outcome::Unchanged)),
+            Ok(Some(pending)) if config.dry_run => {
+                if log::log_enabled!(log::Level::Info) {
+                    diffs[index] = Some(pending.diff(color));
+                }
+                Some(Ok(Outcome::WouldUpdate))
+            }
+            Ok(Some(pending)) => {
+                match writes.iter_mut().find(|(k, _)| *k == kind) {
+                    Some((_, group)) => group.push((index, updater, pending)),
+                    None => writes.push((kind, vec![(index, updater, pending)])),
+                }
+                None
+            }
+        };
+    }
+    while let Some(joined) = sets.join_next().await {
+        let (index, outcome) = joined?;
+        outcomes[index] = Some(outcome);
+    }
+
+    let mut written = Vec::new();
+    let mut failed = false;
+    for (_, group) in writes {
+        let specs: Vec<_> = group.iter().map(|(_, _, p)| p.spec.clone()).collect();
+        let existing: Vec<_> = group.iter().map(|(_, _, p)| p.existing.clone()).collect();
+        let provider = Arc::clone(group[0].1.provider());
+        match provider.upsert_records(&specs, &existing).await {
+            Ok(records) => {
+                for ((index, updater, pending), record) in group.into_iter().zip(records) {
+                    written.push((index, updater, pending, record));
+                }
+            }
+            Err(e) => {
+                // One failed request fails every write in it; keep the original
+                // error on the first so its class decides the exit code.
+                failed = true;
+                let message = secret::describe(&e);
+                for (_, updater, pending) in &group {
+                    updater.write_failed(pending, &e);
+                    updater.restore(pending).await;
+                }
+                let mut e = Some(e);
+                for (index, _, _) in group {
+                    outcomes[index] =
+                        Some(Err(e.take().unwrap_or_else(|| anyhow!(message.clone()))));
+                }
+            }
+        }
+    }
+
+    let mut finishing = JoinSet::new();
+    for (index, updater, pending, record) in written {
+        if failed {
+            // The update is all or nothing: a failed write undoes the others.
+            updater.restore(&pending).await;
+            outcomes[index] = Some(Err(RolledBack.into()));
+            continue;
+        }
+        finishing.spawn(async move { (index, updater.finish(pending, record).await) });
+    }
+    while let Some(joined) = finishing.join_next().await {
+        let (index, outcome) = joined?;
+        outcomes[index] = Some(outcome);
+    }
+
+    state.cycle_finished();
+    state.settle().await;
+
+    let mut status = Status::Success;
+    let mut results = Vec::new();
+    for (outcome, domain) in outcomes.into_iter().zip(&config.domains) {
+        let mut result = UpdateResult {
+            name: domain.name.clone(),
+            outcome: None,
+            error: None,
+        };
+        match outcome.expect("every domain has an outcome") {
+            Ok(outcome) => {
+                if outcome == Outcome::Updated && status == Status::Success {
+                    status = Status::Updated;
+                }
+                result.outcome = Some(outcome);
+            }
+            Err(e) => {
+                if matches!(status, Status::Success | Status::Updated) && !e.is::<RolledBack>() {
+                    status = exit::classify(&e);
+                }
+                result.error = Some(secret::describe(&e));
+            }
+        }
+        results.push(result);
+    }
+    if let Some(metrics_config) = &config.metrics {
+        let success = results.iter().all(|r| r.error.is_none());
+        let changed = results
+            .iter()
+            .filter(|r| r.outcome == Some(Outcome::Updated))
+            .count();
+        let body = metrics::render_run(success, started.elapsed(), changed);
+        if let Err(e) = metrics::push(metrics_config, body).await {
+            log::warn!("{:#}", e);
+        }
+    }
+
+    if !format.is_text() {
+        output::print(format, &results)?;
+        return Ok(status.into());
+    }
+    for (result, diff) in results.iter().zip(diffs) {
+        match (&result.outcome, &result.error) {
+            (Some(Outcome::Updated), _) => println!("updated   {}", result.name),
+            (Some(Outcome::Unchanged), _) => println!("unchanged {}", result.name),
+            (Some(Outcome::WouldUpdate), _) => {
+                println!("would update {}", result.name);
+                print!("{}", diff.unwrap_or_default());
+            }
+            (None, Some(e)) => eprintln!("error     {}: {}", result.name, e),
+            (None, None) => {}
+        }
+    }
+    Ok(status.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::config::{DomainConfig, DynDns2Config, PowerDnsConfig};
+    use crate::secret::Secret;
+
+    const OLD: &str = "198.51.100.1";
+    const NEW: &str = "198.51.100.7";
+    const ZONE: &str = "/api/v1/servers/localhost/zones";
+
+    fn domain(name: &str, provider: ProviderKind) -> DomainConfig {
+        DomainConfig {
+            name: name.to_string(),
+            provider,
+            proxied: false,
+            via: Vec::new(),
+            aliases: Vec::new(),
+            content: Some(NEW.to_string()),
+            record_id: None,
+            zone_id: None,
+            windows: Vec::new(),
+        }
+    }
+
+    fn rrset(ip: &str) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "rrsets": [{
+                "name": "a.example.com.",
+                "type": "A",
+                "ttl": 300,
+                "records": [{ "content": ip, "disabled": false }]
+            }]
+        }))
+    }
+
+    #[tokio::test]
+    async fn a_failed_write_rolls_the_others_back() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(ZONE))
+            .and(query_param("zone", "example.com."))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!([{ "id": "example.com.", "name": "example.com." }])),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(ZONE))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+            .mount(&server)
+            .await;
+        // The check reads the old address, the rollback the one just written.
+        let zone = format!("{}/example.com.", ZONE);
+        Mock::given(method("GET"))
+            .and(path(zone.as_str()))
+            .respond_with(rrset(OLD))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(zone.as_str()))
+            .respond_with(rrset(NEW))
+            .mount(&server)
+            .await;
+        Mock::given(method("PATCH"))
+            .and(path(zone.as_str()))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/nic/update"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+
+        let state_file = std::env::temp_dir().join(format!(
+            "cfbind-update-rollback-{}.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&state_file);
+        let config = Config {
+            domains: vec![
+                domain("a.example.com", ProviderKind::PowerDns),
+                domain("b.example.com", ProviderKind::DynDns2),
+            ],
+            powerdns: Some(PowerDnsConfig {
+                url: server.uri(),
+                api_key: Secret::new("key"),
+                server_id: "localhost".to_string(),
+            }),
+            dyndns2: Some(DynDns2Config {
+                server: Some(server.uri()),
+                username: "user".to_string(),
+                password: Secret::new("dyndns-pass"),
+            }),
+            state_file: Some(state_file),
+            ..Config::default()
+        };
+        let status = run(&config, Format::Json).await.unwrap();
+        // The rejected credentials decide the exit code, not the rollback.
+        assert_eq!(status, ExitCode::from(Status::Auth));
+
+        let patches: Vec<Value> = server
+            .received_requests()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|request| request.method.as_str() == "PATCH")
+            .map(|request| request.body_json().unwrap())
+            .collect();
+        let written = |patch: &Value| patch["rrsets"][0]["records"][0]["content"].clone();
+        assert_eq!(written(&patches[0]), json!(NEW));
+        assert_eq!(written(&patches[1]), json!(OLD));
+    }
+}
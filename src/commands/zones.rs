@@ -0,0 +1,44 @@
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::output::{self, Format};
+use crate::provider::cloudflare::{get_zones, Cloudflare};
+
+#[derive(Debug, Serialize)]
+struct ZoneInfo {
+    name: String,
+    id: String,
+    status: String,
+    plan: Option<String>,
+}
+
+pub async fn list(config: &Config, format: Format) -> Result<()> {
+    let cloudflare = Cloudflare::from_config(config).await?;
+    let mut zones: Vec<ZoneInfo> = get_zones(&cloudflare.client())
+        .await?
+        .into_values()
+        .map(|zone| ZoneInfo {
+            name: zone.name,
+            id: zone.id,
+            status: format!("{:?}", zone.status).to_lowercase(),
+            plan: zone.plan.map(|plan| plan.name),
+        })
+        .collect();
+    zones.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if !format.is_text() {
+        return output::print(format, &zones);
+    }
+    println!("{:<32} {:<34} {:<12} {}", "NAME", "ID", "STATUS", "PLAN");
+    for zone in &zones {
+        println!(
+            "{:<32} {:<34} {:<12} {}",
+            zone.name,
+            zone.id,
+            zone.status,
+            zone.plan.as_deref().unwrap_or("-")
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,1207 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use url::Url;
+
+use crate::encrypted;
+use crate::output::EventFormat;
+use crate::secret::Secret;
+use crate::state::EventKind;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    #[default]
+    Cloudflare,
+    Route53,
+    Hetzner,
+    DynDns2,
+    Rfc2136,
+    PowerDns,
+    Porkbun,
+    /// The `[plugins.provider]` program.
+    Plugin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+/// Shortest and longest TTL the config accepts, besides 1 for automatic.
+/// Cloudflare's Enterprise zones go lower; the provider's `check_ttl` allows
+/// that for TTLs set by an update rule.
+pub const MIN_TTL: u32 = 60;
+pub const MAX_TTL: u32 = 86400;
+
+/// How long resolvers may cache a record: `auto` leaves it to Cloudflare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "TtlValue", into = "TtlValue")]
+pub enum Ttl {
+    Auto,
+    Seconds(u32),
+}
+
+impl Ttl {
+    /// The value sent to Cloudflare, where 1 means automatic.
+    pub fn seconds(self) -> u32 {
+        match self {
+            Ttl::Auto => 1,
+            Ttl::Seconds(seconds) => seconds,
+        }
+    }
+}
+
+impl TryFrom<u32> for Ttl {
+    type Error = String;
+
+    fn try_from(seconds: u32) -> Result<Self, String> {
+        match seconds {
+            1 => Ok(Ttl::Auto),
+            MIN_TTL..=MAX_TTL => Ok(Ttl::Seconds(seconds)),
+            _ => Err(format!(
+                "TTL must be auto or between {} and {} seconds, got {}",
+                MIN_TTL, MAX_TTL, seconds
+            )),
+        }
+    }
+}
+
+impl FromStr for Ttl {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        if value.eq_ignore_ascii_case("auto") {
+            return Ok(Ttl::Auto);
+        }
+        let seconds = value
+            .parse::<u32>()
+            .map_err(|_| format!("TTL must be auto or a number of seconds, got {:?}", value))?;
+        Ttl::try_from(seconds)
+    }
+}
+
+impl fmt::Display for Ttl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ttl::Auto => write!(f, "auto"),
+            Ttl::Seconds(seconds) => write!(f, "{}", seconds),
+        }
+    }
+}
+
+/// `ttl` as written in the config file: `"auto"` or a number.
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum TtlValue {
+    Seconds(u32),
+    Keyword(String),
+}
+
+impl TryFrom<TtlValue> for Ttl {
+    type Error = String;
+
+    fn try_from(value: TtlValue) -> Result<Self, String> {
+        match value {
+            TtlValue::Seconds(seconds) => Ttl::try_from(seconds),
+            TtlValue::Keyword(keyword) => keyword.parse(),
+        }
+    }
+}
+
+impl From<Ttl> for TtlValue {
+    fn from(ttl: Ttl) -> Self {
+        match ttl {
+            Ttl::Auto => TtlValue::Keyword("auto".to_string()),
+            Ttl::Seconds(seconds) => TtlValue::Seconds(seconds),
+        }
+    }
+}
+
+/// Which Cloudflare API to talk to: `production`, or the base URL of another
+/// endpoint such as a regional gateway or an emulator.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum CloudflareEnvironment {
+    #[default]
+    Production,
+    Custom(Url),
+}
+
+impl FromStr for CloudflareEnvironment {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        if value.eq_ignore_ascii_case("production") {
+            return Ok(CloudflareEnvironment::Production);
+        }
+        // A trailing slash makes endpoint paths join below the base rather
+        // than replace its last segment.
+        let url = Url::parse(&format!("{}/", value.trim_end_matches('/'))).map_err(|e| {
+            format!(
+                "Cloudflare environment must be production or a URL, got {:?}: {}",
+                value, e
+            )
+        })?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("Cloudflare API URL {} is not http(s)", value));
+        }
+        Ok(CloudflareEnvironment::Custom(url))
+    }
+}
+
+impl TryFrom<String> for CloudflareEnvironment {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for CloudflareEnvironment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloudflareEnvironment::Production => write!(f, "production"),
+            CloudflareEnvironment::Custom(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+impl From<CloudflareEnvironment> for String {
+    fn from(environment: CloudflareEnvironment) -> Self {
+        environment.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Config {
+    /// Seconds between update cycles.
+    pub interval: Option<u64>,
+    /// Report pending changes without writing them.
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Most domains synced at the same time, defaults to 4.
+    pub concurrency: Option<usize>,
+    /// IP changes per hour above which a flapping alert is raised, defaults to 4; 0 disables it.
+    pub flap_threshold: Option<u32>,
+    /// TTL of written records, `auto` (the default) or 60-86400 seconds.
+    pub ttl: Option<Ttl>,
+    /// Comment set on each record written to Cloudflare. `{{hostname}}`, `{{username}}`,
+    /// `{{timestamp}}` and `{{version}}` are filled in at write time.
+    pub comment: Option<String>,
+    /// Rhai script deciding each write before it is made.
+    pub rules: Option<PathBuf>,
+    /// Times records are held or re-asserted, for domains listing none of their own.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub windows: Vec<WindowConfig>,
+    /// Only detect and publish addresses of this family (`v4` or `v6`).
+    pub ip_family: Option<IpFamily>,
+    /// Where runtime state survives restarts, defaults to `~/.local/state/cfbind/state.json`.
+    pub state_file: Option<PathBuf>,
+    /// Touched after every cycle in which all domains synced; removed while any fails.
+    pub healthcheck_file: Option<PathBuf>,
+    /// Rewritten as JSON after every cycle, for scripts and monitoring checks.
+    pub status_file: Option<PathBuf>,
+    /// Stream every lifecycle event on stdout in this format.
+    pub events: Option<EventFormat>,
+    /// Look for new releases once a day; by default only when run on a terminal.
+    pub update_check: Option<bool>,
+    pub cloudflare: Option<CloudflareConfig>,
+    pub route53: Option<Route53Config>,
+    pub hetzner: Option<HetznerConfig>,
+    pub dyndns2: Option<DynDns2Config>,
+    pub rfc2136: Option<Rfc2136Config>,
+    pub powerdns: Option<PowerDnsConfig>,
+    pub porkbun: Option<PorkbunConfig>,
+    pub dyndns_server: Option<DynDnsServerConfig>,
+    pub api: Option<ApiConfig>,
+    pub control: Option<ControlConfig>,
+    pub grpc: Option<GrpcConfig>,
+    pub controller: Option<ControllerConfig>,
+    #[serde(default)]
+    pub verify: VerifyConfig,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub ip: IpConfig,
+    pub history: Option<HistoryConfig>,
+    pub sentry: Option<SentryConfig>,
+    pub metrics: Option<MetricsConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<MirrorConfig>,
+    #[serde(default)]
+    pub plugins: PluginsConfig,
+    #[serde(default)]
+    pub domains: Vec<DomainConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub zones: Vec<ZoneConfig>,
+    /// Customers or sites run apart from the domains above and each other.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tenants: Vec<TenantConfig>,
+}
+
+/// A customer or site with its own credentials, domains, notifiers and
+/// state. Each tenant gets its own provider clients, and one that fails is
+/// retried on its own while the others carry on. Every other setting is
+/// inherited from the top level, except the listeners, which stay there.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TenantConfig {
+    /// Letters, digits, `-` and `_`; also names the tenant's state file.
+    pub name: String,
+    pub cloudflare: Option<CloudflareConfig>,
+    pub route53: Option<Route53Config>,
+    pub hetzner: Option<HetznerConfig>,
+    pub dyndns2: Option<DynDns2Config>,
+    pub rfc2136: Option<Rfc2136Config>,
+    pub powerdns: Option<PowerDnsConfig>,
+    pub porkbun: Option<PorkbunConfig>,
+    /// The provider and notifier plugins; IP sources are shared.
+    #[serde(default)]
+    pub plugins: PluginsConfig,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<MirrorConfig>,
+    /// Defaults to `state.<name>.json` beside the top-level state file.
+    pub state_file: Option<PathBuf>,
+    pub status_file: Option<PathBuf>,
+    pub healthcheck_file: Option<PathBuf>,
+    pub history: Option<HistoryConfig>,
+    #[serde(default)]
+    pub domains: Vec<DomainConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub zones: Vec<ZoneConfig>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CloudflareConfig {
+    pub api_key: Option<Secret>,
+    /// File holding the token instead, e.g. a mounted secret. It is re-read
+    /// every minute and on SIGHUP, so rotating it needs no restart.
+    pub api_key_file: Option<PathBuf>,
+    /// Or fetch it from HashiCorp Vault at startup and every `refresh_every`.
+    pub vault: Option<VaultConfig>,
+    /// Seconds the list of zones is reused before it is fetched again, defaults to an hour.
+    pub zone_refresh: Option<u64>,
+    /// API endpoint to send requests to, defaults to production. `api_base_url`
+    /// is still read as a name for it.
+    #[serde(alias = "api_base_url")]
+    pub environment: Option<CloudflareEnvironment>,
+}
+
+/// A token kept in a Vault KV v2 secret.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VaultConfig {
+    /// e.g. `https://vault.example.com:8200`.
+    pub address: String,
+    #[serde(default = "default_vault_mount")]
+    pub mount: String,
+    /// Secret path below the mount, e.g. `cfbind/cloudflare`.
+    pub path: String,
+    /// Field of the secret holding the token.
+    #[serde(default = "default_vault_key")]
+    pub key: String,
+    #[serde(default)]
+    pub auth: VaultAuth,
+    /// Seconds between re-reads of the secret, defaults to an hour.
+    pub refresh_every: Option<u64>,
+}
+
+fn default_vault_mount() -> String {
+    "secret".to_string()
+}
+
+fn default_vault_key() -> String {
+    "api_token".to_string()
+}
+
+/// How cfbind logs in to Vault.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "method", rename_all = "lowercase")]
+pub enum VaultAuth {
+    /// A Vault token, or `VAULT_TOKEN` from the environment when unset.
+    Token { token: Option<Secret> },
+    #[serde(rename = "approle")]
+    AppRole { role_id: String, secret_id: Secret },
+    /// The pod's service account token, for cfbind running in Kubernetes.
+    Kubernetes {
+        role: String,
+        jwt_file: Option<PathBuf>,
+    },
+}
+
+impl Default for VaultAuth {
+    fn default() -> Self {
+        VaultAuth::Token { token: None }
+    }
+}
+
+/// Credentials are resolved through the standard AWS chain (environment,
+/// shared profile, instance metadata); these only narrow it down.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Route53Config {
+    pub region: Option<String>,
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct HetznerConfig {
+    pub api_token: Option<Secret>,
+}
+
+/// Any service speaking the DynDNS2 `/nic/update` protocol (No-IP, Dynu, ...).
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DynDns2Config {
+    pub server: Option<String>,
+    pub username: String,
+    pub password: Secret,
+}
+
+/// A primary nameserver accepting TSIG-signed dynamic updates (RFC 2136),
+/// e.g. BIND or Knot. Updates are sent with `nsupdate`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Rfc2136Config {
+    /// Host name or address of the primary.
+    pub server: String,
+    #[serde(default = "default_dns_port")]
+    pub port: u16,
+    /// The zone to update; found through its SOA record when unset.
+    pub zone: Option<String>,
+    pub key_name: String,
+    #[serde(default = "default_tsig_algorithm")]
+    pub key_algorithm: String,
+    /// The base64 TSIG secret, as in the server's `key` statement.
+    pub key_secret: Secret,
+    /// The `nsupdate` binary to run.
+    #[serde(default = "default_nsupdate")]
+    pub nsupdate: String,
+}
+
+fn default_dns_port() -> u16 {
+    53
+}
+
+fn default_tsig_algorithm() -> String {
+    "hmac-sha256".to_string()
+}
+
+fn default_nsupdate() -> String {
+    "nsupdate".to_string()
+}
+
+/// A PowerDNS Authoritative Server with its HTTP API enabled.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PowerDnsConfig {
+    /// Where the API listens, e.g. `http://ns1.example.net:8081`.
+    pub url: String,
+    /// The server's `api-key`.
+    pub api_key: Secret,
+    #[serde(default = "default_powerdns_server_id")]
+    pub server_id: String,
+}
+
+fn default_powerdns_server_id() -> String {
+    "localhost".to_string()
+}
+
+/// A Porkbun API key pair, with API access enabled for each domain.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PorkbunConfig {
+    pub api_key: Secret,
+    pub secret_api_key: Secret,
+}
+
+/// Accepts DynDNS2 updates from LAN devices and applies them to `hosts`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DynDnsServerConfig {
+    pub listen: String,
+    pub username: String,
+    pub password: Secret,
+    #[serde(default)]
+    pub hosts: Vec<DomainConfig>,
+}
+
+/// Receives IP reports from `cfbind agent` machines and applies them to each
+/// agent's `hosts`, so the provider credentials stay on this one host.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ControllerConfig {
+    pub listen: String,
+    /// Seconds an agent must wait between two reports of one address family.
+    #[serde(default = "default_controller_min_interval")]
+    pub min_interval: u64,
+    #[serde(default)]
+    pub agents: Vec<AgentConfig>,
+}
+
+fn default_controller_min_interval() -> u64 {
+    30
+}
+
+/// One machine allowed to report its IP, and the names it may set.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AgentConfig {
+    pub name: String,
+    /// Sent by the agent as `Authorization: Bearer <token>`.
+    pub token: Secret,
+    #[serde(default)]
+    pub hosts: Vec<DomainConfig>,
+}
+
+/// An internal resolver that also gets each synced name, pointing at the LAN
+/// address, so clients at home reach it without going through the router's
+/// public IP (split horizon).
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MirrorConfig {
+    #[serde(flatten)]
+    pub target: MirrorTarget,
+    /// Published instead of this host's own LAN address of the same family.
+    pub address: Option<IpAddr>,
+    /// Only mirror these names, defaults to every domain.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub names: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum MirrorTarget {
+    /// Pi-hole's local DNS records, through the v6 API.
+    Pihole { url: String, password: Secret },
+    /// A hosts file read through `addn-hosts`, reloaded with `reload`.
+    Dnsmasq {
+        hosts_file: PathBuf,
+        #[serde(default = "default_dnsmasq_reload")]
+        reload: String,
+    },
+    /// Local data set with `unbound-control`.
+    Unbound {
+        #[serde(default = "default_unbound_control")]
+        control: String,
+    },
+}
+
+fn default_dnsmasq_reload() -> String {
+    "pkill -HUP dnsmasq".to_string()
+}
+
+fn default_unbound_control() -> String {
+    "unbound-control".to_string()
+}
+
+/// Programs extending cfbind, spoken to with JSON over stdin and stdout.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct PluginsConfig {
+    /// Used for domains with `provider = "plugin"`.
+    pub provider: Option<PluginConfig>,
+    /// Asked for the public IP before the echo services, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ip_sources: Vec<PluginConfig>,
+    /// Told about events as they happen.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notifiers: Vec<NotifierConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PluginConfig {
+    /// The program and its arguments, run once per call.
+    pub command: Vec<String>,
+    /// Seconds a call may take.
+    #[serde(default = "default_plugin_timeout")]
+    pub timeout: u64,
+    /// Handed to the plugin with every call, e.g. its credentials.
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub settings: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct NotifierConfig {
+    #[serde(flatten)]
+    pub plugin: PluginConfig,
+    /// Only these kinds of events, defaults to changes and failures.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<EventKind>,
+}
+
+fn default_plugin_timeout() -> u64 {
+    30
+}
+
+/// HTTP control API, authenticated with `Authorization: Bearer <token>`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ApiConfig {
+    pub listen: String,
+    pub token: Secret,
+}
+
+/// The gRPC control service of `proto/cfbind.proto`, authenticated like the
+/// HTTP API. Needs a build with the `grpc` feature.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GrpcConfig {
+    pub listen: String,
+    pub token: Secret,
+}
+
+/// Local control over a Unix socket, or a named pipe on Windows. Only the
+/// socket's owner, root and the listed users and groups may connect, going by
+/// the peer's credentials; the pipe keeps Windows' default security instead.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ControlConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket: Option<PathBuf>,
+    /// e.g. `\\.\pipe\cfbind`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pipe: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow_uids: Vec<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow_gids: Vec<u32>,
+}
+
+/// Checks run after each write to confirm the change is actually served.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VerifyConfig {
+    /// Query the zone's authoritative nameservers directly.
+    #[serde(default)]
+    pub authoritative: bool,
+    /// Resolve the record over DNS-over-HTTPS.
+    #[serde(default)]
+    pub doh: bool,
+    /// DoH JSON endpoint, defaults to cloudflare-dns.com.
+    pub doh_url: Option<String>,
+    /// Restore the previous record content when verification fails.
+    #[serde(default)]
+    pub rollback: bool,
+    /// Seconds to keep retrying before giving up.
+    #[serde(default = "default_verify_timeout")]
+    pub timeout: u64,
+}
+
+impl Default for VerifyConfig {
+    fn default() -> Self {
+        VerifyConfig {
+            authoritative: false,
+            doh: false,
+            doh_url: None,
+            rollback: false,
+            timeout: default_verify_timeout(),
+        }
+    }
+}
+
+fn default_verify_timeout() -> u64 {
+    60
+}
+
+/// Keeps a durable log of IP changes and DNS writes in SQLite.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct HistoryConfig {
+    /// Database file, defaults to `~/.local/share/cfbind/history.db`.
+    pub path: Option<PathBuf>,
+}
+
+/// Opt-in error reporting to Sentry.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SentryConfig {
+    pub dsn: String,
+    /// Tags events, e.g. with a site or fleet name.
+    pub environment: Option<String>,
+}
+
+/// Prometheus metrics for setups without a scrapeable listener.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct MetricsConfig {
+    /// `.prom` file rewritten after each cycle, for node_exporter's textfile collector.
+    pub textfile: Option<PathBuf>,
+    /// Pushgateway base URL that `cfbind update` pushes each run's results to.
+    pub pushgateway: Option<String>,
+    /// Job label for pushed metrics, defaults to `cfbind`.
+    pub job: Option<String>,
+}
+
+/// Settings for the echo service and provider API requests. The timeouts keep
+/// a stalled connection from holding up the update loop.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HttpConfig {
+    /// Seconds to wait for a connection to be established.
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    /// Seconds a whole request, including reading the response, may take.
+    #[serde(default = "default_http_timeout")]
+    pub timeout: u64,
+    /// Outbound proxy, e.g. `http://proxy:3128` or `socks5://proxy:1080`.
+    pub proxy: Option<String>,
+    /// PEM bundle of extra CAs to trust, e.g. a TLS-intercepting proxy's.
+    pub ca_file: Option<PathBuf>,
+    #[serde(default)]
+    pub tls: TlsBackend,
+    /// Sent on every request, defaults to `cfbind/<version> (+<repository>)`.
+    pub user_agent: Option<String>,
+    /// Local address requests leave from, to pick the uplink on a host with
+    /// several, e.g. dual WAN or a VPN next to the WAN.
+    pub bind_address: Option<IpAddr>,
+    /// Network interface requests leave through, e.g. `eth1` or `wg0`
+    /// (Linux only).
+    pub bind_interface: Option<String>,
+    /// Fixed addresses for host names, used instead of looking them up.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub resolve: BTreeMap<String, IpAddr>,
+    /// When the system resolver fails, look names up over DNS-over-HTTPS at
+    /// a hardcoded address, so a broken local resolver doesn't stop updates.
+    #[serde(default)]
+    pub doh_bootstrap: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsBackend {
+    /// The platform TLS library and its certificate store.
+    #[default]
+    Native,
+    /// rustls with the bundled Mozilla roots.
+    Rustls,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            connect_timeout: default_connect_timeout(),
+            timeout: default_http_timeout(),
+            proxy: None,
+            ca_file: None,
+            tls: TlsBackend::default(),
+            user_agent: None,
+            bind_address: None,
+            bind_interface: None,
+            resolve: BTreeMap::new(),
+            doh_bootstrap: false,
+        }
+    }
+}
+
+fn default_connect_timeout() -> u64 {
+    10
+}
+
+fn default_http_timeout() -> u64 {
+    30
+}
+
+/// How the public IP is detected.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct IpConfig {
+    /// Seconds an echo service's answer is reused before the service is asked
+    /// again, however many domains or cycles want it sooner.
+    #[serde(default = "default_ip_min_interval")]
+    pub min_interval: u64,
+    /// Echo services answering with the bare address, defaults to ipify.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<String>,
+    /// Used instead of `sources` when detecting an IPv4 address.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources_v4: Vec<String>,
+    /// Used instead of `sources` when detecting an IPv6 address, as many echo
+    /// services can only be reached over IPv4.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources_v6: Vec<String>,
+    /// Ask the sources in turn, one per detection, instead of always the first.
+    #[serde(default = "default_ip_rotate")]
+    pub rotate: bool,
+    /// Seconds one source may take before the next one is asked instead.
+    #[serde(default = "default_ip_timeout")]
+    pub timeout: u64,
+    /// What to do while traffic leaves through a VPN, whose exit address the
+    /// echo services would report.
+    #[serde(default)]
+    pub on_vpn: OnVpn,
+    /// Interfaces to treat as VPNs besides tun*, wg* and the like.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vpn_interfaces: Vec<String>,
+}
+
+/// `ignore` (only warn), `skip` (leave the records alone until the VPN is
+/// down) or the name of a physical interface to detect through instead.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub enum OnVpn {
+    #[default]
+    Ignore,
+    Skip,
+    Interface(String),
+}
+
+impl From<String> for OnVpn {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "ignore" => OnVpn::Ignore,
+            "skip" => OnVpn::Skip,
+            _ => OnVpn::Interface(value),
+        }
+    }
+}
+
+impl From<OnVpn> for String {
+    fn from(on_vpn: OnVpn) -> Self {
+        match on_vpn {
+            OnVpn::Ignore => "ignore".to_string(),
+            OnVpn::Skip => "skip".to_string(),
+            OnVpn::Interface(interface) => interface,
+        }
+    }
+}
+
+impl Default for IpConfig {
+    fn default() -> Self {
+        IpConfig {
+            min_interval: default_ip_min_interval(),
+            sources: Vec::new(),
+            sources_v4: Vec::new(),
+            sources_v6: Vec::new(),
+            rotate: default_ip_rotate(),
+            timeout: default_ip_timeout(),
+            on_vpn: OnVpn::default(),
+            vpn_interfaces: Vec::new(),
+        }
+    }
+}
+
+fn default_ip_min_interval() -> u64 {
+    30
+}
+
+fn default_ip_rotate() -> bool {
+    true
+}
+
+fn default_ip_timeout() -> u64 {
+    10
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DomainConfig {
+    pub name: String,
+    #[serde(default)]
+    pub provider: ProviderKind,
+    #[serde(default = "default_proxied")]
+    pub proxied: bool,
+    /// Local addresses of several WAN links. The public IP seen through each
+    /// one is kept in the name's record set, for round-robin DNS.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub via: Vec<IpAddr>,
+    /// Labels below `name` kept in sync with it, e.g. `["www"]`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// Fixed content published instead of the detected IP. An address, host
+    /// name or other text makes an A/AAAA, CNAME or TXT record.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    /// Cloudflare ID of the existing record to write, for tokens that can't
+    /// list records. With `zone_id` too, not even the zones are listed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone_id: Option<String>,
+    /// Times the record is held or re-asserted, instead of the top-level `windows`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub windows: Vec<WindowConfig>,
+}
+
+fn default_proxied() -> bool {
+    true
+}
+
+impl DomainConfig {
+    /// A record ID only makes sense for one plain Cloudflare record.
+    pub fn check_record_id(&self) -> Result<()> {
+        if self.zone_id.is_some() && self.record_id.is_none() {
+            bail!(
+                "{}: zone_id is only used together with record_id",
+                self.name
+            );
+        }
+        if self.record_id.is_some() {
+            if self.provider != ProviderKind::Cloudflare {
+                bail!("{}: record_id is only supported for Cloudflare", self.name);
+            }
+            if !self.via.is_empty() || !self.aliases.is_empty() {
+                bail!(
+                    "{}: record_id can't be combined with via or aliases",
+                    self.name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The domain and its aliases as a zone group with `name` as the apex.
+    pub fn into_zone(self) -> ZoneConfig {
+        ZoneConfig {
+            hosts: std::iter::once("@".to_string())
+                .chain(self.aliases)
+                .collect(),
+            name: self.name,
+            provider: self.provider,
+            proxied: self.proxied,
+            windows: self.windows,
+        }
+    }
+}
+
+/// A daily stretch of time in which records are held or re-asserted.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WindowConfig {
+    pub action: WindowAction,
+    /// `HH:MM`. A window ending before it starts runs past midnight.
+    pub start: String,
+    pub end: String,
+    /// Days the window starts on, e.g. `["mon", "fri"]`; every day when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub days: Vec<chrono::Weekday>,
+    /// IANA time zone the times are in, e.g. `Europe/Berlin`; local time by default.
+    pub timezone: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowAction {
+    /// Leave records as they are, even when the IP changed.
+    Hold,
+    /// Rewrite records once per window, even when they look up to date.
+    Force,
+}
+
+/// Several hosts of one zone pointed at the same IP, synced together.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ZoneConfig {
+    pub name: String,
+    /// Labels below the zone, e.g. `["nas", "git"]`; `@` is the apex.
+    pub hosts: Vec<String>,
+    #[serde(default)]
+    pub provider: ProviderKind,
+    #[serde(default = "default_proxied")]
+    pub proxied: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub windows: Vec<WindowConfig>,
+}
+
+impl ZoneConfig {
+    pub fn domains(&self) -> Vec<DomainConfig> {
+        self.hosts
+            .iter()
+            .map(|host| DomainConfig {
+                name: match host.as_str() {
+                    "@" => self.name.clone(),
+                    _ => format!("{}.{}", host, self.name),
+                },
+                provider: self.provider,
+                proxied: self.proxied,
+                via: Vec::new(),
+                aliases: Vec::new(),
+                content: None,
+                record_id: None,
+                zone_id: None,
+                windows: self.windows.clone(),
+            })
+            .collect()
+    }
+}
+
+pub const DEFAULT_INTERVAL: u64 = 60;
+pub const DEFAULT_CONCURRENCY: usize = 4;
+pub const DEFAULT_FLAP_THRESHOLD: u32 = 4;
+
+const EXAMPLE: &str = include_str!("config.example.toml");
+
+/// A config that could not be read, parsed or validated. Loading wraps its
+/// errors in this, so they and no others exit with the config status.
+#[derive(Debug)]
+pub struct Invalid(pub anyhow::Error);
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Invalid {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// `~/.config/cfbind/config.toml` or the platform equivalent.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("cfbind").join("config.toml"))
+}
+
+/// The commented example config, with the given values filled in. They are
+/// written as TOML values, so quotes or backslashes in them stay valid.
+pub fn example(
+    domain: Option<&str>,
+    api_key: Option<&str>,
+    proxied: bool,
+    interval: u64,
+) -> String {
+    let string = |value: &str| toml::Value::String(value.to_string()).to_string();
+    EXAMPLE
+        .replace("{domain}", &string(domain.unwrap_or("home.example.com")))
+        .replace("{api_key}", &string(api_key.unwrap_or("your-api-token")))
+        .replace("{proxied}", &toml::Value::Boolean(proxied).to_string())
+        .replace(
+            "{interval}",
+            &toml::Value::Integer(interval as i64).to_string(),
+        )
+}
+
+/// Parses `CFBIND_*` boolean variables the way clap does: 1/true/yes/on or 0/false/no/off.
+fn env_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+impl Config {
+    /// Container mode: fills the config from `CFBIND_*` variables and fails
+    /// with every missing or malformed one listed, before anything starts.
+    pub fn apply_env(&mut self) -> Result<()> {
+        let mut problems = Vec::new();
+        let proxied = match std::env::var("CFBIND_PROXIED") {
+            Ok(value) => env_bool(&value).unwrap_or_else(|| {
+                problems.push(format!(
+                    "CFBIND_PROXIED must be true or false, got {:?}",
+                    value
+                ));
+                true
+            }),
+            Err(_) => default_proxied(),
+        };
+        if let Ok(domains) = std::env::var("CFBIND_DOMAINS") {
+            let names = domains.split(',').map(str::trim).filter(|n| !n.is_empty());
+            self.domains.extend(names.map(|name| DomainConfig {
+                name: name.to_string(),
+                provider: ProviderKind::Cloudflare,
+                proxied,
+                via: Vec::new(),
+                aliases: Vec::new(),
+                content: None,
+                record_id: None,
+                zone_id: None,
+                windows: Vec::new(),
+            }));
+        }
+        if let Ok(interval) = std::env::var("CFBIND_INTERVAL") {
+            match interval.parse::<u64>() {
+                Ok(interval) if interval > 0 => self.interval = Some(interval),
+                _ => problems.push(format!(
+                    "CFBIND_INTERVAL must be a number of seconds, got {:?}",
+                    interval
+                )),
+            }
+        }
+        if self.domains.is_empty() && self.zones.is_empty() {
+            problems.push(
+                "CFBIND_DOMAINS is not set, e.g. CFBIND_DOMAINS=home.example.com,vpn.example.com"
+                    .to_string(),
+            );
+        }
+        let cloudflare = self.cloudflare.as_ref();
+        if cloudflare
+            .is_none_or(|c| c.api_key.is_none() && c.api_key_file.is_none() && c.vault.is_none())
+        {
+            problems.push("CF_API_KEY is not set, it needs a Cloudflare API token".to_string());
+        }
+        if !problems.is_empty() {
+            bail!(
+                "Missing or invalid environment:\n  {}",
+                problems.join("\n  ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Shared limit on in-flight syncs, so a long domain list doesn't hit rate limits.
+    pub fn sync_limit(&self) -> Arc<Semaphore> {
+        let permits = self.concurrency.unwrap_or(DEFAULT_CONCURRENCY).max(1);
+        Arc::new(Semaphore::new(permits))
+    }
+
+    /// The windows of `domain`, or the top-level ones when it lists none.
+    pub fn windows_for(&self, domain: &DomainConfig) -> &[WindowConfig] {
+        if domain.windows.is_empty() {
+            &self.windows
+        } else {
+            &domain.windows
+        }
+    }
+
+    /// The config `tenant` runs with: its own sections in place of the top
+    /// level's, everything else inherited, and no listeners.
+    pub fn for_tenant(&self, tenant: &TenantConfig) -> Config {
+        let state_file = tenant.state_file.clone().or_else(|| {
+            let shared = self
+                .state_file
+                .clone()
+                .or_else(crate::state::default_path)?;
+            Some(shared.with_file_name(format!("state.{}.json", tenant.name)))
+        });
+        Config {
+            cloudflare: tenant.cloudflare.clone(),
+            route53: tenant.route53.clone(),
+            hetzner: tenant.hetzner.clone(),
+            dyndns2: tenant.dyndns2.clone(),
+            rfc2136: tenant.rfc2136.clone(),
+            powerdns: tenant.powerdns.clone(),
+            porkbun: tenant.porkbun.clone(),
+            plugins: PluginsConfig {
+                ip_sources: self.plugins.ip_sources.clone(),
+                ..tenant.plugins.clone()
+            },
+            mirrors: tenant.mirrors.clone(),
+            state_file,
+            status_file: tenant.status_file.clone(),
+            healthcheck_file: tenant.healthcheck_file.clone(),
+            history: tenant.history.clone(),
+            domains: tenant.domains.clone(),
+            zones: tenant.zones.clone(),
+            dyndns_server: None,
+            api: None,
+            control: None,
+            grpc: None,
+            controller: None,
+            metrics: None,
+            tenants: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Config> {
+        let raw = encrypted::read(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&raw)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ttl_accepts_auto_and_the_bounds() {
+        assert_eq!(Ttl::try_from(1), Ok(Ttl::Auto));
+        assert_eq!(Ttl::try_from(MIN_TTL), Ok(Ttl::Seconds(MIN_TTL)));
+        assert_eq!(Ttl::try_from(MAX_TTL), Ok(Ttl::Seconds(MAX_TTL)));
+        assert_eq!("AUTO".parse(), Ok(Ttl::Auto));
+        assert_eq!("300".parse(), Ok(Ttl::Seconds(300)));
+    }
+
+    #[test]
+    fn ttl_rejects_values_out_of_bounds() {
+        assert!(Ttl::try_from(0).is_err());
+        assert!(Ttl::try_from(MIN_TTL - 1).is_err());
+        assert!(Ttl::try_from(MAX_TTL + 1).is_err());
+        assert!("soon".parse::<Ttl>().is_err());
+    }
+
+    #[test]
+    fn ttl_reads_numbers_and_keywords_from_toml() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            ttl: Ttl,
+        }
+        let ttl = |raw: &str| toml::from_str::<Wrapper>(raw).map(|w| w.ttl);
+        assert_eq!(ttl("ttl = 120").unwrap(), Ttl::Seconds(120));
+        assert_eq!(ttl("ttl = \"auto\"").unwrap(), Ttl::Auto);
+        assert!(ttl("ttl = 30").is_err());
+        assert_eq!(Ttl::Auto.seconds(), 1);
+    }
+
+    #[test]
+    fn environment_parses_production_and_urls() {
+        assert_eq!("Production".parse(), Ok(CloudflareEnvironment::Production));
+        let CloudflareEnvironment::Custom(url) = "http://localhost:8080/client/v4".parse().unwrap()
+        else {
+            panic!("expected a custom environment");
+        };
+        assert_eq!(url.as_str(), "http://localhost:8080/client/v4/");
+        assert_eq!(
+            url.join("zones").unwrap().as_str(),
+            "http://localhost:8080/client/v4/zones"
+        );
+    }
+
+    #[test]
+    fn environment_rejects_other_schemes() {
+        assert!("ftp://example.com"
+            .parse::<CloudflareEnvironment>()
+            .is_err());
+        assert!("staging".parse::<CloudflareEnvironment>().is_err());
+    }
+
+    #[test]
+    fn on_vpn_round_trips_through_strings() {
+        assert_eq!(OnVpn::from("ignore".to_string()), OnVpn::Ignore);
+        assert_eq!(OnVpn::from("skip".to_string()), OnVpn::Skip);
+        assert_eq!(
+            OnVpn::from("eth0".to_string()),
+            OnVpn::Interface("eth0".to_string())
+        );
+        assert_eq!(String::from(OnVpn::Interface("eth0".to_string())), "eth0");
+        assert_eq!(String::from(OnVpn::Skip), "skip");
+    }
+
+    #[test]
+    fn env_bool_reads_clap_spellings() {
+        assert_eq!(env_bool("Yes"), Some(true));
+        assert_eq!(env_bool("off"), Some(false));
+        assert_eq!(env_bool("maybe"), None);
+    }
+
+    // The only test touching the process environment, so the others can run
+    // alongside it.
+    #[test]
+    fn apply_env_fills_in_and_lists_problems() {
+        std::env::set_var("CFBIND_DOMAINS", "home.example.com, vpn.example.com,");
+        std::env::set_var("CFBIND_PROXIED", "no");
+        std::env::set_var("CFBIND_INTERVAL", "120");
+        let mut config = Config {
+            cloudflare: Some(CloudflareConfig {
+                api_key: Some(Secret::new("token")),
+                ..CloudflareConfig::default()
+            }),
+            ..Config::default()
+        };
+        config.apply_env().unwrap();
+        let names: Vec<&str> = config.domains.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, ["home.example.com", "vpn.example.com"]);
+        assert!(config.domains.iter().all(|d| !d.proxied));
+        assert_eq!(config.interval, Some(120));
+
+        std::env::remove_var("CFBIND_DOMAINS");
+        std::env::set_var("CFBIND_PROXIED", "perhaps");
+        std::env::set_var("CFBIND_INTERVAL", "0");
+        let message = Config::default().apply_env().unwrap_err().to_string();
+        std::env::remove_var("CFBIND_PROXIED");
+        std::env::remove_var("CFBIND_INTERVAL");
+        for variable in [
+            "CFBIND_PROXIED",
+            "CFBIND_INTERVAL",
+            "CFBIND_DOMAINS",
+            "CF_API_KEY",
+        ] {
+            assert!(
+                message.contains(variable),
+                "{} not in {}",
+                variable,
+                message
+            );
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use tokio::net::windows::named_pipe::{ClientOptions, NamedPipeServer, ServerOptions};
+
+use crate::config::ControlConfig;
+use crate::control_socket::{answer, request, Reply};
+use crate::state::State;
+
+/// What opening a pipe fails with while every instance is taken.
+const ERROR_PIPE_BUSY: i32 = 231;
+
+/// How often `send` retries a busy pipe before giving up.
+const BUSY_RETRIES: u32 = 20;
+
+fn listen(name: &str, first: bool) -> Result<NamedPipeServer> {
+    ServerOptions::new()
+        // Fails if another process already holds the name, instead of
+        // serving alongside it.
+        .first_pipe_instance(first)
+        .reject_remote_clients(true)
+        .create(name)
+        .with_context(|| format!("Failed to listen on {}", name))
+}
+
+/// Accepts control connections on the configured named pipe until the
+/// process exits. The pipe keeps Windows' default security, under which only
+/// the account cfbind runs as, administrators and SYSTEM may write to it.
+pub async fn serve(config: ControlConfig, state: Arc<State>) -> Result<()> {
+    let Some(name) = config.pipe else {
+        bail!("[control] needs a pipe on this platform");
+    };
+    if !config.allow_uids.is_empty() || !config.allow_gids.is_empty() {
+        log::warn!("allow_uids and allow_gids don't apply to a named pipe, ignoring them");
+    }
+    let mut server = listen(&name, true)?;
+    log::info!("Control pipe listening on {}", name);
+    loop {
+        server.connect().await?;
+        // The next client needs an instance of its own.
+        let client = std::mem::replace(&mut server, listen(&name, false)?);
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            let (read, write) = tokio::io::split(client);
+            if let Err(e) = answer(read, write, &state, "pipe client").await {
+                log::debug!("Control connection failed: {:#}", e);
+            }
+        });
+    }
+}
+
+/// Sends `command` to the pipe `name` and returns the reply.
+pub async fn send(name: &str, command: &str) -> Result<Reply> {
+    let mut retries = BUSY_RETRIES;
+    let client = loop {
+        match ClientOptions::new().open(name) {
+            Ok(client) => break client,
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) && retries > 0 => {
+                retries -= 1;
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to connect to {}", name)),
+        }
+    };
+    let (read, write) = tokio::io::split(client);
+    request(read, write, command).await
+}
@@ -0,0 +1,198 @@
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use std::sync::Arc;
+
+#[cfg(unix)]
+use anyhow::bail;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+#[cfg(unix)]
+use crate::config::ControlConfig;
+use crate::state::State;
+
+/// The commands the socket (or pipe, on Windows) understands, one per line.
+pub const COMMANDS: [&str; 5] = ["status", "update", "pause", "resume", "reload"];
+
+/// One line answering one command.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Reply {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Reply {
+    fn ok() -> Reply {
+        Reply {
+            ok: true,
+            status: None,
+            error: None,
+        }
+    }
+
+    fn error(message: String) -> Reply {
+        Reply {
+            ok: false,
+            status: None,
+            error: Some(message),
+        }
+    }
+}
+
+#[cfg(unix)]
+struct Allowed {
+    uids: Vec<u32>,
+    gids: Vec<u32>,
+}
+
+/// Removes a socket left behind by an earlier run, refusing to touch anything else.
+#[cfg(unix)]
+fn remove_stale(path: &Path) -> Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove stale socket {}", path.display())),
+        Ok(_) => bail!("{} exists and is not a socket", path.display()),
+        Err(_) => Ok(()),
+    }
+}
+
+fn execute(command: &str, state: &State) -> Reply {
+    match command {
+        "status" => match serde_json::to_value(state.status()) {
+            Ok(status) => Reply {
+                status: Some(status),
+                ..Reply::ok()
+            },
+            Err(e) => Reply::error(e.to_string()),
+        },
+        "update" => {
+            state.trigger_update();
+            Reply::ok()
+        }
+        "pause" => {
+            state.set_paused(true);
+            Reply::ok()
+        }
+        "resume" => {
+            state.set_paused(false);
+            Reply::ok()
+        }
+        "reload" => {
+            state.trigger_reload();
+            Reply::ok()
+        }
+        _ => Reply::error(format!(
+            "Unknown command {:?}, expected one of {}",
+            command,
+            COMMANDS.join(", ")
+        )),
+    }
+}
+
+async fn write_reply<W: AsyncWrite + Unpin>(write: &mut W, reply: &Reply) -> Result<()> {
+    write
+        .write_all(format!("{}\n", serde_json::to_string(reply)?).as_bytes())
+        .await?;
+    Ok(())
+}
+
+/// Answers each command line read from `read` until the client hangs up.
+pub(crate) async fn answer<R, W>(read: R, mut write: W, state: &State, peer: &str) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        let command = line.trim();
+        if command.is_empty() {
+            continue;
+        }
+        log::debug!("Control command {:?} from {}", command, peer);
+        write_reply(&mut write, &execute(command, state)).await?;
+    }
+    Ok(())
+}
+
+/// Sends one command line and reads the reply.
+pub(crate) async fn request<R, W>(read: R, mut write: W, command: &str) -> Result<Reply>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    write.write_all(format!("{}\n", command).as_bytes()).await?;
+    write.shutdown().await?;
+    let line = BufReader::new(read)
+        .lines()
+        .next_line()
+        .await?
+        .context("The control channel closed without answering")?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(unix)]
+async fn handle(stream: UnixStream, state: Arc<State>, allowed: Arc<Allowed>) -> Result<()> {
+    let peer = stream.peer_cred()?;
+    let (read, mut write) = stream.into_split();
+    if !allowed.uids.contains(&peer.uid()) && !allowed.gids.contains(&peer.gid()) {
+        log::warn!(
+            "Refused control connection from uid {} (pid {:?})",
+            peer.uid(),
+            peer.pid()
+        );
+        let reply = Reply::error("Permission denied".to_string());
+        return write_reply(&mut write, &reply).await;
+    }
+    answer(read, write, &state, &format!("uid {}", peer.uid())).await
+}
+
+/// Accepts control connections on the configured socket until the process exits.
+#[cfg(unix)]
+pub async fn serve(config: ControlConfig, state: Arc<State>) -> Result<()> {
+    let Some(socket) = &config.socket else {
+        bail!("[control] needs a socket on this platform");
+    };
+    remove_stale(socket)?;
+    let listener = UnixListener::bind(socket)
+        .with_context(|| format!("Failed to listen on {}", socket.display()))?;
+    // Group members may connect; the peer check below still decides.
+    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o660))?;
+    let owner = std::fs::metadata(socket)?.uid();
+    let mut uids = config.allow_uids.clone();
+    uids.extend([owner, 0]);
+    let allowed = Arc::new(Allowed {
+        uids,
+        gids: config.allow_gids.clone(),
+    });
+    log::info!("Control socket listening on {}", socket.display());
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let state = Arc::clone(&state);
+        let allowed = Arc::clone(&allowed);
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, state, allowed).await {
+                log::debug!("Control connection failed: {:#}", e);
+            }
+        });
+    }
+}
+
+/// Sends `command` to the socket at `path` and returns the reply.
+#[cfg(unix)]
+pub async fn send(path: &Path, command: &str) -> Result<Reply> {
+    let stream = UnixStream::connect(path)
+        .await
+        .with_context(|| format!("Failed to connect to {}", path.display()))?;
+    let (read, write) = stream.into_split();
+    request(read, write, command).await
+}
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use axum::extract::{ConnectInfo, State as AxumState};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
+
+use crate::config::ControllerConfig;
+use crate::dyndns_server::Target;
+use crate::secret::{self, Secret};
+use crate::state::{EventKind, State};
+use crate::store::WriteResult;
+use crate::updater::Outcome;
+
+/// Where agents send their reports.
+pub const REPORT_PATH: &str = "/v1/report";
+
+/// An agent's report. Without `ip`, the address the report came from is used.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Report {
+    pub ip: Option<IpAddr>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct HostResult {
+    pub name: String,
+    pub outcome: Option<Outcome>,
+    pub error: Option<String>,
+}
+
+/// What the controller did with a report.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ReportResult {
+    pub agent: String,
+    pub ip: IpAddr,
+    pub hosts: Vec<HostResult>,
+}
+
+/// An agent as the controller knows it, with the names it may set.
+pub struct Agent {
+    pub name: String,
+    pub token: Secret,
+    pub targets: HashMap<String, Target>,
+}
+
+struct Controller {
+    agents: Vec<Agent>,
+    min_interval: Duration,
+    /// When each agent last reported an address of each family.
+    reported: Mutex<HashMap<(usize, bool), Instant>>,
+    /// Shared with the updaters, so agents can't push past the provider's rate limits.
+    limit: Arc<Semaphore>,
+    dry_run: bool,
+    state: Arc<State>,
+}
+
+impl Controller {
+    fn agent(&self, headers: &HeaderMap) -> Option<usize> {
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))?;
+        self.agents
+            .iter()
+            .position(|agent| agent.token.matches(token))
+    }
+
+    /// How long the agent must still wait before reporting an address of
+    /// this family again, or `None` after taking the report.
+    fn throttle(&self, agent: usize, ip: IpAddr) -> Option<Duration> {
+        let mut reported = self.reported.lock().unwrap();
+        let key = (agent, ip.is_ipv4());
+        if let Some(last) = reported.get(&key) {
+            let elapsed = last.elapsed();
+            if elapsed < self.min_interval {
+                return Some(self.min_interval - elapsed);
+            }
+        }
+        reported.insert(key, Instant::now());
+        None
+    }
+
+    async fn apply(&self, name: &str, target: &Target, ip: IpAddr) -> Result<Outcome> {
+        let _permit = self.limit.acquire().await?;
+        let spec = target.spec(name, ip);
+        let existing = target
+            .provider
+            .get_record(name, spec.content.record_type())
+            .await?;
+        let outcome = match &existing {
+            Some(record) if spec.matches(record) => Outcome::Unchanged,
+            _ if self.dry_run => return Ok(Outcome::WouldUpdate),
+            _ => {
+                if let Err(e) = target.provider.upsert_record(&spec).await {
+                    let old = existing.as_ref().map(|r| &r.content);
+                    let error = secret::describe(&e);
+                    let failed = WriteResult::Failed;
+                    self.state
+                        .record_write(name, old, &spec.content, failed, Some(&error));
+                    return Err(e);
+                }
+                let old = existing.as_ref().map(|r| &r.content);
+                self.state
+                    .record_write(name, old, &spec.content, WriteResult::Updated, None);
+                Outcome::Updated
+            }
+        };
+        Ok(outcome)
+    }
+}
+
+async fn report(
+    AxumState(controller): AxumState<Arc<Controller>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    report: Option<Json<Report>>,
+) -> Response {
+    let Some(index) = controller.agent(&headers) else {
+        log::warn!("Rejected report from {}: bad token", peer);
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    let agent = &controller.agents[index];
+    if controller.state.is_paused() {
+        // Refused rather than taken, so the agent reports again once resumed.
+        log::info!("Refused report from {} while paused", agent.name);
+        for name in agent.targets.keys() {
+            controller
+                .state
+                .notify(name, EventKind::Skipped, "paused".to_string());
+        }
+        return (StatusCode::SERVICE_UNAVAILABLE, "updates are paused").into_response();
+    }
+    let ip = report
+        .and_then(|Json(report)| report.ip)
+        .unwrap_or_else(|| peer.ip());
+    if let Some(wait) = controller.throttle(index, ip) {
+        let retry_after = wait.as_secs().max(1).to_string();
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after)],
+        )
+            .into_response();
+    }
+
+    let mut hosts = Vec::new();
+    for (name, target) in &agent.targets {
+        let mut result = HostResult {
+            name: name.clone(),
+            outcome: None,
+            error: None,
+        };
+        match controller.apply(name, target, ip).await {
+            Ok(outcome) => {
+                let kind = match outcome {
+                    Outcome::Updated => EventKind::Updated,
+                    Outcome::Unchanged | Outcome::WouldUpdate => EventKind::Unchanged,
+                };
+                controller.state.record(name, kind, ip.to_string());
+                result.outcome = Some(outcome);
+            }
+            Err(e) => {
+                log::error!(
+                    "Failed to apply {}'s report to {}: {:#}",
+                    agent.name,
+                    name,
+                    e
+                );
+                let error = secret::describe(&e);
+                controller
+                    .state
+                    .record(name, EventKind::Error, error.clone());
+                result.error = Some(error);
+            }
+        }
+        hosts.push(result);
+    }
+    hosts.sort_by(|a, b| a.name.cmp(&b.name));
+    log::info!("Report from {} ({}): {}", agent.name, peer, ip);
+    Json(ReportResult {
+        agent: agent.name.clone(),
+        ip,
+        hosts,
+    })
+    .into_response()
+}
+
+/// Accepts reports from `agents`. Run it behind a TLS-terminating proxy
+/// when agents reach it over the internet.
+pub async fn serve(
+    config: ControllerConfig,
+    agents: Vec<Agent>,
+    limit: Arc<Semaphore>,
+    dry_run: bool,
+    state: Arc<State>,
+) -> Result<()> {
+    let controller = Arc::new(Controller {
+        agents,
+        min_interval: Duration::from_secs(config.min_interval),
+        reported: Mutex::new(HashMap::new()),
+        limit,
+        dry_run,
+        state,
+    });
+    let app = Router::new()
+        .route(REPORT_PATH, post(report))
+        .with_state(controller);
+    let listener = TcpListener::bind(&config.listen).await?;
+    log::info!("Controller listening on {}", config.listen);
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use crate::provider::{Record, RecordSpec};
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+fn field(out: &mut String, name: &str, old: Option<String>, new: String, color: bool) {
+    let (red, green, reset) = if color {
+        (RED, GREEN, RESET)
+    } else {
+        ("", "", "")
+    };
+    match old {
+        Some(old) if old == new => out.push_str(&format!("    {:<8} {}\n", name, new)),
+        Some(old) => out.push_str(&format!(
+            "  {}- {:<8} {}{}\n  {}+ {:<8} {}{}\n",
+            red, name, old, reset, green, name, new, reset
+        )),
+        None => out.push_str(&format!("  {}+ {:<8} {}{}\n", green, name, new, reset)),
+    }
+}
+
+/// Renders a field-by-field diff between the current record (if any) and the
+/// state that would be written, in ANSI colors when `color` is set.
+pub fn render(existing: Option<&Record>, spec: &RecordSpec, color: bool) -> String {
+    let mut out = format!(
+        "{} {} {}\n",
+        if existing.is_some() {
+            "update"
+        } else {
+            "create"
+        },
+        spec.content.record_type(),
+        spec.name
+    );
+    field(
+        &mut out,
+        "content",
+        existing.map(|r| r.content.to_string()),
+        spec.content.to_string(),
+        color,
+    );
+    field(
+        &mut out,
+        "ttl",
+        existing.map(|r| r.ttl.to_string()),
+        spec.ttl
+            .map_or_else(|| "auto".to_string(), |ttl| ttl.to_string()),
+        color,
+    );
+    field(
+        &mut out,
+        "proxied",
+        existing.map(|r| r.proxied.to_string()),
+        spec.proxied.to_string(),
+        color,
+    );
+    out
+}
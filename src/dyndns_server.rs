@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use anyhow::Result;
+use axum::extract::{ConnectInfo, Query, State as AxumState};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use base64::Engine;
+use serde::Deserialize;
+use tokio::net::TcpListener;
+
+use crate::config::{DynDnsServerConfig, Ttl};
+use crate::provider::{DnsProvider, RecordContent, RecordSpec};
+use crate::secret::{self, Secret};
+use crate::state::{EventKind, State};
+use crate::store::WriteResult;
+use crate::template;
+
+/// A hostname the server is willing to update and where it lives.
+pub struct Target {
+    pub provider: Arc<dyn DnsProvider>,
+    pub proxied: bool,
+    /// The configured TTL and comment, written as the updaters write them.
+    pub ttl: Option<Ttl>,
+    pub comment: Option<String>,
+}
+
+impl Target {
+    /// The record `name` should hold to point at `ip`.
+    pub fn spec(&self, name: &str, ip: IpAddr) -> RecordSpec {
+        RecordSpec {
+            name: name.to_string(),
+            content: RecordContent::from(ip),
+            ttl: self.ttl.map(Ttl::seconds),
+            proxied: self.proxied && self.provider.supports_proxy(),
+            comment: self.comment.as_deref().map(template::comment),
+        }
+    }
+}
+
+struct ServerState {
+    username: String,
+    password: Secret,
+    targets: HashMap<String, Target>,
+    state: Arc<State>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateParams {
+    hostname: Option<String>,
+    myip: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+fn basic_auth(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let encoded = value.strip_prefix("Basic ")?;
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+async fn update(
+    AxumState(server): AxumState<Arc<ServerState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(params): Query<UpdateParams>,
+) -> Response {
+    // Some routers can only put credentials in the query string.
+    let credentials =
+        basic_auth(&headers).or_else(|| params.username.clone().zip(params.password.clone()));
+    match credentials {
+        Some((username, password))
+            if username == server.username && server.password.matches(&password) => {}
+        _ => {
+            log::warn!("Rejected DynDNS2 update from {}: bad credentials", peer);
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Basic realm=\"cfbind\"")],
+                "badauth",
+            )
+                .into_response();
+        }
+    }
+
+    let Some(hostnames) = params.hostname.as_deref() else {
+        return "notfqdn".into_response();
+    };
+    let ip = match params.myip.as_deref() {
+        Some(myip) => match myip.parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => return "911".into_response(),
+        },
+        None => peer.ip(),
+    };
+
+    let state = &server.state;
+    if state.is_paused() {
+        // The protocol's "try again later"; clients back off before retrying.
+        log::info!("Refused DynDNS2 update from {} while paused", peer);
+        let known = hostnames.split(',').map(str::trim);
+        for hostname in known.filter(|h| server.targets.contains_key(*h)) {
+            state.notify(hostname, EventKind::Skipped, "paused".to_string());
+        }
+        return "911".into_response();
+    }
+    let mut answers = Vec::new();
+    for hostname in hostnames.split(',').map(str::trim) {
+        let Some(target) = server.targets.get(hostname) else {
+            answers.push("nohost".to_string());
+            continue;
+        };
+        let spec = target.spec(hostname, ip);
+        let answer = match target
+            .provider
+            .get_record(hostname, spec.content.record_type())
+            .await
+        {
+            Ok(Some(record)) if spec.matches(&record) => {
+                state.record(hostname, EventKind::Unchanged, ip.to_string());
+                format!("nochg {}", ip)
+            }
+            Ok(existing) => {
+                let old = existing.as_ref().map(|r| &r.content);
+                match target.provider.upsert_record(&spec).await {
+                    Ok(_) => {
+                        let updated = WriteResult::Updated;
+                        state.record_write(hostname, old, &spec.content, updated, None);
+                        state.record(hostname, EventKind::Updated, ip.to_string());
+                        format!("good {}", ip)
+                    }
+                    Err(e) => {
+                        log::error!("DynDNS2 update of {} failed: {:#}", hostname, e);
+                        let error = secret::describe(&e);
+                        let failed = WriteResult::Failed;
+                        state.record_write(hostname, old, &spec.content, failed, Some(&error));
+                        state.record(hostname, EventKind::Error, error);
+                        "dnserr".to_string()
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("DynDNS2 lookup of {} failed: {:#}", hostname, e);
+                state.record(hostname, EventKind::Error, secret::describe(&e));
+                "dnserr".to_string()
+            }
+        };
+        log::info!("DynDNS2 update from {}: {} -> {}", peer, hostname, answer);
+        answers.push(answer);
+    }
+    answers.join("\n").into_response()
+}
+
+pub async fn serve(
+    config: DynDnsServerConfig,
+    targets: HashMap<String, Target>,
+    state: Arc<State>,
+) -> Result<()> {
+    let server = Arc::new(ServerState {
+        username: config.username,
+        password: config.password,
+        targets,
+        state,
+    });
+    let app = Router::new()
+        .route("/nic/update", get(update))
+        .with_state(server);
+    let listener = TcpListener::bind(&config.listen).await?;
+    log::info!("DynDNS2 server listening on {}", config.listen);
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
+    Ok(())
+}
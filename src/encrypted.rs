@@ -0,0 +1,88 @@
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+
+const AGE_BINARY: &[u8] = b"age-encryption.org/v1";
+const AGE_ARMORED: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// Where SOPS keeps age identities when `SOPS_AGE_KEY_FILE` is not set.
+fn default_age_key_file() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("sops").join("age").join("keys.txt"))
+}
+
+/// Identities from `SOPS_AGE_KEY` or the `SOPS_AGE_KEY_FILE` file, the same
+/// variables SOPS itself reads, so one key serves both formats.
+fn age_identities() -> Result<Vec<Box<dyn age::Identity + Send + Sync>>> {
+    let keys = match std::env::var("SOPS_AGE_KEY") {
+        Ok(keys) => keys,
+        Err(_) => {
+            let path = std::env::var_os("SOPS_AGE_KEY_FILE")
+                .map(PathBuf::from)
+                .or_else(default_age_key_file)
+                .context("Set SOPS_AGE_KEY or SOPS_AGE_KEY_FILE to decrypt the config")?;
+            std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read age key file {}", path.display()))?
+        }
+    };
+    Ok(age::IdentityFile::from_buffer(keys.as_bytes())?.into_identities()?)
+}
+
+fn decrypt_age(raw: &[u8]) -> Result<Vec<u8>> {
+    let identities = age_identities()?;
+    let armored = age::armor::ArmoredReader::new(raw);
+    let decryptor = match age::Decryptor::new(armored)? {
+        age::Decryptor::Recipients(decryptor) => decryptor,
+        _ => bail!("Passphrase-encrypted configs are not supported, encrypt to an age key"),
+    };
+    let mut plain = Vec::new();
+    decryptor
+        .decrypt(identities.iter().map(|i| i.as_ref() as &dyn age::Identity))?
+        .read_to_end(&mut plain)?;
+    Ok(plain)
+}
+
+/// Whether `raw` is a SOPS file in binary mode, a JSON wrapper with the
+/// ciphertext under `data` and the key metadata under `sops`.
+fn is_sops(raw: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Value>(raw)
+        .is_ok_and(|value| value.get("sops").is_some() && value.get("data").is_some())
+}
+
+fn decrypt_sops(path: &Path) -> Result<Vec<u8>> {
+    let output = Command::new("sops")
+        .args([
+            "--decrypt",
+            "--input-type",
+            "binary",
+            "--output-type",
+            "binary",
+        ])
+        .arg(path)
+        .output()
+        .context("Failed to run sops, is it installed?")?;
+    if !output.status.success() {
+        bail!(
+            "sops could not decrypt {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Reads a config file that may be encrypted with age (binary or armored) or
+/// SOPS, returning the plain text.
+pub fn read(path: &Path) -> Result<String> {
+    let mut raw = Vec::new();
+    BufReader::new(std::fs::File::open(path)?).read_to_end(&mut raw)?;
+    let plain = if raw.starts_with(AGE_BINARY) || raw.starts_with(AGE_ARMORED) {
+        decrypt_age(&raw).with_context(|| format!("Failed to decrypt {}", path.display()))?
+    } else if is_sops(&raw) {
+        decrypt_sops(path)?
+    } else {
+        raw
+    };
+    String::from_utf8(plain).with_context(|| format!("{} is not valid UTF-8", path.display()))
+}
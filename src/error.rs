@@ -0,0 +1,210 @@
+use std::fmt;
+
+use ::cloudflare::framework::response::ApiFailure;
+
+use crate::exit::Status;
+
+const AUTH_ERROR_CODES: [u16; 4] = [6003, 6111, 9109, 10000];
+/// "Authentication error": the token itself was refused.
+const INVALID_TOKEN: u16 = 10000;
+/// "Unauthorized to access requested resource": the token lacks a permission.
+const UNAUTHORIZED: u16 = 9109;
+const NOT_FOUND_ERROR_CODES: [u16; 3] = [1001, 7003, 81044];
+/// "A record with the same settings already exists" and its close relatives.
+const CONFLICT_ERROR_CODES: [u16; 3] = [81053, 81057, 81058];
+/// "Please wait and consider throttling your request speed", and its variants.
+const RATE_LIMIT_ERROR_CODES: [u16; 3] = [971, 9710, 9711];
+
+/// The failures callers react to differently. Each variant keeps the
+/// provider's own error as its source.
+#[derive(Debug)]
+pub enum CfbindError {
+    /// The provider rejected the credentials.
+    Auth(anyhow::Error),
+    /// No zone the credentials can see holds the name.
+    ZoneNotFound(String),
+    /// The zone or record a request named by ID does not exist (any more).
+    RecordNotFound(anyhow::Error),
+    /// A record with the same name and content already exists.
+    Conflict(anyhow::Error),
+    /// The provider asked to slow down.
+    RateLimited(anyhow::Error),
+    /// The provider could not be reached.
+    Network(anyhow::Error),
+    /// A response could not be understood.
+    Parse(anyhow::Error),
+    /// Any other rejected request.
+    Api(anyhow::Error),
+}
+
+impl CfbindError {
+    /// Whether `error` or one of its causes is a rate limit.
+    pub fn is_rate_limited(error: &anyhow::Error) -> bool {
+        error.chain().any(|cause| {
+            matches!(
+                cause.downcast_ref::<CfbindError>(),
+                Some(CfbindError::RateLimited(_))
+            )
+        })
+    }
+
+    /// The exit code class of the error.
+    pub fn status(&self) -> Status {
+        match self {
+            CfbindError::Auth(_) => Status::Auth,
+            CfbindError::ZoneNotFound(_) | CfbindError::RecordNotFound(_) => Status::NotFound,
+            CfbindError::Network(_) => Status::Network,
+            CfbindError::Conflict(_)
+            | CfbindError::RateLimited(_)
+            | CfbindError::Parse(_)
+            | CfbindError::Api(_) => Status::Failure,
+        }
+    }
+}
+
+impl fmt::Display for CfbindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CfbindError::Auth(_) => write!(f, "The provider rejected the credentials"),
+            CfbindError::ZoneNotFound(name) => write!(f, "Zone for {} not found", name),
+            CfbindError::RecordNotFound(_) => write!(f, "Record not found"),
+            CfbindError::Conflict(_) => write!(f, "A conflicting record already exists"),
+            CfbindError::RateLimited(_) => write!(f, "Rate limited by the provider"),
+            CfbindError::Network(_) => write!(f, "The provider could not be reached"),
+            CfbindError::Parse(_) => write!(f, "Unexpected response from the provider"),
+            CfbindError::Api(_) => write!(f, "The provider rejected the request"),
+        }
+    }
+}
+
+impl std::error::Error for CfbindError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CfbindError::ZoneNotFound(_) => None,
+            CfbindError::Auth(e)
+            | CfbindError::RecordNotFound(e)
+            | CfbindError::Conflict(e)
+            | CfbindError::RateLimited(e)
+            | CfbindError::Network(e)
+            | CfbindError::Parse(e)
+            | CfbindError::Api(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+impl From<reqwest::Error> for CfbindError {
+    fn from(error: reqwest::Error) -> Self {
+        match error.status().map(|s| s.as_u16()) {
+            Some(401 | 403) => CfbindError::Auth(error.into()),
+            Some(404) => CfbindError::RecordNotFound(error.into()),
+            Some(429) => CfbindError::RateLimited(error.into()),
+            _ if error.is_connect() || error.is_timeout() || error.is_request() => {
+                CfbindError::Network(error.into())
+            }
+            _ if error.is_decode() => CfbindError::Parse(error.into()),
+            _ => CfbindError::Api(error.into()),
+        }
+    }
+}
+
+/// Sorts a failed Cloudflare call by its error `codes`, then its HTTP
+/// `status`; `error` becomes the source.
+pub fn classify(status: u16, codes: &[u16], error: anyhow::Error) -> CfbindError {
+    let has = |known: &[u16]| codes.iter().any(|c| known.contains(c));
+    if has(&[INVALID_TOKEN]) {
+        CfbindError::Auth(error.context(
+            "Cloudflare refused the API token; check that it is current and was copied whole",
+        ))
+    } else if has(&[UNAUTHORIZED]) {
+        CfbindError::Auth(error.context(
+            "The API token may not touch this zone; it needs Zone:Read and DNS:Edit on it",
+        ))
+    } else if has(&AUTH_ERROR_CODES) {
+        CfbindError::Auth(error)
+    } else if has(&NOT_FOUND_ERROR_CODES) {
+        CfbindError::RecordNotFound(error)
+    } else if has(&CONFLICT_ERROR_CODES) {
+        CfbindError::Conflict(error)
+    } else if has(&RATE_LIMIT_ERROR_CODES) || status == 429 {
+        CfbindError::RateLimited(error)
+    } else {
+        match status {
+            401 | 403 => CfbindError::Auth(error),
+            404 => CfbindError::RecordNotFound(error),
+            _ => CfbindError::Api(error),
+        }
+    }
+}
+
+impl From<ApiFailure> for CfbindError {
+    fn from(failure: ApiFailure) -> Self {
+        match failure {
+            ApiFailure::Error(status, errors) => {
+                let codes: Vec<u16> = errors.errors.iter().map(|e| e.code).collect();
+                let failure = ApiFailure::Error(status, errors);
+                classify(status.as_u16(), &codes, anyhow::Error::new(failure))
+            }
+            ApiFailure::Invalid(error) => CfbindError::from(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn class(status: u16, codes: &[u16]) -> CfbindError {
+        classify(status, codes, anyhow::anyhow!("rejected"))
+    }
+
+    #[test]
+    fn refused_and_underprivileged_tokens_are_auth_errors() {
+        assert!(matches!(class(400, &[10000]), CfbindError::Auth(_)));
+        assert!(matches!(class(403, &[9109]), CfbindError::Auth(_)));
+        assert!(matches!(class(400, &[6003]), CfbindError::Auth(_)));
+    }
+
+    #[test]
+    fn auth_errors_explain_the_code() {
+        let CfbindError::Auth(refused) = class(400, &[10000]) else {
+            panic!("not an auth error");
+        };
+        assert!(refused.to_string().contains("refused the API token"));
+        let CfbindError::Auth(unauthorized) = class(403, &[9109]) else {
+            panic!("not an auth error");
+        };
+        assert!(unauthorized.to_string().contains("Zone:Read and DNS:Edit"));
+    }
+
+    #[test]
+    fn missing_records_and_conflicts_are_told_apart() {
+        assert!(matches!(
+            class(404, &[81044]),
+            CfbindError::RecordNotFound(_)
+        ));
+        assert!(matches!(class(400, &[81057]), CfbindError::Conflict(_)));
+    }
+
+    #[test]
+    fn rate_limits_are_recognized_by_code_or_status() {
+        for code in [971, 9710, 9711] {
+            assert!(matches!(class(400, &[code]), CfbindError::RateLimited(_)));
+        }
+        assert!(matches!(class(429, &[]), CfbindError::RateLimited(_)));
+    }
+
+    #[test]
+    fn unknown_codes_fall_back_to_the_status() {
+        assert!(matches!(class(403, &[1234]), CfbindError::Auth(_)));
+        assert!(matches!(class(404, &[]), CfbindError::RecordNotFound(_)));
+        assert!(matches!(class(500, &[1234]), CfbindError::Api(_)));
+    }
+
+    #[test]
+    fn rate_limits_are_found_in_the_chain() {
+        let error = anyhow::Error::new(class(429, &[])).context("Failed to update");
+        assert!(CfbindError::is_rate_limited(&error));
+        let error = anyhow::Error::new(class(500, &[])).context("Failed to update");
+        assert!(!CfbindError::is_rate_limited(&error));
+    }
+}
@@ -0,0 +1,85 @@
+use std::process::ExitCode;
+
+use crate::config::Invalid;
+use crate::error::CfbindError;
+use crate::ip::DetectionFailed;
+
+/// Process exit codes, so wrappers can branch on the class of failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Status {
+    /// Nothing needed to change.
+    Success = 0,
+    /// Any failure not covered below, or a `check` mismatch.
+    Failure = 1,
+    /// At least one record was written.
+    Updated = 2,
+    /// The provider rejected the credentials.
+    Auth = 3,
+    /// A zone or record does not exist.
+    NotFound = 4,
+    /// The provider or echo service could not be reached.
+    Network = 5,
+    /// The config file could not be read or parsed, or failed validation.
+    Config = 6,
+}
+
+impl From<Status> for ExitCode {
+    fn from(status: Status) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
+
+fn classify_http(status: u16) -> Option<Status> {
+    match status {
+        401 | 403 => Some(Status::Auth),
+        404 => Some(Status::NotFound),
+        _ => None,
+    }
+}
+
+fn classify_reqwest(error: &reqwest::Error) -> Status {
+    if let Some(status) = error.status().and_then(|s| classify_http(s.as_u16())) {
+        return status;
+    }
+    if error.is_connect() || error.is_timeout() || error.is_request() {
+        return Status::Network;
+    }
+    Status::Failure
+}
+
+/// Walks the error chain for the first cause with a known class.
+pub fn classify(error: &anyhow::Error) -> Status {
+    for cause in error.chain() {
+        if let Some(e) = cause.downcast_ref::<CfbindError>() {
+            return e.status();
+        }
+        if cause.is::<DetectionFailed>() {
+            return Status::Network;
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return classify_reqwest(e);
+        }
+        if cause.is::<Invalid>() {
+            return Status::Config;
+        }
+    }
+    Status::Failure
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn missing_file() -> anyhow::Error {
+        std::io::Error::from(std::io::ErrorKind::NotFound).into()
+    }
+
+    #[test]
+    fn only_config_errors_exit_with_the_config_status() {
+        assert_eq!(classify(&missing_file()), Status::Failure);
+        let invalid = anyhow::Error::from(Invalid(missing_file()));
+        assert_eq!(classify(&invalid), Status::Config);
+        assert_eq!(classify(&invalid.context("starting")), Status::Config);
+    }
+}
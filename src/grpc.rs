@@ -0,0 +1,174 @@
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::StreamExt;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status as RpcStatus};
+
+use crate::config::GrpcConfig;
+use crate::secret::Secret;
+use crate::state::{self, State};
+
+pub mod proto {
+    tonic::include_proto!("cfbind.v1");
+}
+
+use proto::control_server::{Control, ControlServer};
+
+fn timestamp(time: Option<DateTime<Utc>>) -> Option<String> {
+    time.map(|t| t.to_rfc3339())
+}
+
+impl From<&state::Event> for proto::Event {
+    fn from(event: &state::Event) -> Self {
+        proto::Event {
+            timestamp: event.timestamp.to_rfc3339(),
+            domain: event.domain.clone(),
+            // The names --events ndjson uses.
+            kind: serde_json::to_value(event.kind)
+                .ok()
+                .and_then(|kind| kind.as_str().map(str::to_string))
+                .unwrap_or_default(),
+            message: event.message.clone(),
+        }
+    }
+}
+
+impl From<state::Status> for proto::Status {
+    fn from(status: state::Status) -> Self {
+        proto::Status {
+            paused: status.paused,
+            current_ip: status.current_ip.map(|ip| ip.to_string()),
+            detection_ok: status.detection_ok,
+            failures: status
+                .failures
+                .into_iter()
+                .map(|(failure, count)| (failure.to_string(), count))
+                .collect(),
+            domains: status
+                .domains
+                .into_iter()
+                .map(|(name, domain)| {
+                    let domain = proto::DomainStatus {
+                        content: domain.content,
+                        last_check: timestamp(domain.last_check),
+                        last_update: timestamp(domain.last_update),
+                        last_error: domain.last_error,
+                        last_success: timestamp(domain.last_success),
+                        verified: domain.verified,
+                    };
+                    (name, domain)
+                })
+                .collect(),
+        }
+    }
+}
+
+struct Service {
+    state: Arc<State>,
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<proto::Event, RpcStatus>> + Send>>;
+
+#[tonic::async_trait]
+impl Control for Service {
+    type WatchEventsStream = EventStream;
+
+    async fn get_status(
+        &self,
+        _: Request<proto::GetStatusRequest>,
+    ) -> Result<Response<proto::Status>, RpcStatus> {
+        Ok(Response::new(self.state.status().into()))
+    }
+
+    async fn get_history(
+        &self,
+        _: Request<proto::GetHistoryRequest>,
+    ) -> Result<Response<proto::GetHistoryResponse>, RpcStatus> {
+        let events = self.state.history().iter().map(Into::into).collect();
+        Ok(Response::new(proto::GetHistoryResponse { events }))
+    }
+
+    async fn trigger_update(
+        &self,
+        _: Request<proto::TriggerUpdateRequest>,
+    ) -> Result<Response<proto::TriggerUpdateResponse>, RpcStatus> {
+        self.state.trigger_update();
+        Ok(Response::new(proto::TriggerUpdateResponse {}))
+    }
+
+    async fn pause(
+        &self,
+        _: Request<proto::PauseRequest>,
+    ) -> Result<Response<proto::PauseResponse>, RpcStatus> {
+        self.state.set_paused(true);
+        Ok(Response::new(proto::PauseResponse {}))
+    }
+
+    async fn resume(
+        &self,
+        _: Request<proto::ResumeRequest>,
+    ) -> Result<Response<proto::ResumeResponse>, RpcStatus> {
+        self.state.set_paused(false);
+        Ok(Response::new(proto::ResumeResponse {}))
+    }
+
+    async fn reload(
+        &self,
+        _: Request<proto::ReloadRequest>,
+    ) -> Result<Response<proto::ReloadResponse>, RpcStatus> {
+        self.state.trigger_reload();
+        Ok(Response::new(proto::ReloadResponse {}))
+    }
+
+    async fn watch_events(
+        &self,
+        _: Request<proto::WatchEventsRequest>,
+    ) -> Result<Response<Self::WatchEventsStream>, RpcStatus> {
+        let events = BroadcastStream::new(self.state.subscribe()).map(|event| match event {
+            Ok(event) => Ok(proto::Event::from(&event)),
+            Err(BroadcastStreamRecvError::Lagged(missed)) => Err(RpcStatus::data_loss(format!(
+                "{} events were missed, watch again",
+                missed
+            ))),
+        });
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+/// Rejects calls without `authorization: Bearer <token>`.
+fn authorize(token: &Secret, request: Request<()>) -> Result<Request<()>, RpcStatus> {
+    let authorized = request
+        .metadata()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|given| token.matches(given));
+    if !authorized {
+        return Err(RpcStatus::unauthenticated("missing or wrong token"));
+    }
+    Ok(request)
+}
+
+pub async fn serve(config: GrpcConfig, state: Arc<State>) -> Result<()> {
+    let addr: SocketAddr = config
+        .listen
+        .parse()
+        .with_context(|| format!("Invalid gRPC listen address {}", config.listen))?;
+    let token = config.token;
+    let service = ControlServer::with_interceptor(Service { state }, move |request| {
+        authorize(&token, request)
+    });
+    log::info!("gRPC control service listening on {}", addr);
+    Server::builder()
+        .add_service(service)
+        .serve(addr)
+        .await
+        .context("gRPC server failed")
+}
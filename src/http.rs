@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+use crate::config::{HttpConfig, TlsBackend};
+use crate::provider::RecordType;
+use crate::verify;
+
+pub const DEFAULT_USER_AGENT: &str = concat!(
+    "cfbind/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/lsgrep/cfbind)"
+);
+
+static SETTINGS: OnceLock<HttpConfig> = OnceLock::new();
+static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+static BOUND: OnceLock<Mutex<HashMap<IpAddr, reqwest::Client>>> = OnceLock::new();
+static BY_INTERFACE: OnceLock<Mutex<HashMap<(String, Option<IpAddr>), reqwest::Client>>> =
+    OnceLock::new();
+
+/// Applies the `[http]` config section. Must run before the first request;
+/// later calls are ignored.
+pub fn init(config: &HttpConfig) -> Result<()> {
+    if let Some(proxy) = &config.proxy {
+        reqwest::Proxy::all(proxy).with_context(|| format!("Invalid proxy URL {}", proxy))?;
+    }
+    if let Some(ca_file) = &config.ca_file {
+        ca_certificates(ca_file)?;
+    }
+    if let Some(user_agent) = &config.user_agent {
+        reqwest::header::HeaderValue::from_str(user_agent)
+            .with_context(|| format!("Invalid user agent {:?}", user_agent))?;
+    }
+    let _ = SETTINGS.set(config.clone());
+    Ok(())
+}
+
+fn ca_certificates(path: &Path) -> Result<Vec<reqwest::Certificate>> {
+    let pem = std::fs::read(path)
+        .with_context(|| format!("Failed to read CA file {}", path.display()))?;
+    reqwest::Certificate::from_pem_bundle(&pem)
+        .with_context(|| format!("Invalid CA file {}", path.display()))
+}
+
+pub fn settings() -> &'static HttpConfig {
+    SETTINGS.get_or_init(HttpConfig::default)
+}
+
+pub fn user_agent() -> &'static str {
+    settings()
+        .user_agent
+        .as_deref()
+        .unwrap_or(DEFAULT_USER_AGENT)
+}
+
+pub fn connect_timeout() -> Duration {
+    Duration::from_secs(settings().connect_timeout)
+}
+
+pub fn timeout() -> Duration {
+    Duration::from_secs(settings().timeout)
+}
+
+/// DoH endpoints reached by address, so finding them needs no resolver.
+const DOH_BOOTSTRAP: [&str; 2] = ["https://1.1.1.1/dns-query", "https://1.0.0.1/dns-query"];
+
+/// Looks names up with the system resolver and, when that fails, over
+/// DNS-over-HTTPS at [`DOH_BOOTSTRAP`]. A broken local resolver is common
+/// exactly when records are stale, so this keeps updates going through.
+struct BootstrapResolver;
+
+async fn doh_lookup(name: &str) -> Result<Vec<IpAddr>> {
+    static DOH_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    let client = DOH_CLIENT.get_or_init(|| {
+        plain_builder()
+            .build()
+            .expect("HTTP client configuration is valid")
+    });
+    let mut failures = Vec::new();
+    for url in DOH_BOOTSTRAP {
+        let mut addresses = Vec::new();
+        for record_type in [RecordType::A, RecordType::Aaaa] {
+            match verify::doh_query(client, url, name, record_type).await {
+                Ok(found) => addresses.extend(found),
+                Err(e) => failures.push(format!("{}: {:#}", url, e)),
+            }
+        }
+        if !addresses.is_empty() {
+            return Ok(addresses);
+        }
+    }
+    bail!("{} not found over DoH: {}", name, failures.join("; "))
+}
+
+impl Resolve for BootstrapResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let name = name.as_str().to_string();
+        Box::pin(async move {
+            let system = match tokio::net::lookup_host((name.as_str(), 0)).await {
+                Ok(found) => {
+                    let found: Vec<SocketAddr> = found.collect();
+                    if !found.is_empty() {
+                        return Ok(Box::new(found.into_iter()) as Addrs);
+                    }
+                    "no addresses".to_string()
+                }
+                Err(e) => e.to_string(),
+            };
+            let found = doh_lookup(&name).await?;
+            log::warn!(
+                "The system resolver failed for {} ({}), resolved it over DoH",
+                name,
+                system
+            );
+            let found = found.into_iter().map(|ip| SocketAddr::new(ip, 0));
+            Ok(Box::new(found.collect::<Vec<_>>().into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Everything but the DoH bootstrap, which itself uses a client like this.
+fn plain_builder() -> reqwest::ClientBuilder {
+    let settings = settings();
+    let mut builder = reqwest::Client::builder()
+        .user_agent(user_agent())
+        .connect_timeout(connect_timeout())
+        .timeout(timeout());
+    builder = match settings.tls {
+        TlsBackend::Native => builder.use_native_tls(),
+        TlsBackend::Rustls => builder.use_rustls_tls(),
+    };
+    if let Some(proxy) = &settings.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy).expect("validated in init"));
+    }
+    if let Some(ca_file) = &settings.ca_file {
+        for certificate in ca_certificates(ca_file).expect("validated in init") {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    if let Some(address) = settings.bind_address {
+        builder = builder.local_address(address);
+    }
+    if let Some(interface) = &settings.bind_interface {
+        builder = builder.interface(interface);
+    }
+    for (host, ip) in &settings.resolve {
+        builder = builder.resolve(host, SocketAddr::new(*ip, 0));
+    }
+    builder
+}
+
+fn builder() -> reqwest::ClientBuilder {
+    let builder = plain_builder();
+    if settings().doh_bootstrap {
+        return builder.dns_resolver(Arc::new(BootstrapResolver));
+    }
+    builder
+}
+
+/// The process-wide HTTP client. Clones share one connection pool, so the
+/// echo request and provider API calls reuse connections across cycles.
+/// Without a configured proxy, `HTTPS_PROXY`/`ALL_PROXY` are honoured.
+pub fn client() -> reqwest::Client {
+    CLIENT
+        .get_or_init(|| {
+            builder()
+                .build()
+                .expect("HTTP client configuration is valid")
+        })
+        .clone()
+}
+
+/// A client whose connections leave from the local address `local`, one per
+/// address and shared like [`client`]. `local` takes the place of a configured
+/// `bind_address`.
+pub fn bound_client(local: IpAddr) -> Result<reqwest::Client> {
+    let mut bound = BOUND.get_or_init(Default::default).lock().unwrap();
+    if let Some(client) = bound.get(&local) {
+        return Ok(client.clone());
+    }
+    let client = builder()
+        .local_address(local)
+        .build()
+        .with_context(|| format!("Failed to bind an HTTP client to {}", local))?;
+    bound.insert(local, client.clone());
+    Ok(client)
+}
+
+/// A client whose connections leave through `interface`, and from `local`
+/// when given, shared like [`client`]. Both take the place of the configured
+/// binding.
+pub fn interface_client(interface: &str, local: Option<IpAddr>) -> Result<reqwest::Client> {
+    let key = (interface.to_string(), local);
+    let mut bound = BY_INTERFACE.get_or_init(Default::default).lock().unwrap();
+    if let Some(client) = bound.get(&key) {
+        return Ok(client.clone());
+    }
+    let mut builder = builder().interface(interface);
+    if let Some(local) = local {
+        builder = builder.local_address(local);
+    }
+    let client = builder
+        .build()
+        .with_context(|| format!("Failed to bind an HTTP client to {}", interface))?;
+    bound.insert(key, client.clone());
+    Ok(client)
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use crate::config::{CloudflareConfig, Config, DomainConfig, ProviderKind};
+use crate::secret::Secret;
+
+/// Joins `\`-continued lines and drops comments and blank lines.
+fn logical_lines(raw: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for line in raw.lines() {
+        let line = match line.find('#') {
+            Some(i) => &line[..i],
+            None => line,
+        };
+        let trimmed = line.trim_end();
+        if let Some(continued) = trimmed.strip_suffix('\\') {
+            current.push_str(continued);
+            current.push(' ');
+            continue;
+        }
+        current.push_str(trimmed);
+        if !current.trim().is_empty() {
+            lines.push(current.trim().to_string());
+        }
+        current.clear();
+    }
+    if !current.trim().is_empty() {
+        lines.push(current.trim().to_string());
+    }
+    lines
+}
+
+/// Splits a line into `key=value` settings and bare host names.
+fn tokens(line: &str) -> (HashMap<String, String>, Vec<String>) {
+    let mut settings = HashMap::new();
+    let mut hosts = Vec::new();
+    for token in line
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|t| !t.is_empty())
+    {
+        match token.split_once('=') {
+            Some((key, value)) => {
+                let value = value.trim_matches(|c| c == '\'' || c == '"');
+                settings.insert(key.to_lowercase(), value.to_string());
+            }
+            None => hosts.push(token.to_string()),
+        }
+    }
+    (settings, hosts)
+}
+
+pub fn convert(raw: &str) -> Result<Config> {
+    let mut config = Config::default();
+    let mut globals: HashMap<String, String> = HashMap::new();
+    for line in logical_lines(raw) {
+        let (settings, hosts) = tokens(&line);
+        if hosts.is_empty() {
+            // ddclient treats settings-only lines as defaults for what follows.
+            globals.extend(settings);
+            continue;
+        }
+        let mut effective = globals.clone();
+        effective.extend(settings);
+
+        let protocol = effective.get("protocol").map(String::as_str);
+        if protocol != Some("cloudflare") {
+            eprintln!(
+                "Skipping {}: protocol {} is not imported",
+                hosts.join(", "),
+                protocol.unwrap_or("(none)")
+            );
+            continue;
+        }
+        if effective.get("login").is_some_and(|l| l != "token") {
+            eprintln!(
+                "Warning: {} uses a global API key (login={}); cfbind needs an API token instead",
+                hosts.join(", "),
+                effective["login"]
+            );
+        }
+        if let Some(password) = effective.get("password") {
+            let cloudflare = config
+                .cloudflare
+                .get_or_insert_with(CloudflareConfig::default);
+            match &cloudflare.api_key {
+                None => cloudflare.api_key = Some(Secret::new(password.as_str())),
+                Some(existing) if existing.expose() != password.as_str() => eprintln!(
+                    "Warning: {} uses a different Cloudflare credential; only the first one is kept",
+                    hosts.join(", ")
+                ),
+                Some(_) => {}
+            }
+        }
+        for host in hosts {
+            // ddclient never touches the proxy flag, so don't start proxying imported hosts.
+            config.domains.push(DomainConfig {
+                name: host,
+                provider: ProviderKind::Cloudflare,
+                proxied: false,
+                via: Vec::new(),
+                aliases: Vec::new(),
+                content: None,
+                record_id: None,
+                zone_id: None,
+                windows: Vec::new(),
+            });
+        }
+    }
+    if let Some(daemon) = globals.get("daemon") {
+        config.interval = daemon.trim_end_matches('s').parse().ok();
+    }
+    if config.domains.is_empty() {
+        bail!("No Cloudflare hosts found in the ddclient config");
+    }
+    Ok(config)
+}
+
+pub fn import(path: &Path) -> Result<Config> {
+    let raw = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    convert(&raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_continued_lines_and_drops_comments() {
+        let raw = "# ddclient.conf\nprotocol=cloudflare, \\\n  zone=example.com # the zone\n\nhome.example.com\n";
+        let lines = logical_lines(raw);
+        assert_eq!(lines.len(), 2);
+        let (settings, hosts) = tokens(&lines[0]);
+        assert_eq!(settings["protocol"], "cloudflare");
+        assert_eq!(settings["zone"], "example.com");
+        assert!(hosts.is_empty());
+        assert_eq!(lines[1], "home.example.com");
+    }
+
+    #[test]
+    fn splits_settings_from_hosts() {
+        let (settings, hosts) =
+            tokens("Login=token, password='s3cret' home.example.com,www.example.com");
+        assert_eq!(settings["login"], "token");
+        assert_eq!(settings["password"], "s3cret");
+        assert_eq!(hosts, ["home.example.com", "www.example.com"]);
+    }
+
+    #[test]
+    fn converts_cloudflare_hosts_only() {
+        let raw = "\
+daemon=300
+protocol=cloudflare
+login=token
+password=cf-api-token
+zone=example.com
+home.example.com, www.example.com
+protocol=dyndns2 other.example.net
+";
+        let config = convert(raw).unwrap();
+        let names: Vec<&str> = config.domains.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, ["home.example.com", "www.example.com"]);
+        let api_key = config.cloudflare.unwrap().api_key.unwrap();
+        assert_eq!(api_key.expose(), "cf-api-token");
+        assert_eq!(config.interval, Some(300));
+    }
+
+    #[test]
+    fn fails_without_cloudflare_hosts() {
+        assert!(convert("protocol=dyndns2\nhome.example.net\n").is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::config::{CloudflareConfig, Config, DomainConfig, ProviderKind};
+use crate::secret::Secret;
+
+/// Reads `KEY=VALUE` lines as found in docker-compose `.env` files.
+fn parse_env_file(raw: &str) -> HashMap<String, String> {
+    raw.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.strip_prefix("export ").unwrap_or(line).split_once('='))
+        .map(|(key, value)| {
+            let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+            (key.trim().to_string(), value.to_string())
+        })
+        .collect()
+}
+
+/// Parses Go-style durations such as `5m` or `1h30m`.
+fn parse_go_duration(value: &str) -> Option<u64> {
+    let mut total = 0;
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let n: u64 = number.parse().ok()?;
+        number.clear();
+        total += match c {
+            'h' => n * 3600,
+            'm' => n * 60,
+            's' => n,
+            _ => return None,
+        };
+    }
+    if number.is_empty() {
+        Some(total)
+    } else {
+        None
+    }
+}
+
+fn split_domains(value: Option<&String>) -> Vec<String> {
+    value
+        .map(|v| {
+            v.split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|d| !d.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub fn convert(env: &HashMap<String, String>) -> Result<Config> {
+    let mut config = Config::default();
+    let token = env
+        .get("CLOUDFLARE_API_TOKEN")
+        .or_else(|| env.get("CF_API_TOKEN"))
+        .cloned();
+    let token_file = match token {
+        Some(_) => None,
+        None => env.get("CF_API_TOKEN_FILE").map(PathBuf::from),
+    };
+    config.cloudflare = Some(CloudflareConfig {
+        api_key: token.map(Secret::new),
+        api_key_file: token_file,
+        vault: None,
+        zone_refresh: None,
+        environment: None,
+    });
+
+    let proxied = match env.get("PROXIED").map(String::as_str) {
+        None | Some("false") => false,
+        Some("true") => true,
+        Some(expression) => {
+            eprintln!(
+                "Warning: PROXIED={} is a domain expression; importing every domain as unproxied",
+                expression
+            );
+            false
+        }
+    };
+
+    let mut names = split_domains(env.get("DOMAINS"));
+    names.extend(split_domains(env.get("IP4_DOMAINS")));
+    let ip6_only = split_domains(env.get("IP6_DOMAINS"));
+    if !ip6_only.is_empty() {
+        eprintln!(
+            "Warning: IPv6-only domains are not imported: {}",
+            ip6_only.join(", ")
+        );
+    }
+    // A domain in both DOMAINS and IP4_DOMAINS is imported once, in order.
+    let mut seen = HashSet::new();
+    names.retain(|name| seen.insert(name.clone()));
+    for name in names {
+        config.domains.push(DomainConfig {
+            name,
+            provider: ProviderKind::Cloudflare,
+            proxied,
+            via: Vec::new(),
+            aliases: Vec::new(),
+            content: None,
+            record_id: None,
+            zone_id: None,
+            windows: Vec::new(),
+        });
+    }
+
+    if let Some(cron) = env.get("UPDATE_CRON") {
+        match cron.strip_prefix("@every ").and_then(parse_go_duration) {
+            Some(interval) => config.interval = Some(interval),
+            None => eprintln!(
+                "Warning: UPDATE_CRON={} is not an @every schedule; using the default interval",
+                cron
+            ),
+        }
+    }
+    if config.domains.is_empty() {
+        bail!("No domains found in DOMAINS or IP4_DOMAINS");
+    }
+    Ok(config)
+}
+
+/// Imports from an env file, or from the current environment when no file is given.
+pub fn import(path: Option<&Path>) -> Result<Config> {
+    let env = match path {
+        Some(path) => parse_env_file(
+            &std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?,
+        ),
+        None => std::env::vars().collect(),
+    };
+    convert(&env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn reads_env_files() {
+        let vars = parse_env_file(
+            "# cloudflare-ddns\nexport CLOUDFLARE_API_TOKEN=\"token\"\n\nDOMAINS='a.example.com'\n",
+        );
+        assert_eq!(vars["CLOUDFLARE_API_TOKEN"], "token");
+        assert_eq!(vars["DOMAINS"], "a.example.com");
+        assert_eq!(vars.len(), 2);
+    }
+
+    #[test]
+    fn parses_go_durations() {
+        assert_eq!(parse_go_duration("5m"), Some(300));
+        assert_eq!(parse_go_duration("1h30m"), Some(5400));
+        assert_eq!(parse_go_duration("90s"), Some(90));
+        assert_eq!(parse_go_duration("5"), None);
+        assert_eq!(parse_go_duration("5d"), None);
+    }
+
+    #[test]
+    fn imports_each_domain_once() {
+        let config = convert(&env(&[
+            ("CLOUDFLARE_API_TOKEN", "token"),
+            ("DOMAINS", "a.example.com, b.example.com"),
+            ("IP4_DOMAINS", "b.example.com a.example.com c.example.com"),
+            ("PROXIED", "true"),
+            ("UPDATE_CRON", "@every 10m"),
+        ]))
+        .unwrap();
+        let names: Vec<&str> = config.domains.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, ["a.example.com", "b.example.com", "c.example.com"]);
+        assert!(config.domains.iter().all(|d| d.proxied));
+        assert_eq!(config.interval, Some(600));
+    }
+
+    #[test]
+    fn reads_the_token_from_a_file_when_not_given() {
+        let config = convert(&env(&[
+            ("CF_API_TOKEN_FILE", "/run/secrets/token"),
+            ("DOMAINS", "a.example.com"),
+        ]))
+        .unwrap();
+        let cloudflare = config.cloudflare.unwrap();
+        assert!(cloudflare.api_key.is_none());
+        assert_eq!(
+            cloudflare.api_key_file,
+            Some(PathBuf::from("/run/secrets/token"))
+        );
+    }
+
+    #[test]
+    fn fails_without_domains() {
+        assert!(convert(&env(&[("CLOUDFLARE_API_TOKEN", "token")])).is_err());
+    }
+}
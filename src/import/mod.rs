@@ -0,0 +1,17 @@
+use anyhow::Result;
+
+use crate::config::Config;
+
+pub mod ddclient;
+pub mod favonia;
+pub mod timothymiller;
+
+/// Renders an imported config as TOML, noting where it came from.
+pub fn render(config: &Config, source: &str) -> Result<String> {
+    Ok(format!(
+        "# Imported from {} by cfbind {}\n\n{}",
+        source,
+        env!("CARGO_PKG_VERSION"),
+        toml::to_string_pretty(config)?
+    ))
+}
@@ -0,0 +1,134 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::config::{CloudflareConfig, Config, DomainConfig, ProviderKind};
+use crate::provider::cloudflare::{get_zone, Cloudflare};
+use crate::secret::Secret;
+
+#[derive(Debug, Deserialize)]
+struct SourceConfig {
+    cloudflare: Vec<ZoneEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ZoneEntry {
+    authentication: Authentication,
+    zone_id: String,
+    subdomains: Vec<Subdomain>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Authentication {
+    api_token: Option<String>,
+}
+
+/// Older versions list plain names, newer ones objects with a proxy flag.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Subdomain {
+    Name(String),
+    Entry {
+        name: String,
+        #[serde(default)]
+        proxied: bool,
+    },
+}
+
+fn fqdn(subdomain: &str, zone: &str) -> String {
+    match subdomain.trim_matches('.') {
+        "" | "@" => zone.to_string(),
+        sub => format!("{}.{}", sub, zone),
+    }
+}
+
+/// The config only carries zone IDs, so zone names are looked up with its token.
+pub async fn import(path: &Path) -> Result<Config> {
+    let raw = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let source: SourceConfig = serde_json::from_str(&raw)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    let mut config = Config::default();
+    for entry in source.cloudflare {
+        let Some(token) = entry.authentication.api_token.filter(|t| !t.is_empty()) else {
+            eprintln!(
+                "Skipping zone {}: only api_token authentication is supported",
+                entry.zone_id
+            );
+            continue;
+        };
+        let token = Secret::new(token);
+        let cloudflare = Cloudflare::new(&token)?;
+        let zone = get_zone(&cloudflare.client(), &entry.zone_id)
+            .await
+            .with_context(|| format!("Failed to look up zone {}", entry.zone_id))?;
+
+        let configured = config
+            .cloudflare
+            .get_or_insert_with(CloudflareConfig::default);
+        match &configured.api_key {
+            None => configured.api_key = Some(token),
+            Some(existing) if *existing != token => eprintln!(
+                "Warning: zone {} uses a different token; only the first one is kept",
+                zone.name
+            ),
+            Some(_) => {}
+        }
+
+        for subdomain in entry.subdomains {
+            let (name, proxied) = match subdomain {
+                Subdomain::Name(name) => (name, false),
+                Subdomain::Entry { name, proxied } => (name, proxied),
+            };
+            config.domains.push(DomainConfig {
+                name: fqdn(&name, &zone.name),
+                provider: ProviderKind::Cloudflare,
+                proxied,
+                via: Vec::new(),
+                aliases: Vec::new(),
+                content: None,
+                record_id: None,
+                zone_id: None,
+                windows: Vec::new(),
+            });
+        }
+    }
+    if config.domains.is_empty() {
+        bail!("No importable zones found in {}", path.display());
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_subdomains_to_the_zone() {
+        assert_eq!(fqdn("home", "example.com"), "home.example.com");
+        assert_eq!(fqdn(".home.", "example.com"), "home.example.com");
+        assert_eq!(fqdn("@", "example.com"), "example.com");
+        assert_eq!(fqdn("", "example.com"), "example.com");
+    }
+
+    #[test]
+    fn reads_plain_and_proxied_subdomains() {
+        let source: SourceConfig = serde_json::from_str(
+            r#"{"cloudflare": [{
+                "authentication": {"api_token": "token"},
+                "zone_id": "z1",
+                "subdomains": ["home", {"name": "www", "proxied": true}]
+            }]}"#,
+        )
+        .unwrap();
+        let entry = &source.cloudflare[0];
+        assert_eq!(entry.zone_id, "z1");
+        assert!(matches!(&entry.subdomains[0], Subdomain::Name(name) if name == "home"));
+        assert!(matches!(
+            &entry.subdomains[1],
+            Subdomain::Entry { name, proxied: true } if name == "www"
+        ));
+    }
+}
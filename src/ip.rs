@@ -0,0 +1,414 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::config::{IpConfig, IpFamily, OnVpn};
+use crate::http;
+use crate::vpn;
+
+/// A way to learn the public address besides the HTTP echo services, such
+/// as a cloud metadata endpoint or a router's API, for crates embedding
+/// cfbind. Registered sources are asked before the echo services, in the
+/// order they were registered; the first answer wins and is subject to
+/// `min_interval` like any other. Binding to a local address or interface
+/// only applies to the echo services.
+#[async_trait]
+pub trait IpSource: Send + Sync {
+    /// Names the source in logs.
+    fn name(&self) -> String;
+
+    /// The public address, of `family` when detection is limited to one.
+    async fn detect(&self, family: Option<IpFamily>) -> Result<IpAddr>;
+}
+
+static SOURCES: OnceLock<Mutex<Vec<Arc<dyn IpSource>>>> = OnceLock::new();
+
+/// Adds `source` to the ones asked by [`detect`].
+pub fn register(source: Arc<dyn IpSource>) {
+    SOURCES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .push(source);
+}
+
+/// Asks the registered sources in turn; `None` when none answers.
+async fn ask_registered(family: Option<IpFamily>, failures: &mut Vec<String>) -> Option<IpAddr> {
+    let sources = SOURCES.get()?.lock().unwrap().clone();
+    for source in sources {
+        let answer =
+            match source.detect(family).await {
+                Ok(ip) if family.is_some_and(|f| (f == IpFamily::V4) != ip.is_ipv4()) => Err(
+                    format!("{}: answered {}, of the wrong family", source.name(), ip),
+                ),
+                Ok(ip) => Ok(ip),
+                Err(e) => Err(format!("{}: {:#}", source.name(), e)),
+            };
+        match answer {
+            Ok(ip) => {
+                log::info!("{} from {}", ip, source.name());
+                return Some(ip);
+            }
+            Err(e) => {
+                log::warn!("IP source {}", e);
+                failures.push(e);
+            }
+        }
+    }
+    None
+}
+
+static FAMILY: OnceLock<IpFamily> = OnceLock::new();
+static SETTINGS: OnceLock<IpConfig> = OnceLock::new();
+/// Whether the VPN warning of `on_vpn = "ignore"` was logged already.
+static VPN_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Detections by the local address their requests are bound to.
+type Detections = HashMap<Option<IpAddr>, Arc<tokio::sync::Mutex<Detection>>>;
+
+static DETECTIONS: OnceLock<Mutex<Detections>> = OnceLock::new();
+
+/// The last answer, and which source the rotation asks next.
+#[derive(Default)]
+struct Detection {
+    answer: Option<Answer>,
+    next: usize,
+}
+
+struct Answer {
+    asked: Instant,
+    /// The address, or why there is none; a failure is reused like an answer
+    /// so a service that errors isn't hammered either.
+    result: Result<IpAddr, String>,
+}
+
+/// No source could tell the public IP. That points at the local connection
+/// rather than the DNS provider, so it is backed off and reported apart.
+#[derive(Debug)]
+pub struct DetectionFailed(pub String);
+
+impl fmt::Display for DetectionFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Could not detect the public IP: {}", self.0)
+    }
+}
+
+impl std::error::Error for DetectionFailed {}
+
+/// Traffic leaves through a VPN and `on_vpn` is `skip`: the detected IP would
+/// be the VPN's exit address, so nothing is detected or written.
+#[derive(Debug)]
+pub struct VpnActive(pub String);
+
+impl fmt::Display for VpnActive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VPN interface {} is up", self.0)
+    }
+}
+
+impl std::error::Error for VpnActive {}
+
+/// Whether `error` or one of its causes is a failed detection.
+pub fn is_detection_failure(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.is::<DetectionFailed>())
+}
+
+/// Applies the `[ip]` config section; later calls are ignored.
+pub fn init(config: &IpConfig) {
+    let _ = SETTINGS.set(config.clone());
+}
+
+/// Restricts [`detect`] to one address family; later calls are ignored.
+pub fn set_family(family: IpFamily) {
+    let _ = FAMILY.set(family);
+}
+
+fn settings() -> &'static IpConfig {
+    SETTINGS.get_or_init(IpConfig::default)
+}
+
+/// The echo services configured for `family`, else the ones for any
+/// family, or `default` when there are none.
+fn sources(family: Option<IpFamily>, default: &str) -> Vec<String> {
+    let settings = settings();
+    let specific = match family {
+        None => &[][..],
+        Some(IpFamily::V4) => settings.sources_v4.as_slice(),
+        Some(IpFamily::V6) => settings.sources_v6.as_slice(),
+    };
+    match (specific, settings.sources.as_slice()) {
+        ([], []) => vec![default.to_string()],
+        ([], sources) | (sources, _) => sources.to_vec(),
+    }
+}
+
+async fn get_current_ip(client: &reqwest::Client, url: &str) -> Result<String> {
+    let response = client.get(url).send().await?.text().await?;
+    Ok(response)
+}
+
+/// Asks one source, giving up on it after the configured `timeout`.
+async fn query(client: &reqwest::Client, url: &str) -> Result<IpAddr, String> {
+    let timeout = Duration::from_secs(settings().timeout);
+    match tokio::time::timeout(timeout, get_current_ip(client, url)).await {
+        Ok(Ok(text)) => IpAddr::from_str(text.trim())
+            .map_err(|e| format!("{} answered {:?}: {}", url, text.trim(), e)),
+        Ok(Err(e)) => Err(format!("{}: {:#}", url, e)),
+        Err(_) => Err(format!("{}: no answer within {:?}", url, timeout)),
+    }
+}
+
+/// Asks one of `sources` for the address, unless one was asked less than
+/// `min_interval` ago, so no service is asked more often than that. With
+/// several services configured, each detection starts at the next one in
+/// turn and moves on to the following ones while a service fails or times
+/// out. Callers arriving while a service is being asked wait for that answer
+/// rather than sending their own request.
+async fn ask(
+    client: reqwest::Client,
+    family: Option<IpFamily>,
+    sources: Vec<String>,
+    local: Option<IpAddr>,
+) -> Result<IpAddr> {
+    let slot = {
+        let mut detections = DETECTIONS.get_or_init(Default::default).lock().unwrap();
+        Arc::clone(detections.entry(local).or_default())
+    };
+    let mut detection = slot.lock().await;
+    if let Some(answer) = detection.answer.as_ref() {
+        if answer.asked.elapsed() < Duration::from_secs(settings().min_interval) {
+            return answer.result.clone().map_err(|e| DetectionFailed(e).into());
+        }
+    }
+    let start = detection.next % sources.len();
+    if settings().rotate {
+        detection.next = (start + 1) % sources.len();
+    }
+    let asked = Instant::now();
+    let mut failures = Vec::new();
+    let mut answered = match local {
+        None => ask_registered(family, &mut failures).await,
+        Some(_) => None,
+    };
+    for url in sources[start..].iter().chain(&sources[..start]) {
+        if answered.is_some() {
+            break;
+        }
+        match query(&client, url).await {
+            Ok(ip) => {
+                match local {
+                    None => log::info!("{} from {}", ip, url),
+                    Some(local) => log::info!("{} from {} (via {})", ip, url, local),
+                }
+                answered = Some(ip);
+                break;
+            }
+            Err(e) => {
+                if sources.len() > 1 {
+                    log::warn!("IP source {}", e);
+                }
+                failures.push(e);
+            }
+        }
+    }
+    let result = answered.ok_or_else(|| failures.join("; "));
+    detection.answer = Some(Answer {
+        asked,
+        result: result.clone(),
+    });
+    result.map_err(|e| DetectionFailed(e).into())
+}
+
+/// The client for the configured family and the source used when none are
+/// configured. With a family set, requests are bound to it so a dual-stack
+/// host reports the address of that family.
+fn family_client() -> Result<(reqwest::Client, &'static str)> {
+    let bound = |unspecified| match http::settings().bind_address {
+        // The configured address already decides the family.
+        Some(_) => Ok(http::client()),
+        None => http::bound_client(unspecified),
+    };
+    Ok(match FAMILY.get() {
+        None => (http::client(), "https://api.ipify.org"),
+        Some(IpFamily::V4) => (
+            bound(IpAddr::V4(Ipv4Addr::UNSPECIFIED))?,
+            "https://api4.ipify.org",
+        ),
+        Some(IpFamily::V6) => (
+            bound(IpAddr::V6(Ipv6Addr::UNSPECIFIED))?,
+            "https://api6.ipify.org",
+        ),
+    })
+}
+
+/// Asks the echo service for the public address this host is seen from.
+/// While traffic leaves through a VPN, `on_vpn` decides whether to go
+/// ahead anyway, give up with [`VpnActive`], or ask through another interface.
+pub async fn detect() -> Result<IpAddr> {
+    let (mut client, default) = family_client()?;
+    let family = FAMILY.get().copied();
+    let settings = settings();
+    // Once warned, ignoring the VPN needs no more route lookups.
+    let warned = settings.on_vpn == OnVpn::Ignore && VPN_WARNED.load(Ordering::Relaxed);
+    let vpn = if warned {
+        None
+    } else {
+        vpn::active(family == Some(IpFamily::V6), &settings.vpn_interfaces).await
+    };
+    if let Some(vpn) = vpn {
+        match &settings.on_vpn {
+            OnVpn::Ignore => {
+                if !VPN_WARNED.swap(true, Ordering::Relaxed) {
+                    log::warn!(
+                        "Traffic leaves through VPN interface {}, so the detected IP may be the VPN's; set [ip] on_vpn to skip or detect through another interface",
+                        vpn
+                    );
+                }
+            }
+            OnVpn::Skip => return Err(VpnActive(vpn).into()),
+            OnVpn::Interface(interface) => {
+                let local = match family {
+                    None => None,
+                    Some(IpFamily::V4) => Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+                    Some(IpFamily::V6) => Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+                };
+                log::debug!("{} is up, detecting through {}", vpn, interface);
+                client = http::interface_client(interface, local)?;
+            }
+        }
+    }
+    ask(client, family, sources(family, default), None).await
+}
+
+/// Asks every source right away, bypassing `min_interval` and the rotation,
+/// to see what each one answers.
+pub async fn probe() -> Result<Vec<(String, Result<IpAddr, String>)>> {
+    let (client, default) = family_client()?;
+    let mut answers = Vec::new();
+    for url in sources(FAMILY.get().copied(), default) {
+        let answer = query(&client, &url).await;
+        answers.push((url, answer));
+    }
+    Ok(answers)
+}
+
+/// Like [`detect`], but leaving through the link that owns `local`, to learn
+/// each WAN link's public address on a multi-homed host.
+pub async fn detect_via(local: IpAddr) -> Result<IpAddr> {
+    let (family, default) = match local {
+        IpAddr::V4(_) => (IpFamily::V4, "https://api4.ipify.org"),
+        IpAddr::V6(_) => (IpFamily::V6, "https://api6.ipify.org"),
+    };
+    ask(
+        http::bound_client(local)?,
+        Some(family),
+        sources(Some(family), default),
+        Some(local),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    /// Echo services at `/<ip>` answering with that address, or with an
+    /// error page at `/broken`.
+    async fn echo() -> MockServer {
+        let server = MockServer::start().await;
+        for ip in ["203.0.113.1", "203.0.113.2"] {
+            Mock::given(method("GET"))
+                .and(path(format!("/{}", ip)))
+                .respond_with(ResponseTemplate::new(200).set_body_string(format!("{}\n", ip)))
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("GET"))
+            .and(path("/broken"))
+            .respond_with(ResponseTemplate::new(502).set_body_string("Bad Gateway"))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn urls(server: &MockServer, paths: &[&str]) -> Vec<String> {
+        paths
+            .iter()
+            .map(|path| format!("{}/{}", server.uri(), path))
+            .collect()
+    }
+
+    /// Each test detects through a local address of its own, which keys the
+    /// cached answer and keeps the registered sources out.
+    async fn detect(local: &str, sources: Vec<String>) -> Result<IpAddr> {
+        ask(
+            reqwest::Client::new(),
+            None,
+            sources,
+            Some(local.parse().unwrap()),
+        )
+        .await
+    }
+
+    /// Drops the cached answer for `local`, as if `min_interval` had passed.
+    async fn expire(local: &str) {
+        let local: IpAddr = local.parse().unwrap();
+        let slot = {
+            let detections = DETECTIONS.get().unwrap().lock().unwrap();
+            Arc::clone(&detections[&Some(local)])
+        };
+        slot.lock().await.answer = None;
+    }
+
+    #[tokio::test]
+    async fn rotates_through_the_sources() {
+        let server = echo().await;
+        let sources = urls(&server, &["203.0.113.1", "203.0.113.2"]);
+        let local = "127.0.0.11";
+        let mut answers = Vec::new();
+        for _ in 0..3 {
+            answers.push(detect(local, sources.clone()).await.unwrap().to_string());
+            expire(local).await;
+        }
+        assert_eq!(answers, ["203.0.113.1", "203.0.113.2", "203.0.113.1"]);
+    }
+
+    #[tokio::test]
+    async fn moves_on_from_a_failing_source() {
+        let server = echo().await;
+        let sources = urls(&server, &["broken", "203.0.113.2"]);
+        let ip = detect("127.0.0.12", sources).await.unwrap();
+        assert_eq!(ip.to_string(), "203.0.113.2");
+    }
+
+    #[tokio::test]
+    async fn fails_when_no_source_answers() {
+        let server = echo().await;
+        let error = detect("127.0.0.13", urls(&server, &["broken"]))
+            .await
+            .unwrap_err();
+        assert!(is_detection_failure(&error));
+        assert!(error.to_string().contains("Bad Gateway"));
+    }
+
+    #[tokio::test]
+    async fn reuses_an_answer_within_min_interval() {
+        let server = echo().await;
+        let sources = urls(&server, &["203.0.113.1", "203.0.113.2"]);
+        let local = "127.0.0.14";
+        let first = detect(local, sources.clone()).await.unwrap();
+        let second = detect(local, sources).await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,291 @@
+//! Keeps DNS records pointed at this host's public address.
+//!
+//! The `cfbind` binary is a thin command line over this crate. Embedding it
+//! lets another program reuse the updaters, providers and listeners, and add
+//! its own ways of learning the address with [`ip::register`]:
+//!
+//! ```no_run
+//! use std::net::IpAddr;
+//! use std::path::Path;
+//! use std::sync::Arc;
+//!
+//! use async_trait::async_trait;
+//! use cfbind::config::{Config, IpFamily};
+//! use cfbind::ip::{self, IpSource};
+//!
+//! struct Metadata;
+//!
+//! #[async_trait]
+//! impl IpSource for Metadata {
+//!     fn name(&self) -> String {
+//!         "instance metadata".to_string()
+//!     }
+//!
+//!     async fn detect(&self, _family: Option<IpFamily>) -> anyhow::Result<IpAddr> {
+//!         Ok("203.0.113.7".parse()?)
+//!     }
+//! }
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let config = Config::load(Path::new("/etc/cfbind/config.toml"))?;
+//! ip::register(Arc::new(Metadata));
+//! cfbind::init(&config)?;
+//! cfbind::run(config).await
+//! # }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use tokio::task::JoinSet;
+
+use crate::config::Config;
+use crate::dyndns_server::Target;
+use crate::provider::{Providers, RecordContent};
+use crate::rules::Rules;
+#[cfg(unix)]
+use crate::state::watch_signals;
+use crate::state::State;
+use crate::updater::{Group, Updater};
+use crate::window::Windows;
+
+pub mod api;
+pub mod cgnat;
+pub mod commands;
+pub mod config;
+#[cfg(windows)]
+pub mod control_pipe;
+pub mod control_socket;
+pub mod controller;
+pub mod diff;
+pub mod dyndns_server;
+pub mod encrypted;
+pub mod error;
+pub mod exit;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod http;
+pub mod import;
+pub mod ip;
+pub mod metrics;
+pub mod mirror;
+pub mod output;
+pub mod plugin;
+pub mod provider;
+pub mod reporting;
+pub mod route;
+pub mod rules;
+pub mod secret;
+pub mod state;
+pub mod store;
+pub mod template;
+pub mod tui;
+pub mod updater;
+pub mod vault;
+pub mod verify;
+pub mod version;
+pub mod vpn;
+pub mod window;
+
+/// How long a failed tenant waits before it is started again.
+const TENANT_RETRY: Duration = Duration::from_secs(300);
+
+/// Applies the process-wide settings of `config`: the HTTP client, IP
+/// detection and the IP source plugins. Call it once, before anything else.
+pub fn init(config: &Config) -> Result<()> {
+    http::init(&config.http)?;
+    ip::init(&config.ip);
+    if let Some(family) = config.ip_family {
+        ip::set_family(family);
+    }
+    for source in &config.plugins.ip_sources {
+        ip::register(Arc::new(plugin::Plugin::new(source)));
+    }
+    Ok(())
+}
+
+/// Spawns the updaters and any configured listeners.
+pub async fn start(config: &Config, state: &Arc<State>) -> Result<JoinSet<Result<()>>> {
+    if config.domains.is_empty()
+        && config.dyndns_server.is_none()
+        && config.controller.is_none()
+        && config.tenants.is_empty()
+    {
+        bail!("No domains configured, pass --domain or list them in the config file");
+    }
+    let interval = Duration::from_secs(config.interval.unwrap_or(config::DEFAULT_INTERVAL));
+    let mut providers = Providers::default();
+    let limit = config.sync_limit();
+    let rules = Rules::from_config(config)?;
+    let mut tasks = JoinSet::new();
+    let mut grouped = HashSet::new();
+    for zone in &config.zones {
+        let provider = providers.get(zone.provider, config).await?;
+        let updaters = zone
+            .domains()
+            .into_iter()
+            .map(|domain| {
+                grouped.insert(domain.name.clone());
+                let windows = Windows::new(config.windows_for(&domain))?;
+                Ok(Updater::new(
+                    Arc::clone(&provider),
+                    domain.name,
+                    domain.proxied,
+                    interval,
+                    Arc::clone(state),
+                )
+                .dry_run(config.dry_run)
+                .ttl(config.ttl)
+                .comment(config.comment.clone())
+                .verify(config.verify.clone())
+                .rules(rules.clone())
+                .windows(windows)
+                .limit(Arc::clone(&limit)))
+            })
+            .collect::<Result<_>>()?;
+        let group = Group::new(
+            zone.name.clone(),
+            provider,
+            updaters,
+            interval,
+            Arc::clone(state),
+        );
+        tasks.spawn(group.run());
+    }
+    for domain in config.domains.iter().filter(|d| !grouped.contains(&d.name)) {
+        let provider = providers.get(domain.provider, config).await?;
+        let updater = Updater::new(
+            provider,
+            domain.name.clone(),
+            domain.proxied,
+            interval,
+            Arc::clone(state),
+        )
+        .dry_run(config.dry_run)
+        .ttl(config.ttl)
+        .comment(config.comment.clone())
+        .verify(config.verify.clone())
+        .rules(rules.clone())
+        .windows(Windows::new(config.windows_for(domain))?)
+        .limit(Arc::clone(&limit))
+        .content(domain.content.as_deref().map(RecordContent::infer))
+        .via(domain.via.clone());
+        tasks.spawn(updater.run());
+    }
+    if let Some(server) = &config.dyndns_server {
+        let mut targets = HashMap::new();
+        for host in &server.hosts {
+            let target = Target {
+                provider: providers.get(host.provider, config).await?,
+                proxied: host.proxied,
+                ttl: config.ttl,
+                comment: config.comment.clone(),
+            };
+            targets.insert(host.name.clone(), target);
+        }
+        tasks.spawn(dyndns_server::serve(
+            server.clone(),
+            targets,
+            Arc::clone(state),
+        ));
+    }
+    if let Some(controller) = &config.controller {
+        let mut agents = Vec::new();
+        for agent in &controller.agents {
+            let mut targets = HashMap::new();
+            for host in &agent.hosts {
+                let target = Target {
+                    provider: providers.get(host.provider, config).await?,
+                    proxied: host.proxied,
+                    ttl: config.ttl,
+                    comment: config.comment.clone(),
+                };
+                targets.insert(host.name.clone(), target);
+            }
+            agents.push(controller::Agent {
+                name: agent.name.clone(),
+                token: agent.token.clone(),
+                targets,
+            });
+        }
+        tasks.spawn(controller::serve(
+            controller.clone(),
+            agents,
+            Arc::clone(&limit),
+            config.dry_run,
+            Arc::clone(state),
+        ));
+    }
+    if let Some(api) = &config.api {
+        tasks.spawn(api::serve(api.clone(), Arc::clone(state)));
+    }
+    if let Some(control) = &config.control {
+        #[cfg(unix)]
+        tasks.spawn(control_socket::serve(control.clone(), Arc::clone(state)));
+        #[cfg(windows)]
+        tasks.spawn(control_pipe::serve(control.clone(), Arc::clone(state)));
+    }
+    if let Some(grpc) = &config.grpc {
+        #[cfg(feature = "grpc")]
+        tasks.spawn(grpc::serve(grpc.clone(), Arc::clone(state)));
+        #[cfg(not(feature = "grpc"))]
+        bail!(
+            "[grpc] is configured at {}, but this build lacks the grpc feature",
+            grpc.listen
+        );
+    }
+    #[cfg(unix)]
+    tasks.spawn(watch_signals(Arc::clone(state)));
+    if state.is_paused() {
+        log::warn!("Updates are still paused from an earlier run; `cfbind resume` resumes them");
+    }
+    let cloudflare = config.cloudflare.as_ref();
+    if cloudflare.is_some_and(|c| c.api_key_file.is_some() || c.vault.is_some()) {
+        tasks.spawn(provider::watch_credentials(
+            providers.all(),
+            Arc::clone(state),
+        ));
+    }
+    Ok(tasks)
+}
+
+/// Runs the updaters and listeners until one of them fails. Tenants run on
+/// their own, so a failing tenant stops neither the rest nor the top level.
+pub async fn run(config: Config) -> Result<()> {
+    let state = State::open(&config)?;
+    let mut tasks = start(&config, &state).await?;
+    for tenant in &config.tenants {
+        tasks.spawn(run_tenant(tenant.name.clone(), config.for_tenant(tenant)));
+    }
+    while let Some(result) = tasks.join_next().await {
+        result??;
+    }
+    Ok(())
+}
+
+/// Runs one tenant's updaters, starting them over after [`TENANT_RETRY`]
+/// whenever one fails, e.g. on credentials that stopped working.
+async fn run_tenant(name: String, config: Config) -> Result<()> {
+    loop {
+        let result = async {
+            let state = State::open(&config)?;
+            let mut tasks = start(&config, &state).await?;
+            while let Some(result) = tasks.join_next().await {
+                result??;
+            }
+            anyhow::Ok(())
+        }
+        .await;
+        if let Err(e) = result {
+            log::error!(
+                "Tenant {} stopped, restarting it in {}s: {:#}",
+                name,
+                TENANT_RETRY.as_secs(),
+                e
+            );
+        }
+        tokio::time::sleep(TENANT_RETRY).await;
+    }
+}
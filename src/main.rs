@@ -1,201 +1,719 @@
-use core::option::Option;
-use std::collections::HashMap;
-use std::net::Ipv4Addr;
-use std::str::FromStr;
-use std::sync::Arc;
-
-use anyhow::{anyhow, Context};
-use anyhow::{Error, Result};
-use clap::{arg, Parser};
-use cloudflare::endpoints::dns::{DnsContent, DnsRecord, Meta};
-use cloudflare::endpoints::zone::Zone;
-use cloudflare::framework::{async_api, Environment, HttpApiClientConfig};
-use cloudflare::framework::auth::Credentials;
-use cloudflare::framework::response::{ApiResponse, ApiSuccess};
-use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, HeaderValue};
-use serde::{Deserialize, Serialize};
-use tokio::task::JoinHandle;
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+
+use cfbind::commands::history::ExportFormat;
+use cfbind::config::{CloudflareEnvironment, Config, DomainConfig, IpFamily, ProviderKind, Ttl};
+use cfbind::output::{EventFormat, Format};
+use cfbind::secret::Secret;
+use cfbind::state::State;
+use cfbind::window::Windows;
+use cfbind::{commands, config, exit, import, provider, reporting, secret, template, tui, version};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    #[arg(
-        short,
-        long = "domain, domain name to be bound to the local device ip address"
-    )]
-    domain: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to a TOML config file listing domains and provider credentials
+    /// [default: ~/.config/cfbind/config.toml when present]
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Domain name to be bound to the local device ip address
+    #[arg(short, long, global = true)]
+    domain: Option<String>,
+
+    /// Keep <this machine's host name>.<this domain> in sync, like --domain without naming the host
+    #[arg(long, global = true, value_name = "DOMAIN", conflicts_with = "domain")]
+    auto_subdomain: Option<String>,
+
+    /// Act on this tenant of the config file instead of its top-level domains
+    #[arg(long, global = true, conflicts_with_all = ["domain", "auto_subdomain"])]
+    tenant: Option<String>,
 
-    #[arg(long = "disable-proxy, disable Cloudflare proxy")]
+    /// Disable Cloudflare proxy
+    #[arg(long, global = true)]
     disable_proxy: bool,
 
-    #[arg(
-        short,
-        long = "api-key, Cloudflare API Key with Edit Zones Permissions, can also be set as an environment variable CF_API_KEY"
-    )]
+    /// Also keep www.<domain> in sync with --domain
+    #[arg(long, global = true)]
+    with_www: bool,
+
+    /// Publish this for --domain instead of the detected IP: an address, a host name (CNAME) or text (TXT)
+    #[arg(long, global = true, requires = "domain")]
+    content: Option<String>,
+
+    /// Write this existing Cloudflare record for --domain instead of searching for it by name
+    #[arg(long, global = true, requires = "domain")]
+    record_id: Option<String>,
+
+    /// Zone of --record-id, so not even the zones need to be listed
+    #[arg(long, global = true, requires = "record_id")]
+    zone_id: Option<String>,
+
+    /// Cloudflare API Key with Edit Zones Permissions
+    #[arg(short, long, env = "CF_API_KEY", global = true)]
     api_key: Option<String>,
+
+    /// Send Cloudflare API requests here instead, e.g. an API gateway or emulator
+    #[arg(long, env = "CFBIND_API_BASE_URL", global = true)]
+    api_base_url: Option<CloudflareEnvironment>,
+
+    /// Send the echo request and API calls from this local address, to pick the uplink
+    #[arg(long, env = "CFBIND_BIND_ADDRESS", global = true)]
+    bind_address: Option<IpAddr>,
+
+    /// Send the echo request and API calls through this network interface (Linux only)
+    #[arg(long, env = "CFBIND_BIND_INTERFACE", global = true)]
+    bind_interface: Option<String>,
+
+    /// Detect and publish the IPv4 address only
+    #[arg(short = '4', long = "ipv4", global = true, conflicts_with = "ipv6")]
+    ipv4: bool,
+
+    /// Detect and publish the IPv6 address only
+    #[arg(short = '6', long = "ipv6", global = true)]
+    ipv6: bool,
+
+    /// TTL of written records: auto, or 60-86400 seconds (120 and up on free plans)
+    #[arg(long, global = true)]
+    ttl: Option<Ttl>,
+
+    /// Report what would change without writing any records
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Stream each detection, skip, update, verification and error on stdout
+    #[arg(long, value_enum, global = true)]
+    events: Option<EventFormat>,
+
+    /// Run as a container: settings from CFBIND_* variables, JSON logs on stdout
+    #[arg(long, env = "CFBIND_CONTAINER", global = true)]
+    container: bool,
+
+    /// Touch this file after each cycle in which every domain synced, for HEALTHCHECK
+    #[arg(long, env = "CFBIND_HEALTHCHECK_FILE", global = true)]
+    healthcheck_file: Option<PathBuf>,
+
+    /// Never look for new cfbind releases
+    #[arg(long, env = "CFBIND_NO_UPDATE_CHECK", global = true)]
+    no_update_check: bool,
+
+    /// Log more; with --dry-run, also print a diff of each pending change
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Output format for command results
+    #[arg(short, long, value_enum, default_value_t, global = true)]
+    output: Format,
+
+    /// Shorthand for --output json
+    #[arg(long, global = true, hide = true)]
+    json: bool,
 }
 
-pub async fn get_zones(api_client: &async_api::Client) -> anyhow::Result<HashMap<String, Zone>> {
-    let result: ApiResponse<Vec<Zone>> = api_client
-        .request(&cloudflare::endpoints::zone::ListZones {
-            params: Default::default(),
-        })
-        .await;
-    match result {
-        Ok(apiResp) => {
-            let zones = apiResp.result;
-            let mut zone_map = HashMap::new();
-            for zone in zones {
-                zone_map.insert(zone.name.clone(), zone);
-            }
-            Ok(zone_map)
-        }
-        Err(e) => {
-            log::error!("Error: {:#?}", e);
-            Err(anyhow::anyhow!("Error: {:#?}", e))
-        }
-    }
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Keep the configured records in sync (the default)
+    Run,
+    /// Create a config file interactively: API token, zone, hostname, proxying and interval
+    Init {
+        /// Overwrite an existing file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Run the updaters with a live terminal view of each domain
+    Tui,
+    /// Show the detected IP and the current state of each record without changing anything
+    Status,
+    /// Check that each domain resolves to the detected IP; exits non-zero on mismatch
+    Check,
+    /// Sync every domain once and exit; exits 2 when a record was changed
+    Update,
+    /// Stop the running instance from updating records, through its control socket or API, until resumed
+    Pause,
+    /// Let the running instance update records again and sync right away
+    Resume,
+    /// Inspect the zones visible to the Cloudflare API token
+    Zones {
+        #[command(subcommand)]
+        command: ZonesCommand,
+    },
+    /// Inspect DNS records in a Cloudflare zone
+    Records {
+        #[command(subcommand)]
+        command: RecordsCommand,
+    },
+    /// Convert another dynamic DNS client's configuration into a cfbind config file
+    Import {
+        #[command(subcommand)]
+        source: ImportSource,
+    },
+    /// Manage the cfbind config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Show recorded IP changes and DNS writes; --domain limits the writes shown
+    History {
+        #[command(subcommand)]
+        command: Option<HistoryCommand>,
+        /// Only show entries this recent: 30m, 12h, 7d, 2w, or a date/RFC 3339 time
+        #[arg(long, global = true)]
+        since: Option<String>,
+    },
+    /// Remove the A/AAAA records managed for a domain
+    Delete {
+        /// Record name, e.g. home.example.com; picked from the Cloudflare records when omitted
+        name: Option<String>,
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Turn Cloudflare's proxy on or off for a record without changing its content
+    Proxy {
+        state: ProxyState,
+        /// Record name, e.g. home.example.com; picked from the Cloudflare records when omitted
+        name: Option<String>,
+    },
+    /// Create and remove ACME DNS-01 challenge records, as the DNS hook of a certificate client
+    Acme {
+        #[command(subcommand)]
+        command: AcmeCommand,
+    },
+    /// Detect the public IP and report it to a cfbind controller, which writes the records
+    Agent {
+        /// The controller's address, e.g. https://ddns.example.com:8443
+        #[arg(long, env = "CFBIND_CONTROLLER")]
+        controller: String,
+        /// This agent's token from the controller's config
+        #[arg(long, env = "CFBIND_AGENT_TOKEN")]
+        token: String,
+        /// Report once and exit instead of every interval
+        #[arg(long)]
+        once: bool,
+    },
+    /// Collect a redacted diagnostics bundle to attach to bug reports
+    DebugDump {
+        /// Where to write the bundle, defaults to cfbind-debug-<time>.json
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Print a shell completion script, e.g. `cfbind completions bash > /etc/bash_completion.d/cfbind`
+    Completions { shell: Shell },
+    /// Generate man pages for packaging
+    #[command(hide = true)]
+    Mangen {
+        /// Write one page per subcommand into this directory instead of printing cfbind.1
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+    },
 }
 
-pub fn root_domain_name(name: String) -> String {
-    let nc = name.clone();
-    let parts = nc.split('.').collect::<Vec<&str>>();
-    if parts.len() <= 2 {
-        nc
-    } else {
-        parts[parts.len() - 2..].join(".")
-    }
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ProxyState {
+    On,
+    Off,
 }
 
-pub async fn get_zone(api_client: &async_api::Client, name: &str) -> anyhow::Result<Zone> {
-    let mut zones = get_zones(api_client).await?;
-    let root_domain = root_domain_name(name.to_string());
-    zones.remove(&root_domain).context("Zone not found")
+#[derive(Subcommand, Debug)]
+enum ZonesCommand {
+    /// List zone names, IDs, status and plan
+    List,
 }
 
-pub async fn get_dns_record(
-    api_client: &async_api::Client,
-    name: &str,
-) -> anyhow::Result<Option<DnsRecord>> {
-    let zone = get_zone(api_client, name).await?;
-    let mut response: ApiSuccess<Vec<DnsRecord>> = api_client
-        .request(&cloudflare::endpoints::dns::ListDnsRecords {
-            zone_identifier: zone.id.as_str(),
-            params: cloudflare::endpoints::dns::ListDnsRecordsParams {
-                name: Some(name.to_string()),
-                ..Default::default()
-            },
-        })
-        .await?;
-    Ok(response.result.into_iter().next())
+#[derive(Subcommand, Debug)]
+enum RecordsCommand {
+    /// List record IDs, types, content, proxied flag and TTL
+    List {
+        /// Zone to list, e.g. example.com; asked for when omitted on a terminal
+        #[arg(long)]
+        zone: Option<String>,
+        /// Only show records with this exact name
+        #[arg(long)]
+        name: Option<String>,
+        /// Only show records of this type (A, AAAA, CNAME, ...)
+        #[arg(long = "type")]
+        record_type: Option<String>,
+    },
 }
 
-pub async fn update_dns_record(
-    api_client: &async_api::Client,
-    name: &str,
-    dns_content: DnsContent,
-    proxied: bool,
-) -> anyhow::Result<()> {
-    let dns_record: Option<DnsRecord> = get_dns_record(api_client, name).await?;
-    let zone = get_zone(api_client, name).await?;
-    log::info!("DNS Record: {:#?}", dns_record);
-    let result = match dns_record {
-        Some(record) => {
-            api_client
-                .request(&cloudflare::endpoints::dns::UpdateDnsRecord {
-                    zone_identifier: record.zone_id.as_str(),
-                    identifier: record.id.as_str(),
-                    params: cloudflare::endpoints::dns::UpdateDnsRecordParams {
-                        ttl: Some(1),
-                        proxied: Some(proxied),
-                        name,
-                        content: dns_content,
-                    },
-                })
-                .await
+#[derive(Subcommand, Debug)]
+enum HistoryCommand {
+    /// Dump the history as one row per IP change or write, for analysis
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Check the config file and report every problem with its line; exits 6 if there are any
+    Validate {
+        /// Skip looking each domain up with the configured credentials
+        #[arg(long)]
+        offline: bool,
+    },
+    /// Write a commented example config, pre-filled from --domain/--api-key/--disable-proxy
+    Init {
+        /// Overwrite an existing file
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum AcmeCommand {
+    /// Add the _acme-challenge TXT record of a domain and wait until its nameservers serve it
+    #[command(visible_alias = "present")]
+    SetTxt {
+        /// The domain being validated, e.g. example.com or *.example.com
+        #[arg(env = "CERTBOT_DOMAIN")]
+        domain: String,
+        /// The challenge's key authorization digest
+        #[arg(env = "CERTBOT_VALIDATION")]
+        value: String,
+        /// Seconds to wait for the nameservers
+        #[arg(long, default_value_t = 120)]
+        timeout: u64,
+    },
+    /// Remove the _acme-challenge TXT records of a domain, or only the one holding the value
+    #[command(visible_alias = "cleanup")]
+    ClearTxt {
+        #[arg(env = "CERTBOT_DOMAIN")]
+        domain: String,
+        #[arg(env = "CERTBOT_VALIDATION")]
+        value: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ImportSource {
+    /// Import the Cloudflare hosts of a ddclient.conf
+    Ddclient {
+        #[arg(default_value = "/etc/ddclient.conf")]
+        path: PathBuf,
+    },
+    /// Import favonia/cloudflare-ddns settings from an env file or the current environment
+    Favonia { env_file: Option<PathBuf> },
+    /// Import a timothymiller/cloudflare-ddns config.json
+    Timothymiller {
+        #[arg(default_value = "config.json")]
+        path: PathBuf,
+    },
+}
+
+/// Reads the config and applies the command line to it, failing with
+/// [`config::Invalid`] so the error exits with the config status.
+fn load_config(args: &Args) -> Result<Config> {
+    assemble_config(args).map_err(|e| config::Invalid(e).into())
+}
+
+fn assemble_config(args: &Args) -> Result<Config> {
+    let mut config = match &args.config {
+        Some(path) => Config::load(path)?,
+        // Containers are configured from the environment alone.
+        None if args.container => Config::default(),
+        None => match config::default_path().filter(|p| p.exists()) {
+            Some(path) => Config::load(&path)?,
+            None => Config::default(),
+        },
+    };
+    if let Some(api_key) = &args.api_key {
+        config
+            .cloudflare
+            .get_or_insert_with(Default::default)
+            .api_key = Some(Secret::new(api_key.as_str()));
+    }
+    if let Some(api_base_url) = &args.api_base_url {
+        config
+            .cloudflare
+            .get_or_insert_with(Default::default)
+            .environment = Some(api_base_url.clone());
+    }
+    config.dry_run |= args.dry_run;
+    if args.events.is_some() {
+        config.events = args.events;
+    }
+    if args.ttl.is_some() {
+        config.ttl = args.ttl;
+    }
+    if args.bind_address.is_some() {
+        config.http.bind_address = args.bind_address;
+    }
+    if args.bind_interface.is_some() {
+        config.http.bind_interface = args.bind_interface.clone();
+    }
+    if args.ipv4 {
+        config.ip_family = Some(IpFamily::V4);
+    } else if args.ipv6 {
+        config.ip_family = Some(IpFamily::V6);
+    }
+    if args.healthcheck_file.is_some() {
+        config.healthcheck_file = args.healthcheck_file.clone();
+    }
+    // The host name is filled in with the other templated names below.
+    let auto = args
+        .auto_subdomain
+        .as_ref()
+        .map(|zone| format!("{{{{hostname}}}}.{}", zone.trim_start_matches('.')));
+    if let Some(domain) = args.domain.as_ref().or(auto.as_ref()) {
+        config.domains.push(DomainConfig {
+            name: domain.clone(),
+            provider: ProviderKind::Cloudflare,
+            proxied: !args.disable_proxy,
+            via: Vec::new(),
+            aliases: if args.with_www {
+                vec!["www".to_string()]
+            } else {
+                Vec::new()
+            },
+            content: args.content.clone(),
+            record_id: args.record_id.clone(),
+            zone_id: args.zone_id.clone(),
+            windows: Vec::new(),
+        });
+    }
+    if args.container {
+        config.apply_env()?;
+    }
+    arrange(&mut config)?;
+    // Tenants are arranged the same way, against the settings they inherit.
+    let mut names = HashSet::new();
+    for mut tenant in std::mem::take(&mut config.tenants) {
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if tenant.name.is_empty() || !tenant.name.chars().all(valid) {
+            bail!(
+                "Invalid tenant name {:?}, use letters, digits, - and _",
+                tenant.name
+            );
         }
-        None => {
-            api_client
-                .request(&cloudflare::endpoints::dns::CreateDnsRecord {
-                    zone_identifier: zone.id.as_str(),
-                    params: cloudflare::endpoints::dns::CreateDnsRecordParams {
-                        name,
-                        content: dns_content,
-                        proxied: Some(proxied),
-                        ttl: Some(1),
-                        priority: None,
-                    },
-                })
-                .await
+        if !names.insert(tenant.name.clone()) {
+            bail!("Two tenants are named {}", tenant.name);
         }
-    };
-    match result {
-        Ok(apiResp) => {
-            log::info!("DNS Record Updated: {:#?}", apiResp.result);
-            Ok(())
+        if !tenant.plugins.ip_sources.is_empty() {
+            bail!(
+                "tenant {}: IP source plugins are shared, list them at the top level",
+                tenant.name
+            );
         }
-        Err(e) => {
-            log::error!("Error: {:#?}", e);
-            Err(anyhow::anyhow!("Error: {:#?}", e))
+        let mut own = config.for_tenant(&tenant);
+        arrange(&mut own).with_context(|| format!("tenant {}", tenant.name))?;
+        if own.domains.is_empty() {
+            bail!("tenant {}: no domains configured", tenant.name);
+        }
+        tenant.domains = own.domains;
+        tenant.zones = own.zones;
+        tenant.mirrors = own.mirrors;
+        config.tenants.push(tenant);
+    }
+    let hosts = config.dyndns_server.iter().flat_map(|s| &s.hosts);
+    let agent_hosts = config
+        .controller
+        .iter()
+        .flat_map(|c| &c.agents)
+        .flat_map(|a| &a.hosts);
+    for domain in hosts.chain(agent_hosts) {
+        provider::validate_name(&domain.name)?;
+    }
+    let mirrors = config.tenants.iter().flat_map(|t| &t.mirrors);
+    for name in config.mirrors.iter().chain(mirrors).flat_map(|m| &m.names) {
+        provider::validate_name(name)?;
+    }
+    if let Some(comment) = &config.comment {
+        template::check_comment(comment)?;
+    }
+    let plugins = config
+        .tenants
+        .iter()
+        .map(|t| &t.plugins)
+        .chain([&config.plugins]);
+    for plugins in plugins {
+        let notifiers = plugins.notifiers.iter().map(|n| &n.plugin);
+        let mut plugins = plugins
+            .provider
+            .iter()
+            .chain(&plugins.ip_sources)
+            .chain(notifiers);
+        if plugins.any(|p| p.command.is_empty()) {
+            bail!("A plugin's command is empty");
         }
     }
+    if let Some(name) = &args.tenant {
+        let Some(tenant) = config.tenants.iter().find(|t| &t.name == name) else {
+            bail!("No tenant named {}", name);
+        };
+        config = config.for_tenant(tenant);
+    }
+    Ok(config)
 }
 
-async fn get_current_ip() -> Result<String> {
-    let response = reqwest::get("https://api.ipify.org").await?.text().await?;
-    Ok(response)
+/// Puts the domains of `config` into the shape every command expects, and
+/// checks them.
+fn arrange(config: &mut Config) -> Result<()> {
+    // Names like {{hostname}}.dyn.example.com are claimed by this machine.
+    for domain in &mut config.domains {
+        domain.name = template::expand(&domain.name)?;
+    }
+    // A domain with aliases is synced as one unit, the same way as a zone's hosts.
+    let (aliased, plain) = config
+        .domains
+        .drain(..)
+        .partition::<Vec<_>, _>(|d| !d.aliases.is_empty());
+    config.domains = plain;
+    if let Some(domain) = config
+        .domains
+        .iter()
+        .chain(&aliased)
+        .find(|d| d.content.is_some() && (!d.via.is_empty() || !d.aliases.is_empty()))
+    {
+        bail!(
+            "{}: content can't be combined with via or aliases",
+            domain.name
+        );
+    }
+    for domain in config.domains.iter().chain(&aliased) {
+        domain.check_record_id()?;
+    }
+    for domain in aliased {
+        if !domain.via.is_empty() {
+            bail!("{}: aliases can't be combined with via", domain.name);
+        }
+        config.zones.push(domain.into_zone());
+    }
+    for zone in &mut config.zones {
+        zone.name = template::expand(&zone.name)?;
+        for host in &mut zone.hosts {
+            *host = template::expand(host)?;
+        }
+    }
+    for name in config.mirrors.iter_mut().flat_map(|m| &mut m.names) {
+        *name = template::expand(name)?;
+    }
+    // Zone hosts are listed as domains too, so every command sees them.
+    let fanned: Vec<DomainConfig> = config.zones.iter().flat_map(|z| z.domains()).collect();
+    config.domains.extend(fanned);
+    for domain in &config.domains {
+        provider::validate_name(&domain.name)?;
+        Windows::check(config.windows_for(domain))
+            .with_context(|| format!("{}: invalid windows", domain.name))?;
+    }
+    Ok(())
 }
 
-fn create_updater(
-    api_key: Arc<String>,
-    domain: Arc<String>,
-    disable_proxy: Arc<bool>,
-) -> JoinHandle<Result<()>> {
-    let creds = Credentials::UserAuthToken {
-        token: api_key.to_string(),
+async fn dispatch(args: Args) -> Result<ExitCode> {
+    let format = if args.json { Format::Json } else { args.output };
+    // Commands that must work without a usable config.
+    let config_path = || match &args.config {
+        Some(path) => Ok(path.clone()),
+        None => config::default_path().context("Could not determine the config directory"),
     };
-    let cf_api_client = async_api::Client::new(
-        creds,
-        HttpApiClientConfig::default(),
-        Environment::Production,
-    );
-
-    match cf_api_client {
-        Ok(client) => {
-            tokio::spawn(async move {
-                loop {
-                    let current_ip = get_current_ip().await.unwrap();
-                    log::info!("{}", current_ip);
-                    // parse string as ip
-                    let record = DnsContent::A {
-                        content: Ipv4Addr::from_str(current_ip.as_str())?,
-                    };
-                    update_dns_record(
-                        &client,
-                        domain.as_str(),
-                        record,
-                        disable_proxy.as_ref().clone(),
-                    )
-                        .await
-                        .unwrap();
-                    tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+    match &args.command {
+        Some(Command::Init { force }) => {
+            commands::init::run(
+                &config_path()?,
+                args.api_key.as_deref(),
+                !args.disable_proxy,
+                *force,
+            )
+            .await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Config {
+            command: ConfigCommand::Init { force },
+        }) => {
+            let path = config_path()?;
+            commands::config::init(
+                &path,
+                args.domain.as_deref(),
+                args.api_key.as_deref(),
+                !args.disable_proxy,
+                *force,
+            )?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Config {
+            command: ConfigCommand::Validate { offline },
+        }) => {
+            let path = config_path()?;
+            return commands::config::validate(&path, args.api_key.as_deref(), *offline, format)
+                .await;
+        }
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(*shell, &mut Args::command(), "cfbind", &mut io::stdout());
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Mangen { out_dir }) => {
+            commands::mangen::run(Args::command(), out_dir.as_deref())?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        _ => {}
+    }
+    let config = load_config(&args)?;
+    cfbind::init(&config).map_err(config::Invalid)?;
+    let _sentry = config.sentry.as_ref().map(reporting::init);
+    if !args.no_update_check && version::enabled(config.update_check) {
+        tokio::spawn(version::watch());
+    }
+    match args.command.unwrap_or(Command::Run) {
+        Command::Run => cfbind::run(config).await?,
+        Command::Tui => {
+            let state = State::open(&config)?;
+            let _tasks = cfbind::start(&config, &state).await?;
+            let domains = config.domains.iter().map(|d| d.name.clone()).collect();
+            tokio::task::spawn_blocking(move || tui::run(state, domains)).await??
+        }
+        Command::Status => commands::status::run(&config, format).await?,
+        Command::Check => return commands::check::run(&config, format).await,
+        Command::Update => return commands::update::run(&config, format).await,
+        Command::Pause => commands::control::set_paused(&config, true, format).await?,
+        Command::Resume => commands::control::set_paused(&config, false, format).await?,
+        Command::Zones {
+            command: ZonesCommand::List,
+        } => commands::zones::list(&config, format).await?,
+        Command::Records {
+            command:
+                RecordsCommand::List {
+                    zone,
+                    name,
+                    record_type,
+                },
+        } => {
+            commands::records::list(
+                &config,
+                zone.as_deref(),
+                name.as_deref(),
+                record_type.as_deref(),
+                format,
+            )
+            .await?
+        }
+        Command::History {
+            command: None,
+            since,
+        } => commands::history::show(&config, since.as_deref(), args.domain.as_deref(), format)?,
+        Command::History {
+            command: Some(HistoryCommand::Export { format }),
+            since,
+        } => commands::history::export(&config, since.as_deref(), args.domain.as_deref(), format)?,
+        Command::Delete { name, yes } => {
+            commands::delete::run(&config, name.as_deref(), yes, format).await?
+        }
+        Command::Acme {
+            command:
+                AcmeCommand::SetTxt {
+                    domain,
+                    value,
+                    timeout,
+                },
+        } => {
+            let timeout = Duration::from_secs(timeout);
+            commands::acme::set_txt(&config, &domain, &value, timeout, format).await?
+        }
+        Command::Acme {
+            command: AcmeCommand::ClearTxt { domain, value },
+        } => commands::acme::clear_txt(&config, &domain, value.as_deref(), format).await?,
+        Command::Agent {
+            controller,
+            token,
+            once,
+        } => {
+            return commands::agent::run(&config, &controller, Secret::new(token), once, format)
+                .await
+        }
+        Command::DebugDump { out } => commands::debug_dump::run(&config, out, format).await?,
+        Command::Proxy { state, name } => {
+            let proxied = matches!(state, ProxyState::On);
+            commands::proxy::run(&config, name.as_deref(), proxied, format).await?
+        }
+        Command::Import { source } => {
+            let (imported, origin) = match source {
+                ImportSource::Ddclient { path } => {
+                    (import::ddclient::import(&path)?, path.display().to_string())
                 }
-            })
+                ImportSource::Favonia { env_file } => (
+                    import::favonia::import(env_file.as_deref())?,
+                    env_file.map_or("the environment".to_string(), |p| p.display().to_string()),
+                ),
+                ImportSource::Timothymiller { path } => (
+                    import::timothymiller::import(&path).await?,
+                    path.display().to_string(),
+                ),
+            };
+            print!("{}", import::render(&imported, &origin)?);
+        }
+        Command::Init { .. }
+        | Command::Config { .. }
+        | Command::Completions { .. }
+        | Command::Mangen { .. } => {
+            unreachable!("handled before loading the config")
         }
-        Err(e) => tokio::spawn(async move { Err(anyhow!(e)) }),
     }
+    Ok(ExitCode::SUCCESS)
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     let args = Args::parse();
-    let domain = Arc::new(args.domain);
-    let api_key = Arc::new(
-        args.api_key
-            .unwrap_or_else(|| std::env::var("CF_API_KEY").unwrap()),
-    );
-    let disable_proxy = Arc::new(args.disable_proxy);
-    let updater: JoinHandle<Result<()>> = create_updater(api_key, domain, disable_proxy);
-    tokio::try_join!(updater)?;
-    Ok(())
+    let level = match args.verbose {
+        0 => "warn",
+        1 => "info",
+        _ => "debug",
+    };
+    let mut logger =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(level));
+    if args.container {
+        // One JSON object per line on stdout, for the container log driver.
+        logger
+            .target(env_logger::Target::Stdout)
+            .format(|buf, record| {
+                let line = serde_json::json!({
+                    "timestamp": chrono::Utc::now().to_rfc3339(),
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "message": secret::scrub(&record.args().to_string()),
+                });
+                writeln!(buf, "{}", line)
+            });
+    } else {
+        // env_logger's own layout, with credentials scrubbed from the message.
+        logger.format(|buf, record| {
+            let style = buf.default_level_style(record.level());
+            writeln!(
+                buf,
+                "[{} {style}{:<5}{style:#} {}] {}",
+                buf.timestamp(),
+                record.level(),
+                record.target(),
+                secret::scrub(&record.args().to_string())
+            )
+        });
+    }
+    if matches!(args.command, Some(Command::Tui)) {
+        logger
+            .target(env_logger::Target::Pipe(Box::new(tui::LogWriter)))
+            .write_style(env_logger::WriteStyle::Never);
+    }
+    logger.init();
+    // Panic messages can quote whatever value was at hand; print them scrubbed.
+    std::panic::set_hook(Box::new(|info| {
+        eprintln!("{}", secret::scrub(&info.to_string()));
+    }));
+    match dispatch(args).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {}", secret::scrub(&format!("{:?}", e)));
+            exit::classify(&e).into()
+        }
+    }
 }
@@ -0,0 +1,258 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+
+use crate::config::MetricsConfig;
+use crate::http;
+use crate::state::{Failure, Status};
+
+const DEFAULT_JOB: &str = "cfbind";
+
+/// Calls to one provider API endpoint since startup.
+#[derive(Default)]
+struct ApiCalls {
+    count: u64,
+    errors: u64,
+    seconds: f64,
+}
+
+static API_CALLS: Mutex<BTreeMap<&'static str, ApiCalls>> = Mutex::new(BTreeMap::new());
+
+/// Counts one provider API call towards the latency and error metrics of
+/// `endpoint`, e.g. `list_zones`.
+pub fn observe_api(endpoint: &'static str, duration: Duration, ok: bool) {
+    let mut calls = API_CALLS.lock().unwrap();
+    let calls = calls.entry(endpoint).or_default();
+    calls.count += 1;
+    calls.seconds += duration.as_secs_f64();
+    if !ok {
+        calls.errors += 1;
+    }
+}
+
+/// Appends the latency and errors of every API endpoint called so far, to
+/// tell a slow or failing provider from local trouble.
+fn render_api(out: &mut String) {
+    let calls = API_CALLS.lock().unwrap();
+    if calls.is_empty() {
+        return;
+    }
+    let _ = writeln!(
+        out,
+        "# HELP cfbind_api_request_duration_seconds Time spent in provider API calls."
+    );
+    let _ = writeln!(out, "# TYPE cfbind_api_request_duration_seconds summary");
+    for (endpoint, calls) in calls.iter() {
+        let _ = writeln!(
+            out,
+            "cfbind_api_request_duration_seconds_sum{{endpoint={}}} {}",
+            label(endpoint),
+            calls.seconds
+        );
+        let _ = writeln!(
+            out,
+            "cfbind_api_request_duration_seconds_count{{endpoint={}}} {}",
+            label(endpoint),
+            calls.count
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP cfbind_api_request_errors_total Provider API calls that failed."
+    );
+    let _ = writeln!(out, "# TYPE cfbind_api_request_errors_total counter");
+    for (endpoint, calls) in calls.iter() {
+        let _ = writeln!(
+            out,
+            "cfbind_api_request_errors_total{{endpoint={}}} {}",
+            label(endpoint),
+            calls.errors
+        );
+    }
+}
+
+/// Quotes a label value as the exposition format requires.
+fn label(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+fn seconds(time: DateTime<Utc>) -> i64 {
+    time.timestamp()
+}
+
+/// Renders `status` in the Prometheus text exposition format.
+pub fn render(status: &Status) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# HELP cfbind_paused Whether updates are paused.");
+    let _ = writeln!(out, "# TYPE cfbind_paused gauge");
+    let _ = writeln!(out, "cfbind_paused {}", status.paused as u8);
+    if let Some(ip) = status.current_ip {
+        let _ = writeln!(out, "# HELP cfbind_ip_info The detected public IP address.");
+        let _ = writeln!(out, "# TYPE cfbind_ip_info gauge");
+        let _ = writeln!(out, "cfbind_ip_info{{ip={}}} 1", label(&ip.to_string()));
+    }
+    if let Some(ok) = status.detection_ok {
+        let _ = writeln!(
+            out,
+            "# HELP cfbind_ip_detection_ok Whether the last IP detection succeeded."
+        );
+        let _ = writeln!(out, "# TYPE cfbind_ip_detection_ok gauge");
+        let _ = writeln!(out, "cfbind_ip_detection_ok {}", ok as u8);
+    }
+    let _ = writeln!(
+        out,
+        "# HELP cfbind_failures_total Failed syncs, by whether detecting the IP or the provider failed."
+    );
+    let _ = writeln!(out, "# TYPE cfbind_failures_total counter");
+    for kind in [Failure::Detection, Failure::Provider] {
+        let count = status.failures.get(&kind).copied().unwrap_or(0);
+        let _ = writeln!(
+            out,
+            "cfbind_failures_total{{kind={}}} {}",
+            label(&kind.to_string()),
+            count
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP cfbind_domain_ok Whether the last sync of the domain succeeded."
+    );
+    let _ = writeln!(out, "# TYPE cfbind_domain_ok gauge");
+    for (name, domain) in &status.domains {
+        let ok = domain.last_error.is_none();
+        let _ = writeln!(
+            out,
+            "cfbind_domain_ok{{domain={}}} {}",
+            label(name),
+            ok as u8
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP cfbind_domain_last_check_timestamp_seconds When the domain was last checked."
+    );
+    let _ = writeln!(
+        out,
+        "# TYPE cfbind_domain_last_check_timestamp_seconds gauge"
+    );
+    for (name, domain) in &status.domains {
+        if let Some(time) = domain.last_check {
+            let _ = writeln!(
+                out,
+                "cfbind_domain_last_check_timestamp_seconds{{domain={}}} {}",
+                label(name),
+                seconds(time)
+            );
+        }
+    }
+    let _ = writeln!(
+        out,
+        "# HELP cfbind_domain_last_update_timestamp_seconds When the domain's record was last written."
+    );
+    let _ = writeln!(
+        out,
+        "# TYPE cfbind_domain_last_update_timestamp_seconds gauge"
+    );
+    for (name, domain) in &status.domains {
+        if let Some(time) = domain.last_update {
+            let _ = writeln!(
+                out,
+                "cfbind_domain_last_update_timestamp_seconds{{domain={}}} {}",
+                label(name),
+                seconds(time)
+            );
+        }
+    }
+    let _ = writeln!(
+        out,
+        "# HELP cfbind_record_verified Whether the domain's last write was confirmed resolvable."
+    );
+    let _ = writeln!(out, "# TYPE cfbind_record_verified gauge");
+    for (name, domain) in &status.domains {
+        if let Some(verified) = domain.verified {
+            let _ = writeln!(
+                out,
+                "cfbind_record_verified{{domain={}}} {}",
+                label(name),
+                verified as u8
+            );
+        }
+    }
+    render_api(&mut out);
+    out
+}
+
+/// Writes the metrics for node_exporter's textfile collector. The file is
+/// replaced by a rename so the collector never reads half of it.
+pub fn write_textfile(path: &Path, status: &Status) -> Result<()> {
+    let tmp = path.with_extension("prom.tmp");
+    std::fs::write(&tmp, render(status))
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+/// The summary of one `cfbind update` run.
+pub fn render_run(success: bool, duration: Duration, changed: usize) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "# HELP cfbind_run_success Whether every domain synced."
+    );
+    let _ = writeln!(out, "# TYPE cfbind_run_success gauge");
+    let _ = writeln!(out, "cfbind_run_success {}", success as u8);
+    let _ = writeln!(
+        out,
+        "# HELP cfbind_run_duration_seconds How long the run took."
+    );
+    let _ = writeln!(out, "# TYPE cfbind_run_duration_seconds gauge");
+    let _ = writeln!(
+        out,
+        "cfbind_run_duration_seconds {}",
+        duration.as_secs_f64()
+    );
+    let _ = writeln!(out, "# HELP cfbind_run_changed Records written by the run.");
+    let _ = writeln!(out, "# TYPE cfbind_run_changed gauge");
+    let _ = writeln!(out, "cfbind_run_changed {}", changed);
+    let _ = writeln!(
+        out,
+        "# HELP cfbind_run_timestamp_seconds When the run finished."
+    );
+    let _ = writeln!(out, "# TYPE cfbind_run_timestamp_seconds gauge");
+    let _ = writeln!(
+        out,
+        "cfbind_run_timestamp_seconds {}",
+        Utc::now().timestamp()
+    );
+    render_api(&mut out);
+    out
+}
+
+/// Replaces the job's metrics on the Pushgateway with `body`, so a short-lived
+/// run still leaves its result behind for Prometheus to scrape.
+pub async fn push(config: &MetricsConfig, body: String) -> Result<()> {
+    let Some(url) = &config.pushgateway else {
+        return Ok(());
+    };
+    let job = config.job.as_deref().unwrap_or(DEFAULT_JOB);
+    let url = format!("{}/metrics/job/{}", url.trim_end_matches('/'), job);
+    http::client()
+        .put(&url)
+        .header("Content-Type", "text/plain; version=0.0.4")
+        .body(body)
+        .send()
+        .await
+        .with_context(|| format!("Failed to push metrics to {}", url))?
+        .error_for_status()?;
+    Ok(())
+}
@@ -0,0 +1,316 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use url::Url;
+
+use crate::config::{Config, MirrorConfig, MirrorTarget};
+use crate::http;
+use crate::route;
+use crate::secret::Secret;
+
+/// The internal address of each family a name should resolve to, keyed by
+/// whether it is IPv4.
+type Addresses = BTreeMap<bool, IpAddr>;
+
+struct Mirror {
+    config: MirrorConfig,
+    names: HashSet<String>,
+    /// What each name was last set to, so unchanged syncs cost nothing.
+    applied: tokio::sync::Mutex<HashMap<String, Addresses>>,
+}
+
+/// Every configured internal resolver.
+pub struct Mirrors {
+    mirrors: Vec<Arc<Mirror>>,
+    running: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Mirrors {
+    /// The `[[mirrors]]` of `config`, or `None` when there are none.
+    pub fn new(config: &Config) -> Option<Arc<Mirrors>> {
+        if config.mirrors.is_empty() {
+            return None;
+        }
+        let domains: HashSet<String> = config.domains.iter().map(|d| d.name.clone()).collect();
+        let mirrors = config
+            .mirrors
+            .iter()
+            .map(|mirror| {
+                let names = if mirror.names.is_empty() {
+                    domains.clone()
+                } else {
+                    mirror.names.iter().cloned().collect()
+                };
+                Arc::new(Mirror {
+                    config: mirror.clone(),
+                    names,
+                    applied: Default::default(),
+                })
+            })
+            .collect();
+        Some(Arc::new(Mirrors {
+            mirrors,
+            running: Mutex::new(Vec::new()),
+        }))
+    }
+
+    /// Points `name` at the internal address of each family in `content`,
+    /// the comma-separated public addresses it was just synced to, in the
+    /// background. Anything that isn't an address is left alone.
+    pub fn sync(&self, name: &str, content: &str) {
+        let families: HashSet<bool> = content
+            .split(", ")
+            .filter_map(|part| part.parse::<IpAddr>().ok())
+            .map(|ip| ip.is_ipv4())
+            .collect();
+        if families.is_empty() {
+            return;
+        }
+        let mut running = self.running.lock().unwrap();
+        running.retain(|task| !task.is_finished());
+        for mirror in &self.mirrors {
+            if !mirror.names.contains(name) {
+                continue;
+            }
+            let mirror = Arc::clone(mirror);
+            let name = name.to_string();
+            let families = families.clone();
+            running.push(tokio::spawn(async move {
+                if let Err(e) = mirror.sync(&name, &families).await {
+                    log::warn!("Failed to mirror {} into {}: {:#}", name, mirror, e);
+                }
+            }));
+        }
+    }
+
+    /// Waits for the mirror writes still running, before a one-shot command exits.
+    pub async fn settle(&self) {
+        let running = std::mem::take(&mut *self.running.lock().unwrap());
+        for task in running {
+            let _ = task.await;
+        }
+    }
+}
+
+impl std::fmt::Display for Mirror {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.config.target {
+            MirrorTarget::Pihole { url, .. } => write!(f, "Pi-hole at {}", url),
+            MirrorTarget::Dnsmasq { hosts_file, .. } => {
+                write!(f, "dnsmasq hosts file {}", hosts_file.display())
+            }
+            MirrorTarget::Unbound { .. } => write!(f, "unbound"),
+        }
+    }
+}
+
+impl Mirror {
+    /// The configured address when it is of the family, else the address
+    /// this host sends to the internet from.
+    async fn address(&self, v4: bool) -> Option<IpAddr> {
+        if let Some(address) = self.config.address.filter(|a| a.is_ipv4() == v4) {
+            return Some(address);
+        }
+        let target = if v4 {
+            route::INTERNET_V4
+        } else {
+            route::INTERNET_V6
+        };
+        route::get(target).await?.source
+    }
+
+    async fn sync(&self, name: &str, families: &HashSet<bool>) -> Result<()> {
+        let mut wanted = Addresses::new();
+        for &v4 in families {
+            if let Some(address) = self.address(v4).await {
+                wanted.insert(v4, address);
+            }
+        }
+        if wanted.is_empty() {
+            bail!("no LAN address found, set address");
+        }
+        // Held throughout, so concurrent syncs of one mirror can't interleave.
+        let mut applied = self.applied.lock().await;
+        let previous = applied.get(name).cloned().unwrap_or_default();
+        if previous == wanted {
+            return Ok(());
+        }
+        let mut all = applied.clone();
+        all.insert(name.to_string(), wanted.clone());
+        match &self.config.target {
+            MirrorTarget::Pihole { url, password } => pihole(url, password, name, &wanted).await?,
+            MirrorTarget::Dnsmasq { hosts_file, reload } => {
+                dnsmasq(hosts_file, reload, &all).await?
+            }
+            MirrorTarget::Unbound { control } => unbound(control, name, &wanted).await?,
+        }
+        let addresses: Vec<String> = wanted.values().map(|ip| ip.to_string()).collect();
+        log::info!("{} -> {} in {}", name, addresses.join(", "), self);
+        *applied = all;
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct PiholeAuth {
+    session: PiholeSession,
+}
+
+#[derive(Deserialize)]
+struct PiholeSession {
+    sid: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PiholeHosts {
+    config: PiholeConfig,
+}
+
+#[derive(Deserialize)]
+struct PiholeConfig {
+    dns: PiholeDns,
+}
+
+#[derive(Deserialize)]
+struct PiholeDns {
+    hosts: Vec<String>,
+}
+
+/// `base` with `segments` appended, each percent-encoded.
+fn pihole_url(base: &str, segments: &[&str]) -> Result<Url> {
+    let mut url = Url::parse(base).with_context(|| format!("Invalid Pi-hole URL {}", base))?;
+    url.path_segments_mut()
+        .map_err(|_| anyhow::anyhow!("Invalid Pi-hole URL {}", base))?
+        .pop_if_empty()
+        .extend(segments);
+    Ok(url)
+}
+
+/// Replaces `name`'s local DNS records, `"<ip> <name>"` entries in Pi-hole's
+/// `dns.hosts`, logging in for just this change.
+async fn pihole(base: &str, password: &Secret, name: &str, wanted: &Addresses) -> Result<()> {
+    let client = http::client();
+    let auth: PiholeAuth = client
+        .post(pihole_url(base, &["api", "auth"])?)
+        .json(&serde_json::json!({ "password": password.expose() }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let sid = auth.session.sid.context("Pi-hole rejected the password")?;
+    let hosts_url = |entry: Option<&str>| {
+        let mut segments = vec!["api", "config", "dns", "hosts"];
+        segments.extend(entry);
+        pihole_url(base, &segments)
+    };
+
+    let result = async {
+        let current: PiholeHosts = client
+            .get(hosts_url(None)?)
+            .header("X-FTL-SID", &sid)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let entries: Vec<String> = wanted
+            .values()
+            .map(|ip| format!("{} {}", ip, name))
+            .collect();
+        for entry in &current.config.dns.hosts {
+            let mut words = entry.split_whitespace();
+            let (Some(_), Some(host)) = (words.next(), words.next()) else {
+                continue;
+            };
+            if host == name && !entries.contains(entry) {
+                client
+                    .delete(hosts_url(Some(entry))?)
+                    .header("X-FTL-SID", &sid)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+        }
+        for entry in entries
+            .iter()
+            .filter(|e| !current.config.dns.hosts.contains(e))
+        {
+            client
+                .put(hosts_url(Some(entry))?)
+                .header("X-FTL-SID", &sid)
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+        Ok::<_, anyhow::Error>(())
+    }
+    .await;
+
+    // Pi-hole only allows a few sessions at a time.
+    let logout = client
+        .delete(pihole_url(base, &["api", "auth"])?)
+        .header("X-FTL-SID", &sid)
+        .send()
+        .await;
+    if let Err(e) = logout {
+        log::debug!("Failed to log out of Pi-hole: {:#}", e);
+    }
+    result
+}
+
+/// Runs `command` through the shell, failing with its output when it does.
+async fn shell(command: &str) -> Result<()> {
+    let output = Command::new("sh")
+        .args(["-c", command])
+        .output()
+        .await
+        .with_context(|| format!("Failed to run {}", command))?;
+    if !output.status.success() {
+        bail!(
+            "{} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Rewrites the hosts file with every mirrored name and has dnsmasq reread it.
+async fn dnsmasq(hosts_file: &Path, reload: &str, all: &HashMap<String, Addresses>) -> Result<()> {
+    let mut names: Vec<_> = all.iter().collect();
+    names.sort_by_key(|(name, _)| name.as_str());
+    let mut contents = String::from("# Written by cfbind, changes are overwritten\n");
+    for (name, addresses) in names {
+        for ip in addresses.values() {
+            contents.push_str(&format!("{} {}\n", ip, name));
+        }
+    }
+    // Written aside and renamed, so dnsmasq never reads half a file.
+    let partial = hosts_file.with_extension("partial");
+    std::fs::write(&partial, contents)
+        .with_context(|| format!("Failed to write {}", partial.display()))?;
+    std::fs::rename(&partial, hosts_file)
+        .with_context(|| format!("Failed to write {}", hosts_file.display()))?;
+    shell(reload).await
+}
+
+/// Replaces `name`'s local data in a running unbound.
+async fn unbound(control: &str, name: &str, wanted: &Addresses) -> Result<()> {
+    shell(&format!("{} local_data_remove '{}'", control, name)).await?;
+    for ip in wanted.values() {
+        let record_type = if ip.is_ipv4() { "A" } else { "AAAA" };
+        shell(&format!(
+            "{} local_data '{} IN {} {}'",
+            control, name, record_type, ip
+        ))
+        .await?;
+    }
+    Ok(())
+}
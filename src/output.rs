@@ -0,0 +1,37 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// How commands print their results. The JSON/YAML shapes are the serialized
+/// report structs of each command and are kept stable across releases.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+    Yaml,
+}
+
+/// How `run` streams its lifecycle events on stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventFormat {
+    /// One JSON object per line.
+    Ndjson,
+}
+
+impl Format {
+    pub fn is_text(self) -> bool {
+        self == Format::Text
+    }
+}
+
+/// Prints `value` in a machine-readable format; text output is left to the caller.
+pub fn print<T: Serialize>(format: Format, value: &T) -> Result<()> {
+    match format {
+        Format::Text => {}
+        Format::Json => println!("{}", serde_json::to_string_pretty(value)?),
+        Format::Yaml => print!("{}", serde_yaml::to_string(value)?),
+    }
+    Ok(())
+}
@@ -0,0 +1,405 @@
+use std::net::IpAddr;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+
+use crate::config::{Config, IpFamily, NotifierConfig, PluginConfig};
+use crate::error::CfbindError;
+use crate::ip::IpSource;
+use crate::provider::{DnsProvider, Record, RecordContent, RecordSpec, RecordType};
+use crate::state::{Event, EventKind};
+
+/// Version of the request format, sent with every call so plugins can
+/// refuse one they don't understand.
+const PROTOCOL: u32 = 1;
+
+/// Notified about when a notifier lists no events.
+const DEFAULT_EVENTS: [EventKind; 5] = [
+    EventKind::Updated,
+    EventKind::RolledBack,
+    EventKind::Error,
+    EventKind::Flapping,
+    EventKind::VerifyFailed,
+];
+
+#[derive(Serialize)]
+struct Request<'a, P> {
+    protocol: u32,
+    method: &'a str,
+    settings: &'a serde_json::Map<String, serde_json::Value>,
+    params: P,
+}
+
+/// A plugin answers with `{"result": ...}` or `{"error": {...}}`.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Reply<T> {
+    Result(T),
+    Error(PluginError),
+}
+
+#[derive(Deserialize)]
+struct PluginError {
+    message: String,
+    /// `auth`, `not_found`, `conflict`, `rate_limited`, `network` or
+    /// `unsupported`; anything else is a plain failure.
+    #[serde(default)]
+    kind: Option<String>,
+}
+
+/// A record the way plugins see it, with its type and content as strings.
+#[derive(Debug, Serialize, Deserialize)]
+struct WireRecord {
+    #[serde(default)]
+    id: String,
+    name: String,
+    #[serde(rename = "type")]
+    record_type: String,
+    content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
+    #[serde(default)]
+    proxied: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+}
+
+fn record_type(value: &str) -> Result<RecordType> {
+    Ok(match value.to_ascii_uppercase().as_str() {
+        "A" => RecordType::A,
+        "AAAA" => RecordType::Aaaa,
+        "CNAME" => RecordType::Cname,
+        "TXT" => RecordType::Txt,
+        _ => bail!("Unsupported record type {:?}", value),
+    })
+}
+
+impl From<&Record> for WireRecord {
+    fn from(record: &Record) -> Self {
+        WireRecord {
+            id: record.id.clone(),
+            name: record.name.clone(),
+            record_type: record.content.record_type().to_string(),
+            content: record.content.to_string(),
+            ttl: Some(record.ttl),
+            proxied: record.proxied,
+            comment: None,
+        }
+    }
+}
+
+impl From<&RecordSpec> for WireRecord {
+    fn from(spec: &RecordSpec) -> Self {
+        WireRecord {
+            id: String::new(),
+            name: spec.name.clone(),
+            record_type: spec.content.record_type().to_string(),
+            content: spec.content.to_string(),
+            ttl: spec.ttl,
+            proxied: spec.proxied,
+            comment: spec.comment.clone(),
+        }
+    }
+}
+
+impl WireRecord {
+    fn into_record(self, default_ttl: Option<u32>) -> Result<Record> {
+        let record_type = record_type(&self.record_type)?;
+        Ok(Record {
+            id: self.id,
+            name: self.name,
+            content: RecordContent::parse(record_type, &self.content)?,
+            ttl: self.ttl.or(default_ttl).unwrap_or(1),
+            proxied: self.proxied,
+        })
+    }
+}
+
+/// A program cfbind runs once per call: it gets one JSON request on stdin
+/// and answers with one JSON reply on stdout. Whatever it writes to stderr
+/// is logged at debug level.
+pub struct Plugin {
+    config: PluginConfig,
+}
+
+impl Plugin {
+    pub fn new(config: &PluginConfig) -> Self {
+        Plugin {
+            config: config.clone(),
+        }
+    }
+
+    fn program(&self) -> &str {
+        self.config.command.first().map_or("", String::as_str)
+    }
+
+    /// Runs `method`, failing when the plugin does.
+    async fn call<P: Serialize, T: DeserializeOwned>(&self, method: &str, params: P) -> Result<T> {
+        match self.try_call(method, params).await? {
+            Reply::Result(result) => Ok(result),
+            Reply::Error(error) => Err(self.error(method, error)),
+        }
+    }
+
+    /// Runs `method`, with `None` when the plugin doesn't implement it.
+    async fn call_optional<P: Serialize, T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: P,
+    ) -> Result<Option<T>> {
+        match self.try_call(method, params).await? {
+            Reply::Result(result) => Ok(Some(result)),
+            Reply::Error(error) if error.kind.as_deref() == Some("unsupported") => Ok(None),
+            Reply::Error(error) => Err(self.error(method, error)),
+        }
+    }
+
+    async fn try_call<P: Serialize, T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: P,
+    ) -> Result<Reply<T>> {
+        let request = serde_json::to_vec(&Request {
+            protocol: PROTOCOL,
+            method,
+            settings: &self.config.settings,
+            params,
+        })?;
+        let mut child = Command::new(self.program())
+            .args(self.config.command.iter().skip(1))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to run plugin {}", self.program()))?;
+        let mut stdin = child.stdin.take().context("Plugin has no stdin")?;
+        stdin.write_all(&request).await?;
+        stdin.write_all(b"\n").await?;
+        drop(stdin);
+        let output = tokio::time::timeout(
+            Duration::from_secs(self.config.timeout),
+            child.wait_with_output(),
+        )
+        .await
+        .map_err(|_| {
+            anyhow!(
+                "Plugin {} did not answer {} within {}s",
+                self.program(),
+                method,
+                self.config.timeout
+            )
+        })??;
+        for line in String::from_utf8_lossy(&output.stderr).lines() {
+            log::debug!("{}: {}", self.program(), line);
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if stdout.trim().is_empty() {
+            bail!(
+                "Plugin {} answered {} with nothing ({})",
+                self.program(),
+                method,
+                output.status
+            );
+        }
+        serde_json::from_str(stdout.trim()).map_err(|e| {
+            CfbindError::Parse(anyhow!(
+                "Plugin {} answered {} with an invalid reply: {}",
+                self.program(),
+                method,
+                e
+            ))
+            .into()
+        })
+    }
+
+    fn error(&self, method: &str, error: PluginError) -> anyhow::Error {
+        let message = anyhow!("{} {}: {}", self.program(), method, error.message);
+        match error.kind.as_deref() {
+            Some("auth") => CfbindError::Auth(message),
+            Some("not_found") => CfbindError::RecordNotFound(message),
+            Some("conflict") => CfbindError::Conflict(message),
+            Some("rate_limited") => CfbindError::RateLimited(message),
+            Some("network") => CfbindError::Network(message),
+            _ => CfbindError::Api(message),
+        }
+        .into()
+    }
+}
+
+#[async_trait]
+impl IpSource for Plugin {
+    fn name(&self) -> String {
+        format!("plugin {}", self.program())
+    }
+
+    async fn detect(&self, family: Option<IpFamily>) -> Result<IpAddr> {
+        self.call("detect", json!({ "family": family })).await
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Plugin {
+    async fn get_record(&self, name: &str, record_type: RecordType) -> Result<Option<Record>> {
+        let record: Option<WireRecord> = self
+            .call(
+                "get_record",
+                json!({ "name": name, "type": record_type.to_string() }),
+            )
+            .await?;
+        record.map(|r| r.into_record(None)).transpose()
+    }
+
+    async fn upsert_record(&self, spec: &RecordSpec) -> Result<Record> {
+        let record: WireRecord = self
+            .call("upsert_record", json!({ "spec": WireRecord::from(spec) }))
+            .await?;
+        record.into_record(spec.ttl)
+    }
+
+    async fn delete_record(&self, record: &Record) -> Result<()> {
+        let _: serde_json::Value = self
+            .call(
+                "delete_record",
+                json!({ "record": WireRecord::from(record) }),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn list_records(&self, name: &str, record_type: RecordType) -> Result<Vec<Record>> {
+        let records: Option<Vec<WireRecord>> = self
+            .call_optional(
+                "list_records",
+                json!({ "name": name, "type": record_type.to_string() }),
+            )
+            .await?;
+        match records {
+            Some(records) => records.into_iter().map(|r| r.into_record(None)).collect(),
+            None => Ok(self
+                .get_record(name, record_type)
+                .await?
+                .into_iter()
+                .collect()),
+        }
+    }
+
+    async fn create_record(&self, spec: &RecordSpec) -> Result<Record> {
+        let record: Option<WireRecord> = self
+            .call_optional("create_record", json!({ "spec": WireRecord::from(spec) }))
+            .await?;
+        match record {
+            Some(record) => record.into_record(spec.ttl),
+            None => bail!(
+                "Plugin {} can't keep several records for {}",
+                self.program(),
+                spec.name
+            ),
+        }
+    }
+
+    async fn update_record(&self, record: &Record, spec: &RecordSpec) -> Result<Record> {
+        let written: Option<WireRecord> = self
+            .call_optional(
+                "update_record",
+                json!({ "record": WireRecord::from(record), "spec": WireRecord::from(spec) }),
+            )
+            .await?;
+        match written {
+            Some(written) => written.into_record(spec.ttl),
+            None => bail!(
+                "Plugin {} can't keep several records for {}",
+                self.program(),
+                spec.name
+            ),
+        }
+    }
+
+    async fn nameservers(&self, name: &str) -> Result<Vec<String>> {
+        Ok(self
+            .call_optional("nameservers", json!({ "name": name }))
+            .await?
+            .unwrap_or_default())
+    }
+}
+
+struct Notifier {
+    plugin: Plugin,
+    events: Vec<EventKind>,
+}
+
+/// Every configured notifier plugin.
+pub struct Notifiers {
+    notifiers: Vec<Arc<Notifier>>,
+    running: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Notifiers {
+    /// The `[[plugins.notifiers]]` of `config`, or `None` when there are none.
+    pub fn new(config: &Config) -> Option<Arc<Notifiers>> {
+        if config.plugins.notifiers.is_empty() {
+            return None;
+        }
+        let notifiers = config
+            .plugins
+            .notifiers
+            .iter()
+            .map(|NotifierConfig { plugin, events }| {
+                let events = if events.is_empty() {
+                    DEFAULT_EVENTS.to_vec()
+                } else {
+                    events.clone()
+                };
+                Arc::new(Notifier {
+                    plugin: Plugin::new(plugin),
+                    events,
+                })
+            })
+            .collect();
+        Some(Arc::new(Notifiers {
+            notifiers,
+            running: Mutex::new(Vec::new()),
+        }))
+    }
+
+    /// Hands `event` to each notifier wanting it, in the background.
+    pub fn send(&self, event: &Event) {
+        // Events raised outside the runtime, e.g. while exiting, are dropped.
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let mut running = self.running.lock().unwrap();
+        running.retain(|task| !task.is_finished());
+        for notifier in &self.notifiers {
+            if !notifier.events.contains(&event.kind) {
+                continue;
+            }
+            let notifier = Arc::clone(notifier);
+            let event = event.clone();
+            running.push(runtime.spawn(async move {
+                let plugin = &notifier.plugin;
+                let result: Result<serde_json::Value> = plugin.call("notify", &event).await;
+                if let Err(e) = result {
+                    log::warn!("Failed to notify plugin {}: {:#}", plugin.program(), e);
+                }
+            }));
+        }
+    }
+
+    /// Waits for the notifications still running, before a one-shot command exits.
+    pub async fn settle(&self) {
+        let running = std::mem::take(&mut *self.running.lock().unwrap());
+        for task in running {
+            let _ = task.await;
+        }
+    }
+}
@@ -0,0 +1,1381 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use ::cloudflare::endpoints::dns::{DnsContent, DnsRecord};
+use ::cloudflare::endpoints::zone::Zone;
+use ::cloudflare::framework::response::{ApiErrors, ApiFailure};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use futures::future::join_all;
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use super::{DnsProvider, Record, RecordContent, RecordSpec, RecordType};
+use crate::config::{CloudflareConfig, CloudflareEnvironment, Config, VaultConfig};
+use crate::error::CfbindError;
+use crate::http;
+use crate::metrics;
+use crate::secret::Secret;
+use crate::vault;
+
+/// Where the production API is served.
+const API_BASE: &str = "https://api.cloudflare.com/client/v4/";
+
+/// Default seconds between re-reads of a token kept in Vault.
+const VAULT_REFRESH_EVERY: u64 = 60 * 60;
+
+/// Default seconds the zone index is reused before it is listed again.
+const ZONE_REFRESH_EVERY: u64 = 60 * 60;
+
+const ZONES_PER_PAGE: u32 = 50;
+
+/// Shortest explicit TTL Cloudflare accepts on free, paid and Enterprise zones.
+const FREE_PLAN_MIN_TTL: u32 = 120;
+const PAID_PLAN_MIN_TTL: u32 = 60;
+const ENTERPRISE_PLAN_MIN_TTL: u32 = 30;
+
+/// The calls the provider makes, one per API request, so tests can stand in
+/// for the API. [`Session`] makes them over HTTP.
+#[async_trait]
+pub trait CloudflareApi: Send + Sync {
+    /// Sends `body` with `method` to `path` below the API base and returns
+    /// the response body, or the failure its error codes describe.
+    async fn call(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, String)],
+        body: Option<serde_json::Value>,
+    ) -> Result<serde_json::Value, CfbindError>;
+}
+
+/// Awaits one API call, timing it for the metrics of `endpoint`.
+async fn timed<T, E>(
+    endpoint: &'static str,
+    call: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let started = Instant::now();
+    let result = call.await;
+    metrics::observe_api(endpoint, started.elapsed(), result.is_ok());
+    result
+}
+
+/// Makes one call, timing it for the metrics of `endpoint`.
+async fn request<T: DeserializeOwned>(
+    api: &dyn CloudflareApi,
+    endpoint: &'static str,
+    method: Method,
+    path: &str,
+    query: &[(&str, String)],
+    body: Option<serde_json::Value>,
+) -> Result<Envelope<T>, CfbindError> {
+    let call = async {
+        let response = api.call(method, path, query, body).await?;
+        serde_json::from_value(response).map_err(|e| CfbindError::Parse(e.into()))
+    };
+    timed(endpoint, call).await
+}
+
+async fn get<T: DeserializeOwned>(
+    api: &dyn CloudflareApi,
+    endpoint: &'static str,
+    path: &str,
+    query: &[(&str, String)],
+) -> Result<Envelope<T>, CfbindError> {
+    request(api, endpoint, Method::GET, path, query, None).await
+}
+
+async fn send<T: DeserializeOwned>(
+    api: &dyn CloudflareApi,
+    endpoint: &'static str,
+    method: Method,
+    path: &str,
+    body: &impl Serialize,
+) -> Result<Envelope<T>, CfbindError> {
+    let body = serde_json::to_value(body).map_err(|e| CfbindError::Parse(e.into()))?;
+    request(api, endpoint, method, path, &[], Some(body)).await
+}
+
+async fn list_zones_page(
+    api: &dyn CloudflareApi,
+    page: u32,
+) -> Result<Envelope<Vec<Zone>>, CfbindError> {
+    let query = [
+        ("page", page.to_string()),
+        ("per_page", ZONES_PER_PAGE.to_string()),
+    ];
+    get(api, "list_zones", "zones", &query).await
+}
+
+/// Every zone the token can see, by name. The pages after the first are
+/// requested concurrently.
+pub async fn get_zones(api: &dyn CloudflareApi) -> anyhow::Result<HashMap<String, Zone>> {
+    let first = list_zones_page(api, 1).await?;
+    let pages = first
+        .result_info
+        .as_ref()
+        .and_then(|info| info.total_pages)
+        .unwrap_or(1);
+    let rest = join_all((2..=pages).map(|page| list_zones_page(api, page))).await;
+    let mut zone_map = HashMap::new();
+    for zone in first.result {
+        zone_map.insert(zone.name.clone(), zone);
+    }
+    for response in rest {
+        for zone in response?.result {
+            zone_map.insert(zone.name.clone(), zone);
+        }
+    }
+    Ok(zone_map)
+}
+
+/// The zone with the ID `id`.
+pub async fn get_zone(api: &dyn CloudflareApi, id: &str) -> anyhow::Result<Zone> {
+    let path = format!("zones/{}", id);
+    let response: Envelope<Zone> = get(api, "get_zone", &path, &[]).await?;
+    Ok(response.result)
+}
+
+/// The most specific zone in `zones` that holds `name`, so a delegated
+/// subzone such as `internal.example.com` wins over `example.com`.
+pub fn find_zone<'a, Z>(zones: &'a HashMap<String, Z>, name: &str) -> anyhow::Result<&'a Z> {
+    // A wildcard label can never be a zone apex.
+    let mut candidate = name.strip_prefix("*.").unwrap_or(name);
+    loop {
+        if let Some(zone) = zones.get(candidate) {
+            return Ok(zone);
+        }
+        match candidate.split_once('.') {
+            Some((_, parent)) => candidate = parent,
+            None => return Err(CfbindError::ZoneNotFound(name.to_string()).into()),
+        }
+    }
+}
+
+/// Passes a successful raw API response through and turns a failed one
+/// into a [`CfbindError`] from Cloudflare's error codes.
+async fn api_result(response: reqwest::Response) -> Result<reqwest::Response, CfbindError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let errors: ApiErrors = response.json().await?;
+    Err(ApiFailure::Error(status, errors).into())
+}
+
+/// Catches a TTL the zone's plan doesn't allow before Cloudflare rejects
+/// the write with a less helpful error.
+fn check_ttl(zone: &Zone, spec: &RecordSpec) -> Result<()> {
+    // Without a plan, as from some emulators, the API has the final say.
+    let Some(plan) = zone.plan.as_ref().map(|plan| plan.name.as_str()) else {
+        return Ok(());
+    };
+    let minimum = if plan.starts_with("Enterprise") {
+        ENTERPRISE_PLAN_MIN_TTL
+    } else if plan.starts_with("Free") {
+        FREE_PLAN_MIN_TTL
+    } else {
+        PAID_PLAN_MIN_TTL
+    };
+    match spec.ttl {
+        Some(ttl) if ttl != 1 && ttl < minimum => bail!(
+            "TTL {} is too short for {}, {} is on the {} plan which needs auto or at least {} seconds",
+            ttl,
+            spec.name,
+            zone.name,
+            plan,
+            minimum
+        ),
+        _ => Ok(()),
+    }
+}
+
+async fn get_dns_records(
+    api: &dyn CloudflareApi,
+    zone: &Zone,
+    name: &str,
+) -> anyhow::Result<Vec<DnsRecord>> {
+    let path = format!("zones/{}/dns_records", zone.id);
+    let query = [("name", name.to_string())];
+    let response: Envelope<Vec<DnsRecord>> = get(api, "list_records", &path, &query).await?;
+    Ok(response.result)
+}
+
+/// Splits a record's content into its type name and value for display.
+pub fn describe_content(content: &DnsContent) -> (&'static str, String) {
+    match content {
+        DnsContent::A { content } => ("A", content.to_string()),
+        DnsContent::AAAA { content } => ("AAAA", content.to_string()),
+        DnsContent::CNAME { content } => ("CNAME", content.clone()),
+        DnsContent::NS { content } => ("NS", content.clone()),
+        DnsContent::MX { content, priority } => ("MX", format!("{} {}", priority, content)),
+        DnsContent::TXT { content } => ("TXT", content.clone()),
+        DnsContent::SRV { content } => ("SRV", content.clone()),
+        #[allow(unreachable_patterns)]
+        _ => ("OTHER", format!("{:?}", content)),
+    }
+}
+
+pub async fn list_zone_records(
+    api: &dyn CloudflareApi,
+    zone: &Zone,
+    name: Option<&str>,
+) -> anyhow::Result<Vec<DnsRecord>> {
+    const PER_PAGE: u32 = 100;
+    let path = format!("zones/{}/dns_records", zone.id);
+    let mut records = Vec::new();
+    for page in 1u32.. {
+        let mut query = vec![
+            ("page", page.to_string()),
+            ("per_page", PER_PAGE.to_string()),
+        ];
+        if let Some(name) = name {
+            query.push(("name", name.to_string()));
+        }
+        let response: Envelope<Vec<DnsRecord>> = get(api, "list_records", &path, &query).await?;
+        let count = response.result.len() as u32;
+        records.extend(response.result);
+        if count < PER_PAGE {
+            break;
+        }
+    }
+    Ok(records)
+}
+
+fn record_type_of(content: &DnsContent) -> Option<RecordType> {
+    match content {
+        DnsContent::A { .. } => Some(RecordType::A),
+        DnsContent::AAAA { .. } => Some(RecordType::Aaaa),
+        DnsContent::CNAME { .. } => Some(RecordType::Cname),
+        DnsContent::TXT { .. } => Some(RecordType::Txt),
+        _ => None,
+    }
+}
+
+fn to_record(record: DnsRecord) -> Option<Record> {
+    let content = match record.content {
+        DnsContent::A { content } => RecordContent::A(content),
+        DnsContent::AAAA { content } => RecordContent::Aaaa(content),
+        DnsContent::CNAME { content } => RecordContent::Cname(content),
+        DnsContent::TXT { content } => RecordContent::Txt(content),
+        _ => return None,
+    };
+    Some(Record {
+        id: record.id,
+        name: record.name,
+        content,
+        ttl: record.ttl,
+        proxied: record.proxied,
+    })
+}
+
+/// A record as written: the body of a create or update, or one entry of a
+/// `/dns_records/batch` request, where `id` is only set for puts.
+#[derive(Debug, Serialize)]
+struct BatchRecord<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<&'a str>,
+    name: &'a str,
+    #[serde(rename = "type")]
+    record_type: String,
+    content: String,
+    ttl: u32,
+    proxied: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<&'a str>,
+}
+
+impl<'a> BatchRecord<'a> {
+    fn new(id: Option<&'a str>, spec: &'a RecordSpec) -> Self {
+        BatchRecord {
+            id,
+            name: &spec.name,
+            record_type: spec.content.record_type().to_string(),
+            content: spec.content.to_string(),
+            ttl: spec.ttl.unwrap_or(1),
+            proxied: spec.proxied,
+            comment: spec.comment.as_deref(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct BatchRequest<'a> {
+    puts: Vec<BatchRecord<'a>>,
+    posts: Vec<BatchRecord<'a>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchResult {
+    #[serde(default)]
+    puts: Vec<DnsRecord>,
+    #[serde(default)]
+    posts: Vec<DnsRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResultInfo {
+    total_pages: Option<u32>,
+}
+
+/// The body of every successful API response.
+#[derive(Debug, Deserialize)]
+struct Envelope<T> {
+    result: T,
+    #[serde(default)]
+    result_info: Option<ResultInfo>,
+}
+
+/// What Cloudflare says about the token in use.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TokenStatus {
+    pub id: String,
+    pub status: String,
+    pub expires_on: Option<String>,
+}
+
+/// A record the config names by ID. It is read and written directly, so a
+/// token that can't list records still works; without a zone ID only the
+/// zones are listed.
+struct Pinned {
+    zone_id: Option<String>,
+    record_id: String,
+}
+
+/// The token API calls authenticate with and where they are sent, swapped as
+/// a unit when the token is rotated. Calls go through the shared HTTP client,
+/// so the proxy, CA, TLS, binding and resolver settings apply to them too.
+pub struct Session {
+    http: reqwest::Client,
+    api_key: Secret,
+    api_base: Url,
+}
+
+impl Session {
+    fn new(api_key: &Secret, api_base: &Url) -> Self {
+        Session {
+            http: http::client(),
+            api_key: api_key.clone(),
+            api_base: api_base.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl CloudflareApi for Session {
+    async fn call(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, String)],
+        body: Option<serde_json::Value>,
+    ) -> Result<serde_json::Value, CfbindError> {
+        let url = self
+            .api_base
+            .join(path)
+            .map_err(|e| CfbindError::Api(e.into()))?;
+        let mut request = self
+            .http
+            .request(method, url)
+            .bearer_auth(self.api_key.expose());
+        if !query.is_empty() {
+            request = request.query(query);
+        }
+        if let Some(body) = &body {
+            request = request.json(body);
+        }
+        let response = api_result(request.send().await?).await?;
+        Ok(response.json().await?)
+    }
+}
+
+/// The base URL of `environment`'s API.
+fn api_base(environment: &CloudflareEnvironment) -> Url {
+    match environment {
+        CloudflareEnvironment::Production => Url::parse(API_BASE).expect("API_BASE is a URL"),
+        CloudflareEnvironment::Custom(url) => url.clone(),
+    }
+}
+
+fn read_token(path: &Path) -> Result<Secret> {
+    let token = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read API token file {}", path.display()))?;
+    Ok(Secret::new(token.trim()))
+}
+
+/// Where a token that can be rotated underneath us comes from.
+enum TokenSource {
+    File(PathBuf),
+    Vault(VaultConfig),
+}
+
+impl TokenSource {
+    async fn fetch(&self) -> Result<Secret> {
+        match self {
+            TokenSource::File(path) => read_token(path),
+            TokenSource::Vault(config) => vault::fetch_secret(config).await,
+        }
+    }
+
+    /// How long a fetched token is used before the source is asked again.
+    fn refresh_every(&self) -> Duration {
+        match self {
+            TokenSource::File(_) => Duration::ZERO,
+            TokenSource::Vault(config) => {
+                Duration::from_secs(config.refresh_every.unwrap_or(VAULT_REFRESH_EVERY))
+            }
+        }
+    }
+}
+
+impl fmt::Display for TokenSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenSource::File(path) => write!(f, "{}", path.display()),
+            TokenSource::Vault(config) => {
+                write!(f, "Vault secret {}/{}", config.mount, config.path)
+            }
+        }
+    }
+}
+
+/// The account's zones as last listed.
+struct ZoneIndex {
+    zones: Arc<HashMap<String, Zone>>,
+    listed: Instant,
+}
+
+pub struct Cloudflare {
+    session: RwLock<Arc<Session>>,
+    /// Stands in for `session`, e.g. a mock in tests.
+    api: Option<Arc<dyn CloudflareApi>>,
+    source: Option<TokenSource>,
+    fetched: Mutex<Instant>,
+    zones: tokio::sync::Mutex<Option<ZoneIndex>>,
+    zone_refresh: Duration,
+    pinned: HashMap<String, Pinned>,
+}
+
+impl Cloudflare {
+    pub fn new(api_key: &Secret) -> Result<Self> {
+        Cloudflare::with_environment(api_key, &CloudflareEnvironment::Production)
+    }
+
+    /// A client for the API of `environment`, which may be a gateway, an
+    /// emulator or a mock server instead of Cloudflare's own.
+    pub fn with_environment(api_key: &Secret, environment: &CloudflareEnvironment) -> Result<Self> {
+        let api_base = api_base(environment);
+        Ok(Cloudflare {
+            session: RwLock::new(Arc::new(Session::new(api_key, &api_base))),
+            api: None,
+            source: None,
+            fetched: Mutex::new(Instant::now()),
+            zones: tokio::sync::Mutex::new(None),
+            zone_refresh: Duration::from_secs(ZONE_REFRESH_EVERY),
+            pinned: HashMap::new(),
+        })
+    }
+
+    /// A provider whose calls all go to `api` instead of over HTTP.
+    pub fn with_api(api: Arc<dyn CloudflareApi>) -> Self {
+        Cloudflare {
+            api: Some(api),
+            ..Cloudflare::new(&Secret::new("")).expect("the production API base is valid")
+        }
+    }
+
+    pub async fn from_config(config: &Config) -> Result<Self> {
+        let cloudflare = config.cloudflare.as_ref();
+        let environment = cloudflare
+            .and_then(|c| c.environment.clone())
+            .unwrap_or_default();
+        let mut provider = match cloudflare {
+            Some(CloudflareConfig {
+                api_key: Some(api_key),
+                ..
+            }) => Cloudflare::with_environment(api_key, &environment)?,
+            Some(CloudflareConfig {
+                api_key_file: Some(path),
+                ..
+            }) => Cloudflare::with_source(TokenSource::File(path.clone()), &environment).await?,
+            Some(CloudflareConfig {
+                vault: Some(vault), ..
+            }) => Cloudflare::with_source(TokenSource::Vault(vault.clone()), &environment).await?,
+            _ => bail!("Cloudflare API key is not configured"),
+        };
+        if let Some(seconds) = cloudflare.and_then(|c| c.zone_refresh) {
+            provider.zone_refresh = Duration::from_secs(seconds);
+        }
+        for domain in &config.domains {
+            if let Some(record_id) = &domain.record_id {
+                let pinned = Pinned {
+                    zone_id: domain.zone_id.clone(),
+                    record_id: record_id.clone(),
+                };
+                provider.pinned.insert(domain.name.clone(), pinned);
+            }
+        }
+        Ok(provider)
+    }
+
+    async fn with_source(source: TokenSource, environment: &CloudflareEnvironment) -> Result<Self> {
+        let api_key = source.fetch().await?;
+        Ok(Cloudflare {
+            source: Some(source),
+            ..Cloudflare::with_environment(&api_key, environment)?
+        })
+    }
+
+    /// The account's zones, listed once and shared by every domain until
+    /// `zone_refresh` has passed. Callers arriving during a listing wait for
+    /// it rather than starting their own.
+    async fn zones(&self) -> Result<Arc<HashMap<String, Zone>>> {
+        let mut index = self.zones.lock().await;
+        if let Some(index) = index.as_ref() {
+            if index.listed.elapsed() < self.zone_refresh {
+                return Ok(Arc::clone(&index.zones));
+            }
+        }
+        let zones = Arc::new(get_zones(&self.client()).await?);
+        log::debug!("Indexed {} zones", zones.len());
+        *index = Some(ZoneIndex {
+            zones: Arc::clone(&zones),
+            listed: Instant::now(),
+        });
+        Ok(zones)
+    }
+
+    /// The current client; hold on to it for one operation only, so a
+    /// rotated token is used from the next one on.
+    pub fn client(&self) -> Arc<dyn CloudflareApi> {
+        match &self.api {
+            Some(api) => Arc::clone(api),
+            None => self.session.read().unwrap().clone(),
+        }
+    }
+
+    /// Asks Cloudflare whether the token is valid and active.
+    pub async fn verify_token(&self) -> Result<TokenStatus> {
+        let response: Envelope<TokenStatus> =
+            get(&*self.client(), "verify_token", "user/tokens/verify", &[]).await?;
+        Ok(response.result)
+    }
+
+    async fn update(
+        &self,
+        zone_id: &str,
+        id: &str,
+        spec: &RecordSpec,
+    ) -> Result<DnsRecord, CfbindError> {
+        let path = format!("zones/{}/dns_records/{}", zone_id, id);
+        let body = BatchRecord::new(None, spec);
+        let response = send(&*self.client(), "update_record", Method::PUT, &path, &body).await?;
+        Ok(response.result)
+    }
+
+    async fn create(&self, zone: &Zone, spec: &RecordSpec) -> Result<DnsRecord, CfbindError> {
+        let path = format!("zones/{}/dns_records", zone.id);
+        let body = BatchRecord::new(None, spec);
+        let response = send(&*self.client(), "create_record", Method::POST, &path, &body).await?;
+        Ok(response.result)
+    }
+
+    /// Applies every write for one zone in a single atomic batch request,
+    /// replacing the records whose ID is given. Records come back in the
+    /// order of `writes`.
+    async fn batch(
+        &self,
+        zone_id: &str,
+        writes: &[(Option<String>, &RecordSpec)],
+    ) -> Result<Vec<DnsRecord>> {
+        let mut request = BatchRequest::default();
+        for (id, spec) in writes {
+            match id {
+                Some(id) => request.puts.push(BatchRecord::new(Some(id), spec)),
+                None => request.posts.push(BatchRecord::new(None, spec)),
+            }
+        }
+        let path = format!("zones/{}/dns_records/batch", zone_id);
+        let response: Envelope<BatchResult> =
+            send(&*self.client(), "batch", Method::POST, &path, &request).await?;
+        let mut puts = response.result.puts.into_iter();
+        let mut posts = response.result.posts.into_iter();
+        writes
+            .iter()
+            .map(|(id, spec)| {
+                let written = match id {
+                    Some(_) => puts.next(),
+                    None => posts.next(),
+                };
+                written.with_context(|| format!("Batch response is missing {}", spec.name))
+            })
+            .collect()
+    }
+
+    /// The zone of a pinned record, from the config or else the zone index.
+    async fn pinned_zone_id(&self, name: &str, pinned: &Pinned) -> Result<String> {
+        match &pinned.zone_id {
+            Some(zone_id) => Ok(zone_id.clone()),
+            None => Ok(find_zone(&*self.zones().await?, name)?.id.clone()),
+        }
+    }
+
+    /// Reads a pinned record by its ID, checking it has the expected type.
+    async fn get_pinned(
+        &self,
+        name: &str,
+        pinned: &Pinned,
+        record_type: RecordType,
+    ) -> Result<DnsRecord> {
+        let zone_id = self.pinned_zone_id(name, pinned).await?;
+        let path = format!("zones/{}/dns_records/{}", zone_id, pinned.record_id);
+        let response: Envelope<DnsRecord> = get(&*self.client(), "get_record", &path, &[]).await?;
+        let result = response.result;
+        if result.name != name || record_type_of(&result.content) != Some(record_type) {
+            bail!(
+                "Record {} is {} {}, not the {} record for {}",
+                pinned.record_id,
+                describe_content(&result.content).0,
+                result.name,
+                record_type,
+                name
+            );
+        }
+        Ok(result)
+    }
+
+    async fn records_named(&self, name: &str) -> Result<Vec<DnsRecord>> {
+        let zones = self.zones().await?;
+        get_dns_records(&self.client(), find_zone(&zones, name)?, name).await
+    }
+
+    async fn find_record(&self, name: &str, record_type: RecordType) -> Result<Option<DnsRecord>> {
+        let records = self.records_named(name).await?;
+        Ok(records
+            .into_iter()
+            .find(|r| record_type_of(&r.content) == Some(record_type)))
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Cloudflare {
+    fn supports_proxy(&self) -> bool {
+        true
+    }
+
+    async fn reload_credentials(&self, force: bool) -> Result<()> {
+        let Some(source) = &self.source else {
+            return Ok(());
+        };
+        if !force && self.fetched.lock().unwrap().elapsed() < source.refresh_every() {
+            return Ok(());
+        }
+        let api_key = source.fetch().await?;
+        *self.fetched.lock().unwrap() = Instant::now();
+        let current = Arc::clone(&self.session.read().unwrap());
+        if api_key == current.api_key {
+            return Ok(());
+        }
+        let session = Arc::new(Session::new(&api_key, &current.api_base));
+        *self.session.write().unwrap() = session;
+        log::info!("Reloaded the Cloudflare API token from {}", source);
+        Ok(())
+    }
+
+    async fn get_record(&self, name: &str, record_type: RecordType) -> Result<Option<Record>> {
+        if let Some(pinned) = self.pinned.get(name) {
+            return Ok(to_record(self.get_pinned(name, pinned, record_type).await?));
+        }
+        Ok(self
+            .find_record(name, record_type)
+            .await?
+            .and_then(to_record))
+    }
+
+    async fn get_records(
+        &self,
+        names: &[String],
+        record_type: RecordType,
+    ) -> Result<Vec<Option<Record>>> {
+        // One zone lookup, then one listing per zone rather than per name.
+        let zones = self.zones().await?;
+        let mut listed: HashMap<String, Vec<DnsRecord>> = HashMap::new();
+        let mut records = Vec::with_capacity(names.len());
+        for name in names {
+            let zone = find_zone(&zones, name)?;
+            if !listed.contains_key(&zone.id) {
+                let zone_records = list_zone_records(&self.client(), zone, None).await?;
+                listed.insert(zone.id.clone(), zone_records);
+            }
+            let zone_records = listed.get_mut(&zone.id).expect("listed above");
+            let record = zone_records
+                .iter()
+                .position(|r| r.name == *name && record_type_of(&r.content) == Some(record_type))
+                .map(|i| zone_records.swap_remove(i))
+                .and_then(to_record);
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    async fn upsert_record(&self, spec: &RecordSpec) -> Result<Record> {
+        if let Some(pinned) = self.pinned.get(&spec.name) {
+            let zone_id = self.pinned_zone_id(&spec.name, pinned).await?;
+            // Only an explicit TTL needs the zone's plan to be checked.
+            if spec.ttl.is_some_and(|ttl| ttl != 1) {
+                check_ttl(&get_zone(&*self.client(), &zone_id).await?, spec)?;
+            }
+            let updated = self.update(&zone_id, &pinned.record_id, spec).await?;
+            return to_record(updated).context("Unexpected record type in response");
+        }
+        let zones = self.zones().await?;
+        let zone = find_zone(&zones, &spec.name)?;
+        check_ttl(zone, spec)?;
+        let dns_record = self
+            .find_record(&spec.name, spec.content.record_type())
+            .await?;
+        let result = match dns_record {
+            Some(record) => match self.update(&record.zone_id, &record.id, spec).await {
+                Err(CfbindError::RecordNotFound(_)) => {
+                    // Deleted between our read and write; put it back.
+                    log::warn!("{} was deleted concurrently, creating it again", spec.name);
+                    self.create(zone, spec).await
+                }
+                result => result,
+            },
+            None => {
+                match self.create(zone, spec).await {
+                    Err(CfbindError::Conflict(e)) => {
+                        // Someone else created it between our read and write; update theirs.
+                        log::warn!(
+                            "{} was created concurrently, updating it instead",
+                            spec.name
+                        );
+                        match self
+                            .find_record(&spec.name, spec.content.record_type())
+                            .await?
+                        {
+                            Some(record) => self.update(&record.zone_id, &record.id, spec).await,
+                            None => Err(CfbindError::Conflict(e)),
+                        }
+                    }
+                    result => result,
+                }
+            }
+        };
+        let written = result?;
+        log::debug!("Wrote {} as record {}", spec.name, written.id);
+        to_record(written).context("Unexpected record type in response")
+    }
+
+    async fn upsert_records(
+        &self,
+        specs: &[RecordSpec],
+        existing: &[Option<Record>],
+    ) -> Result<Vec<Record>> {
+        // Pinned records are written by ID, one at a time.
+        if specs.len() < 2 || specs.iter().any(|s| self.pinned.contains_key(&s.name)) {
+            let mut records = Vec::new();
+            for spec in specs {
+                records.push(self.upsert_record(spec).await?);
+            }
+            return Ok(records);
+        }
+        // Group the writes by zone, remembering where each one came from.
+        let zones = self.zones().await?;
+        let mut groups: Vec<(&Zone, Vec<usize>, Vec<(Option<String>, &RecordSpec)>)> = Vec::new();
+        for (index, spec) in specs.iter().enumerate() {
+            let zone = find_zone(&zones, &spec.name)?;
+            check_ttl(zone, spec)?;
+            // Looked up by the caller; a record deleted since then makes the
+            // batch fail, and the writes are redone one by one below.
+            let id = existing
+                .get(index)
+                .and_then(Option::as_ref)
+                .filter(|r| r.content.record_type() == spec.content.record_type())
+                .map(|r| r.id.clone());
+            match groups.iter_mut().find(|(z, _, _)| z.id == zone.id) {
+                Some((_, indices, writes)) => {
+                    indices.push(index);
+                    writes.push((id, spec));
+                }
+                None => groups.push((zone, vec![index], vec![(id, spec)])),
+            }
+        }
+        let mut records: Vec<Option<Record>> = vec![None; specs.len()];
+        for (zone, indices, writes) in &groups {
+            log::info!(
+                "Writing {} records to {} in one batch",
+                writes.len(),
+                zone.name
+            );
+            let written = match self.batch(&zone.id, writes).await {
+                Ok(written) => written,
+                Err(e) if matches!(e.downcast_ref(), Some(CfbindError::RecordNotFound(_))) => {
+                    // A record went away since it was read. The batch is atomic,
+                    // so nothing was written; redo the writes one at a time,
+                    // which re-creates missing records.
+                    log::warn!("{}, writing the records of {} one by one", e, zone.name);
+                    for (index, (_, spec)) in indices.iter().zip(writes) {
+                        records[*index] = Some(self.upsert_record(spec).await?);
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            for (index, written) in indices.iter().zip(written) {
+                records[*index] =
+                    Some(to_record(written).context("Unexpected record type in response")?);
+            }
+        }
+        Ok(records.into_iter().flatten().collect())
+    }
+
+    async fn list_records(&self, name: &str, record_type: RecordType) -> Result<Vec<Record>> {
+        Ok(self
+            .records_named(name)
+            .await?
+            .into_iter()
+            .filter(|r| record_type_of(&r.content) == Some(record_type))
+            .filter_map(to_record)
+            .collect())
+    }
+
+    async fn create_record(&self, spec: &RecordSpec) -> Result<Record> {
+        let zones = self.zones().await?;
+        let zone = find_zone(&zones, &spec.name)?;
+        check_ttl(zone, spec)?;
+        let created = self.create(zone, spec).await?;
+        to_record(created).context("Unexpected record type in response")
+    }
+
+    async fn update_record(&self, record: &Record, spec: &RecordSpec) -> Result<Record> {
+        let zones = self.zones().await?;
+        let zone = find_zone(&zones, &record.name)?;
+        check_ttl(zone, spec)?;
+        let updated = match self.update(&zone.id, &record.id, spec).await {
+            Err(CfbindError::RecordNotFound(_)) => {
+                log::warn!("{} was deleted concurrently, creating it again", spec.name);
+                self.create(zone, spec).await?
+            }
+            result => result?,
+        };
+        to_record(updated).context("Unexpected record type in response")
+    }
+
+    async fn nameservers(&self, name: &str) -> Result<Vec<String>> {
+        let zones = self.zones().await?;
+        Ok(find_zone(&zones, name)?.name_servers.clone())
+    }
+
+    async fn delete_record(&self, record: &Record) -> Result<()> {
+        let zone_id = match self.pinned.get(&record.name) {
+            Some(pinned) => self.pinned_zone_id(&record.name, pinned).await?,
+            None => find_zone(&*self.zones().await?, &record.name)?.id.clone(),
+        };
+        let path = format!("zones/{}/dns_records/{}", zone_id, record.id);
+        let _: Envelope<serde_json::Value> = request(
+            &*self.client(),
+            "delete_record",
+            Method::DELETE,
+            &path,
+            &[],
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use wiremock::matchers::{body_partial_json, header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    const NAME: &str = "home.example.com";
+
+    fn zones(names: &[&str]) -> HashMap<String, String> {
+        names
+            .iter()
+            .map(|name| (name.to_string(), name.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn finds_the_most_specific_zone() {
+        let zones = zones(&["example.com", "internal.example.com"]);
+        assert_eq!(
+            find_zone(&zones, "home.example.com").unwrap(),
+            "example.com"
+        );
+        assert_eq!(
+            find_zone(&zones, "nas.internal.example.com").unwrap(),
+            "internal.example.com"
+        );
+        assert_eq!(find_zone(&zones, "example.com").unwrap(), "example.com");
+    }
+
+    #[test]
+    fn looks_wildcards_up_below_the_star() {
+        let zones = zones(&["example.com", "internal.example.com"]);
+        assert_eq!(
+            find_zone(&zones, "*.internal.example.com").unwrap(),
+            "internal.example.com"
+        );
+    }
+
+    #[test]
+    fn fails_for_names_outside_every_zone() {
+        let zones = zones(&["example.com"]);
+        let error = find_zone(&zones, "home.example.net").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(CfbindError::ZoneNotFound(name)) if name == "home.example.net"
+        ));
+        assert!(find_zone(&zones, "badexample.com").is_err());
+    }
+
+    fn zone(id: &str, name: &str) -> Value {
+        json!({
+            "id": id,
+            "name": name,
+            "account": { "id": "account", "name": "Account" },
+            "created_on": "2024-01-01T00:00:00Z",
+            "modified_on": "2024-01-01T00:00:00Z",
+            "development_mode": 0,
+            "meta": {
+                "custom_certificate_quota": 0,
+                "page_rule_quota": 3,
+                "phishing_detected": false,
+                "multiple_railguns_allowed": false
+            },
+            "name_servers": ["ada.ns.cloudflare.com", "bob.ns.cloudflare.com"],
+            "owner": { "type": "user", "id": "owner", "email": "owner@example.com" },
+            "paused": false,
+            "permissions": ["#dns_records:edit"],
+            "plan": {
+                "id": "plan",
+                "name": "Pro Website",
+                "price": 20.0,
+                "currency": "USD",
+                "is_subscribed": true,
+                "can_subscribe": false,
+                "legacy_id": "pro",
+                "legacy_discount": false,
+                "externally_managed": false
+            },
+            "status": "active",
+            "type": "full"
+        })
+    }
+
+    fn record(id: &str, ip: &str) -> Value {
+        json!({
+            "id": id,
+            "zone_id": "z1",
+            "zone_name": "example.com",
+            "name": NAME,
+            "type": "A",
+            "content": ip,
+            "proxiable": true,
+            "proxied": false,
+            "ttl": 1,
+            "locked": false,
+            "meta": { "auto_added": false },
+            "created_on": "2024-01-01T00:00:00Z",
+            "modified_on": "2024-01-01T00:00:00Z"
+        })
+    }
+
+    fn success(result: Value) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(json!({
+            "success": true,
+            "errors": [],
+            "messages": [],
+            "result": result,
+            "result_info": { "page": 1, "per_page": 50, "total_pages": 1, "count": 1, "total_count": 1 }
+        }))
+    }
+
+    fn failure(status: u16, code: u16) -> ResponseTemplate {
+        ResponseTemplate::new(status).set_body_json(json!({
+            "success": false,
+            "errors": [{ "code": code, "message": "rejected" }],
+            "messages": [],
+            "result": null
+        }))
+    }
+
+    fn spec(ip: &str) -> RecordSpec {
+        RecordSpec {
+            name: NAME.to_string(),
+            content: RecordContent::A(ip.parse().unwrap()),
+            ttl: None,
+            proxied: false,
+            comment: None,
+        }
+    }
+
+    #[test]
+    fn takes_the_shortest_ttl_from_the_plan() {
+        let on_plan = |plan: &str| {
+            let mut zone = zone("z1", "example.com");
+            zone["plan"]["name"] = json!(plan);
+            serde_json::from_value::<Zone>(zone).unwrap()
+        };
+        let with_ttl = |ttl| RecordSpec {
+            ttl: Some(ttl),
+            ..spec("192.0.2.1")
+        };
+        assert!(check_ttl(&on_plan("Enterprise Website"), &with_ttl(30)).is_ok());
+        assert!(check_ttl(&on_plan("Pro Website"), &with_ttl(30)).is_err());
+        assert!(check_ttl(&on_plan("Pro Website"), &with_ttl(60)).is_ok());
+        assert!(check_ttl(&on_plan("Free Website"), &with_ttl(60)).is_err());
+        assert!(check_ttl(&on_plan("Free Website"), &with_ttl(1)).is_ok());
+    }
+
+    /// A mock API holding the zones `example.com` (z1) and
+    /// `internal.example.com` (z2).
+    async fn server() -> (MockServer, Cloudflare) {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/zones"))
+            .and(header("authorization", "Bearer token"))
+            .respond_with(success(json!([
+                zone("z1", "example.com"),
+                zone("z2", "internal.example.com")
+            ])))
+            .mount(&server)
+            .await;
+        let base = Url::parse(&format!("{}/", server.uri())).unwrap();
+        let cloudflare = Cloudflare::with_environment(
+            &Secret::new("token"),
+            &CloudflareEnvironment::Custom(base),
+        )
+        .unwrap();
+        (server, cloudflare)
+    }
+
+    fn list_records(zone: &str, name: &str) -> Mock {
+        Mock::given(method("GET"))
+            .and(path(format!("/zones/{}/dns_records", zone)))
+            .and(query_param("name", name))
+    }
+
+    #[tokio::test]
+    async fn looks_records_up_in_the_most_specific_zone() {
+        let (server, cloudflare) = server().await;
+        let name = "nas.internal.example.com";
+        list_records("z2", name)
+            .respond_with(success(json!([])))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let record = cloudflare.get_record(name, RecordType::A).await.unwrap();
+        assert!(record.is_none());
+    }
+
+    #[tokio::test]
+    async fn fails_for_a_name_outside_every_zone() {
+        let (_server, cloudflare) = server().await;
+        let error = cloudflare
+            .get_record("home.example.net", RecordType::A)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(CfbindError::ZoneNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn creates_a_missing_record() {
+        let (server, cloudflare) = server().await;
+        list_records("z1", NAME)
+            .respond_with(success(json!([])))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/zones/z1/dns_records"))
+            .and(body_partial_json(
+                json!({ "name": NAME, "type": "A", "content": "192.0.2.1" }),
+            ))
+            .respond_with(success(record("r1", "192.0.2.1")))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let written = cloudflare.upsert_record(&spec("192.0.2.1")).await.unwrap();
+        assert_eq!(written.id, "r1");
+    }
+
+    #[tokio::test]
+    async fn updates_an_existing_record() {
+        let (server, cloudflare) = server().await;
+        list_records("z1", NAME)
+            .respond_with(success(json!([record("r1", "192.0.2.1")])))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/zones/z1/dns_records/r1"))
+            .and(body_partial_json(json!({ "content": "192.0.2.2" })))
+            .respond_with(success(record("r1", "192.0.2.2")))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let written = cloudflare.upsert_record(&spec("192.0.2.2")).await.unwrap();
+        assert_eq!(
+            written.content,
+            RecordContent::A("192.0.2.2".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn checks_the_ttl_of_a_pinned_record() {
+        let (server, mut cloudflare) = server().await;
+        cloudflare.pinned.insert(
+            NAME.to_string(),
+            Pinned {
+                zone_id: Some("z1".to_string()),
+                record_id: "r1".to_string(),
+            },
+        );
+        let mut free = zone("z1", "example.com");
+        free["plan"]["name"] = json!("Free Website");
+        Mock::given(method("GET"))
+            .and(path("/zones/z1"))
+            .respond_with(success(free))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/zones/z1/dns_records/r1"))
+            .respond_with(success(record("r1", "192.0.2.1")))
+            .expect(0)
+            .mount(&server)
+            .await;
+        let spec = RecordSpec {
+            ttl: Some(60),
+            ..spec("192.0.2.1")
+        };
+        assert!(cloudflare.upsert_record(&spec).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn updates_a_record_created_concurrently() {
+        let (server, cloudflare) = server().await;
+        // Missing when first read, then there once the create was refused.
+        list_records("z1", NAME)
+            .respond_with(success(json!([])))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        list_records("z1", NAME)
+            .respond_with(success(json!([record("r1", "192.0.2.1")])))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/zones/z1/dns_records"))
+            .respond_with(failure(400, 81057))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/zones/z1/dns_records/r1"))
+            .respond_with(success(record("r1", "192.0.2.2")))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let written = cloudflare.upsert_record(&spec("192.0.2.2")).await.unwrap();
+        assert_eq!(written.id, "r1");
+    }
+
+    #[tokio::test]
+    async fn recreates_a_record_deleted_concurrently() {
+        let (server, cloudflare) = server().await;
+        list_records("z1", NAME)
+            .respond_with(success(json!([record("r1", "192.0.2.1")])))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/zones/z1/dns_records/r1"))
+            .respond_with(failure(404, 81044))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/zones/z1/dns_records"))
+            .respond_with(success(record("r2", "192.0.2.2")))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let written = cloudflare.upsert_record(&spec("192.0.2.2")).await.unwrap();
+        assert_eq!(written.id, "r2");
+    }
+
+    /// Specs for `nas.example.com`, which has no record yet, and [`NAME`],
+    /// which is r1.
+    fn batch_writes(ip: &str) -> ([RecordSpec; 2], [Option<Record>; 2]) {
+        let nas = RecordSpec {
+            name: "nas.example.com".to_string(),
+            ..spec(ip)
+        };
+        let r1 = serde_json::from_value(record("r1", "192.0.2.1")).unwrap();
+        ([nas, spec(ip)], [None, to_record(r1)])
+    }
+
+    #[tokio::test]
+    async fn writes_several_records_in_one_batch() {
+        let (server, cloudflare) = server().await;
+        let mut nas = record("r2", "192.0.2.2");
+        nas["name"] = json!("nas.example.com");
+        Mock::given(method("POST"))
+            .and(path("/zones/z1/dns_records/batch"))
+            .and(body_partial_json(json!({
+                "puts": [{ "id": "r1", "name": NAME, "content": "192.0.2.2" }],
+                "posts": [{ "name": "nas.example.com", "content": "192.0.2.2" }]
+            })))
+            .respond_with(success(json!({
+                "puts": [record("r1", "192.0.2.2")],
+                "posts": [nas]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let (specs, existing) = batch_writes("192.0.2.2");
+        let written = cloudflare.upsert_records(&specs, &existing).await.unwrap();
+        let ids: Vec<&str> = written.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, ["r2", "r1"]);
+        // The records were known, so nothing but the zones is read again.
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn fails_every_write_of_a_rejected_batch() {
+        let (server, cloudflare) = server().await;
+        Mock::given(method("POST"))
+            .and(path("/zones/z1/dns_records/batch"))
+            .respond_with(failure(400, 81058))
+            .expect(1)
+            .mount(&server)
+            .await;
+        // The batch is atomic, so none of it is retried on its own.
+        Mock::given(method("PUT"))
+            .respond_with(success(record("r1", "192.0.2.2")))
+            .expect(0)
+            .mount(&server)
+            .await;
+        let (specs, existing) = batch_writes("192.0.2.2");
+        let error = cloudflare
+            .upsert_records(&specs, &existing)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(CfbindError::Conflict(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn reports_records_missing_from_a_batch_response() {
+        let (server, cloudflare) = server().await;
+        Mock::given(method("POST"))
+            .and(path("/zones/z1/dns_records/batch"))
+            .respond_with(success(json!({ "puts": [record("r1", "192.0.2.2")] })))
+            .mount(&server)
+            .await;
+        let (specs, existing) = batch_writes("192.0.2.2");
+        let error = cloudflare
+            .upsert_records(&specs, &existing)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("nas.example.com"));
+    }
+
+    #[tokio::test]
+    async fn reports_rate_limits() {
+        let (server, cloudflare) = server().await;
+        list_records("z1", NAME)
+            .respond_with(failure(429, 971))
+            .mount(&server)
+            .await;
+        let error = cloudflare
+            .upsert_record(&spec("192.0.2.1"))
+            .await
+            .unwrap_err();
+        assert!(CfbindError::is_rate_limited(&error));
+    }
+
+    #[tokio::test]
+    async fn reports_a_refused_token() {
+        let (server, cloudflare) = server().await;
+        list_records("z1", NAME)
+            .respond_with(failure(403, 10000))
+            .mount(&server)
+            .await;
+        let error = cloudflare
+            .get_record(NAME, RecordType::A)
+            .await
+            .unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(CfbindError::Auth(_))));
+    }
+
+    /// Answers from canned bodies by path, recording every call.
+    #[derive(Default)]
+    struct Canned {
+        calls: Mutex<Vec<(Method, String)>>,
+    }
+
+    #[async_trait]
+    impl CloudflareApi for Canned {
+        async fn call(
+            &self,
+            method: Method,
+            path: &str,
+            _query: &[(&str, String)],
+            _body: Option<Value>,
+        ) -> Result<Value, CfbindError> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push((method.clone(), path.to_string()));
+            let result = match path {
+                "zones" => json!([zone("z1", "example.com")]),
+                "zones/z1/dns_records" => json!([record("r1", "192.0.2.1")]),
+                _ => {
+                    return Err(CfbindError::Api(anyhow::anyhow!(
+                        "unexpected call to {}",
+                        path
+                    )))
+                }
+            };
+            Ok(json!({ "result": result }))
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_each_zone_once_for_several_names() {
+        let api = Arc::new(Canned::default());
+        let cloudflare = Cloudflare::with_api(api.clone());
+        let names = vec![NAME.to_string(), "www.example.com".to_string()];
+        let records = cloudflare.get_records(&names, RecordType::A).await.unwrap();
+        assert_eq!(records[0].as_ref().map(|r| r.id.as_str()), Some("r1"));
+        assert!(records[1].is_none());
+        let calls = api.calls.lock().unwrap();
+        assert_eq!(
+            *calls,
+            vec![
+                (Method::GET, "zones".to_string()),
+                (Method::GET, "zones/z1/dns_records".to_string()),
+            ]
+        );
+    }
+}
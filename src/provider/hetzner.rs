@@ -0,0 +1,241 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use super::{DnsProvider, Record, RecordContent, RecordSpec, RecordType};
+use crate::error::CfbindError;
+use crate::http;
+use crate::secret::Secret;
+
+const API_BASE: &str = "https://dns.hetzner.com/api/v1";
+const DEFAULT_TTL: u32 = 300;
+
+#[derive(Debug, Deserialize)]
+struct Zone {
+    id: String,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ZonesResponse {
+    zones: Vec<Zone>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HetznerRecord {
+    id: String,
+    #[serde(rename = "type")]
+    record_type: String,
+    name: String,
+    value: String,
+    ttl: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordsResponse {
+    records: Vec<HetznerRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordResponse {
+    record: HetznerRecord,
+}
+
+#[derive(Debug, Serialize)]
+struct RecordRequest<'a> {
+    zone_id: &'a str,
+    #[serde(rename = "type")]
+    record_type: String,
+    name: &'a str,
+    value: String,
+    ttl: u32,
+}
+
+/// Hetzner names records relative to the zone, with `@` for the apex.
+fn relative_name<'a>(name: &'a str, zone: &str) -> &'a str {
+    if name == zone {
+        "@"
+    } else {
+        name.strip_suffix(zone)
+            .and_then(|n| n.strip_suffix('.'))
+            .unwrap_or(name)
+    }
+}
+
+fn to_record(record: HetznerRecord, zone: &Zone, record_type: RecordType) -> Result<Record> {
+    let name = if record.name == "@" {
+        zone.name.clone()
+    } else {
+        format!("{}.{}", record.name, zone.name)
+    };
+    Ok(Record {
+        id: record.id,
+        name,
+        content: RecordContent::parse(record_type, &record.value)?,
+        ttl: record.ttl.unwrap_or(DEFAULT_TTL),
+        proxied: false,
+    })
+}
+
+pub struct Hetzner {
+    client: Client,
+    api_token: Secret,
+}
+
+impl Hetzner {
+    pub fn new(api_token: &Secret) -> Self {
+        Hetzner {
+            client: http::client(),
+            api_token: api_token.clone(),
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, format!("{}{}", API_BASE, path))
+            .header("Auth-API-Token", self.api_token.expose())
+    }
+
+    /// Finds the most specific zone containing `name` by walking up its labels.
+    async fn find_zone(&self, name: &str) -> Result<Zone> {
+        // A wildcard label can never be a zone apex.
+        let name = name.strip_prefix("*.").unwrap_or(name);
+        let labels: Vec<&str> = name.split('.').collect();
+        for i in 0..labels.len().saturating_sub(1) {
+            let candidate = labels[i..].join(".");
+            let response: ZonesResponse = self
+                .request(reqwest::Method::GET, "/zones")
+                .query(&[("name", candidate.as_str())])
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            if let Some(zone) = response.zones.into_iter().find(|z| z.name == candidate) {
+                return Ok(zone);
+            }
+        }
+        Err(CfbindError::ZoneNotFound(name.to_string()).into())
+    }
+
+    async fn find_record(
+        &self,
+        zone: &Zone,
+        name: &str,
+        record_type: RecordType,
+    ) -> Result<Option<HetznerRecord>> {
+        let response: RecordsResponse = self
+            .request(reqwest::Method::GET, "/records")
+            .query(&[("zone_id", zone.id.as_str())])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let relative = relative_name(name, &zone.name);
+        Ok(response
+            .records
+            .into_iter()
+            .find(|r| r.name == relative && r.record_type == record_type.to_string()))
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Hetzner {
+    async fn get_record(&self, name: &str, record_type: RecordType) -> Result<Option<Record>> {
+        let zone = self.find_zone(name).await?;
+        match self.find_record(&zone, name, record_type).await? {
+            Some(record) => Ok(Some(to_record(record, &zone, record_type)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn upsert_record(&self, spec: &RecordSpec) -> Result<Record> {
+        let zone = self.find_zone(&spec.name).await?;
+        let existing = self
+            .find_record(&zone, &spec.name, spec.content.record_type())
+            .await?;
+        let body = RecordRequest {
+            zone_id: &zone.id,
+            record_type: spec.content.record_type().to_string(),
+            name: relative_name(&spec.name, &zone.name),
+            value: spec.content.to_string(),
+            ttl: spec.ttl.unwrap_or(DEFAULT_TTL),
+        };
+        let request = match &existing {
+            Some(record) => self.request(reqwest::Method::PUT, &format!("/records/{}", record.id)),
+            None => self.request(reqwest::Method::POST, "/records"),
+        };
+        let response: RecordResponse = request
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        log::info!("Hetzner record updated: {:#?}", response.record);
+        to_record(response.record, &zone, spec.content.record_type())
+    }
+
+    async fn delete_record(&self, record: &Record) -> Result<()> {
+        self.request(reqwest::Method::DELETE, &format!("/records/{}", record.id))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn zone() -> Zone {
+        Zone {
+            id: "zone".to_string(),
+            name: "example.com".to_string(),
+        }
+    }
+
+    #[test]
+    fn names_records_relative_to_the_zone() {
+        assert_eq!(relative_name("example.com", "example.com"), "@");
+        assert_eq!(relative_name("home.example.com", "example.com"), "home");
+        assert_eq!(relative_name("a.b.example.com", "example.com"), "a.b");
+        assert_eq!(
+            relative_name("home.example.net", "example.com"),
+            "home.example.net"
+        );
+    }
+
+    #[test]
+    fn reads_records_from_the_response() {
+        let response: RecordsResponse = serde_json::from_value(json!({
+            "records": [
+                { "id": "r1", "type": "A", "name": "@", "value": "192.0.2.1", "ttl": 60, "zone_id": "zone" },
+                { "id": "r2", "type": "AAAA", "name": "home", "value": "2001:db8::1", "zone_id": "zone" }
+            ]
+        }))
+        .unwrap();
+        let mut records = response.records.into_iter();
+        let apex = to_record(records.next().unwrap(), &zone(), RecordType::A).unwrap();
+        assert_eq!(apex.name, "example.com");
+        assert_eq!(apex.content, RecordContent::A("192.0.2.1".parse().unwrap()));
+        assert_eq!(apex.ttl, 60);
+        let home = to_record(records.next().unwrap(), &zone(), RecordType::Aaaa).unwrap();
+        assert_eq!(home.name, "home.example.com");
+        assert_eq!(home.ttl, DEFAULT_TTL);
+    }
+
+    #[test]
+    fn rejects_values_of_the_wrong_type() {
+        let record: HetznerRecord = serde_json::from_value(json!({
+            "id": "r1", "type": "A", "name": "home", "value": "not an address"
+        }))
+        .unwrap();
+        assert!(to_record(record, &zone(), RecordType::A).is_err());
+    }
+}
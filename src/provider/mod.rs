@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::config::{Config, ProviderKind};
+use crate::plugin;
+use crate::state::State;
+
+pub mod cloudflare;
+pub mod dyndns2;
+pub mod hetzner;
+pub mod porkbun;
+pub mod powerdns;
+pub mod rfc2136;
+pub mod route53;
+
+/// How often file-based credentials are checked for changes.
+const RELOAD_EVERY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordType {
+    A,
+    Aaaa,
+    Cname,
+    Txt,
+}
+
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordType::A => write!(f, "A"),
+            RecordType::Aaaa => write!(f, "AAAA"),
+            RecordType::Cname => write!(f, "CNAME"),
+            RecordType::Txt => write!(f, "TXT"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecordContent {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Txt(String),
+}
+
+impl RecordContent {
+    pub fn record_type(&self) -> RecordType {
+        match self {
+            RecordContent::A(_) => RecordType::A,
+            RecordContent::Aaaa(_) => RecordType::Aaaa,
+            RecordContent::Cname(_) => RecordType::Cname,
+            RecordContent::Txt(_) => RecordType::Txt,
+        }
+    }
+
+    /// Content given by the user, typed by its shape: an IPv4 or IPv6
+    /// address, a host name for a CNAME, and anything else as TXT.
+    pub fn infer(value: &str) -> Self {
+        if let Ok(ip) = value.parse::<IpAddr>() {
+            return RecordContent::from(ip);
+        }
+        let host = value.trim_end_matches('.');
+        let is_host = host.contains('.')
+            && host.split('.').all(|label| {
+                !label.is_empty()
+                    && label
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            });
+        if is_host {
+            RecordContent::Cname(host.to_string())
+        } else {
+            RecordContent::Txt(value.to_string())
+        }
+    }
+
+    /// Reads a provider's value for a record of `record_type` back.
+    pub fn parse(record_type: RecordType, value: &str) -> Result<Self> {
+        Ok(match record_type {
+            RecordType::A | RecordType::Aaaa => RecordContent::from(value.parse::<IpAddr>()?),
+            RecordType::Cname => RecordContent::Cname(value.trim_end_matches('.').to_string()),
+            RecordType::Txt => RecordContent::Txt(value.to_string()),
+        })
+    }
+
+    /// The address of an A or AAAA record.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            RecordContent::A(ip) => Some(IpAddr::V4(*ip)),
+            RecordContent::Aaaa(ip) => Some(IpAddr::V6(*ip)),
+            RecordContent::Cname(_) | RecordContent::Txt(_) => None,
+        }
+    }
+}
+
+impl From<IpAddr> for RecordContent {
+    fn from(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => RecordContent::A(ip),
+            IpAddr::V6(ip) => RecordContent::Aaaa(ip),
+        }
+    }
+}
+
+impl fmt::Display for RecordContent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordContent::A(ip) => write!(f, "{}", ip),
+            RecordContent::Aaaa(ip) => write!(f, "{}", ip),
+            RecordContent::Cname(host) => write!(f, "{}", host),
+            RecordContent::Txt(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// Checks a record name before any provider sees it. A `*` is only allowed
+/// as the whole leftmost label of a name below a zone, e.g. `*.example.com`.
+pub fn validate_name(name: &str) -> Result<()> {
+    let labels: Vec<&str> = name.trim_end_matches('.').split('.').collect();
+    if labels.iter().any(|label| label.is_empty()) {
+        bail!("Invalid record name {:?}", name);
+    }
+    for (i, label) in labels.iter().enumerate() {
+        if label.contains('*') && (i > 0 || *label != "*") {
+            bail!(
+                "Invalid wildcard {:?}, only a leading \"*.\" label is allowed",
+                name
+            );
+        }
+    }
+    if labels[0] == "*" && labels.len() < 3 {
+        bail!(
+            "Wildcard {:?} must sit below a zone, e.g. *.example.com",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// TXT `text` as one quoted zone file string, for providers that take
+/// record data in that syntax.
+pub fn quote_txt(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The text of a string quoted by [`quote_txt`]; unquoted input is kept as is.
+pub fn unquote_txt(quoted: &str) -> String {
+    let Some(inner) = quoted.strip_prefix('"').and_then(|q| q.strip_suffix('"')) else {
+        return quoted.to_string();
+    };
+    let mut text = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+/// Whether `name` is a wildcard such as `*.example.com`.
+pub fn is_wildcard(name: &str) -> bool {
+    name.starts_with("*.")
+}
+
+/// A record as it currently exists at the provider.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub id: String,
+    pub name: String,
+    pub content: RecordContent,
+    pub ttl: u32,
+    pub proxied: bool,
+}
+
+/// The desired state of a record. A `ttl` of `None` leaves the choice to the provider.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordSpec {
+    pub name: String,
+    pub content: RecordContent,
+    pub ttl: Option<u32>,
+    pub proxied: bool,
+    /// Written along with the record where the provider supports it, but
+    /// never a reason to write on its own.
+    pub comment: Option<String>,
+}
+
+impl RecordSpec {
+    pub fn matches(&self, record: &Record) -> bool {
+        self.content == record.content
+            && self.ttl.is_none_or(|ttl| ttl == record.ttl)
+            && self.proxied == record.proxied
+    }
+}
+
+/// A DNS backend the update engine can drive. Implementations are responsible
+/// for locating the zone a name belongs to; callers only deal in record names.
+#[async_trait]
+pub trait DnsProvider: Send + Sync {
+    /// Whether records can be proxied (Cloudflare's orange cloud).
+    fn supports_proxy(&self) -> bool {
+        false
+    }
+
+    async fn get_record(&self, name: &str, record_type: RecordType) -> Result<Option<Record>>;
+
+    /// Looks several records up at once, in the order of `names`.
+    async fn get_records(
+        &self,
+        names: &[String],
+        record_type: RecordType,
+    ) -> Result<Vec<Option<Record>>> {
+        let mut records = Vec::with_capacity(names.len());
+        for name in names {
+            records.push(self.get_record(name, record_type).await?);
+        }
+        Ok(records)
+    }
+
+    async fn upsert_record(&self, spec: &RecordSpec) -> Result<Record>;
+
+    /// Writes several records, in as few requests as the backend allows.
+    /// `existing` holds what `get_records` found for each spec, so backends
+    /// needn't look the records up again. Returns the written records in the
+    /// order of `specs`.
+    async fn upsert_records(
+        &self,
+        specs: &[RecordSpec],
+        _existing: &[Option<Record>],
+    ) -> Result<Vec<Record>> {
+        let mut records = Vec::with_capacity(specs.len());
+        for spec in specs {
+            records.push(self.upsert_record(spec).await?);
+        }
+        Ok(records)
+    }
+
+    async fn delete_record(&self, record: &Record) -> Result<()>;
+
+    /// Every record of `record_type` at `name`, for names holding a record set.
+    async fn list_records(&self, name: &str, record_type: RecordType) -> Result<Vec<Record>> {
+        Ok(self
+            .get_record(name, record_type)
+            .await?
+            .into_iter()
+            .collect())
+    }
+
+    /// Adds a record next to any that already exist at the same name.
+    async fn create_record(&self, spec: &RecordSpec) -> Result<Record> {
+        bail!("This provider can't keep several records for {}", spec.name)
+    }
+
+    /// Rewrites one specific member of a record set.
+    async fn update_record(&self, record: &Record, spec: &RecordSpec) -> Result<Record> {
+        let _ = record;
+        bail!("This provider can't keep several records for {}", spec.name)
+    }
+
+    /// The authoritative nameservers of the zone holding `name`, if the provider knows them.
+    async fn nameservers(&self, _name: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Re-reads credentials kept outside the config, e.g. a rotated token
+    /// file, once their refresh is due or right away when `force` is set.
+    async fn reload_credentials(&self, _force: bool) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub async fn build(kind: ProviderKind, config: &Config) -> Result<Arc<dyn DnsProvider>> {
+    let provider: Arc<dyn DnsProvider> = match kind {
+        ProviderKind::Cloudflare => Arc::new(cloudflare::Cloudflare::from_config(config).await?),
+        ProviderKind::Route53 => {
+            Arc::new(route53::Route53::new(&config.route53.clone().unwrap_or_default()).await?)
+        }
+        ProviderKind::Hetzner => {
+            let api_token = config
+                .hetzner
+                .as_ref()
+                .and_then(|c| c.api_token.as_ref())
+                .context("Hetzner API token is not configured")?;
+            Arc::new(hetzner::Hetzner::new(api_token))
+        }
+        ProviderKind::DynDns2 => {
+            let dyndns2 = config
+                .dyndns2
+                .as_ref()
+                .context("DynDNS2 credentials are not configured")?;
+            Arc::new(dyndns2::DynDns2::new(dyndns2))
+        }
+        ProviderKind::Rfc2136 => {
+            let rfc2136 = config
+                .rfc2136
+                .as_ref()
+                .context("RFC 2136 server is not configured")?;
+            Arc::new(rfc2136::Rfc2136::new(rfc2136))
+        }
+        ProviderKind::PowerDns => {
+            let powerdns = config
+                .powerdns
+                .as_ref()
+                .context("PowerDNS API is not configured")?;
+            Arc::new(powerdns::PowerDns::new(powerdns))
+        }
+        ProviderKind::Porkbun => {
+            let porkbun = config
+                .porkbun
+                .as_ref()
+                .context("Porkbun API keys are not configured")?;
+            Arc::new(porkbun::Porkbun::new(porkbun))
+        }
+        ProviderKind::Plugin => {
+            let plugin = config
+                .plugins
+                .provider
+                .as_ref()
+                .context("[plugins.provider] is not configured")?;
+            Arc::new(plugin::Plugin::new(plugin))
+        }
+    };
+    Ok(provider)
+}
+
+/// Builds each provider at most once so domains sharing a backend share its client.
+#[derive(Default)]
+pub struct Providers {
+    built: HashMap<ProviderKind, Arc<dyn DnsProvider>>,
+}
+
+impl Providers {
+    pub async fn get(
+        &mut self,
+        kind: ProviderKind,
+        config: &Config,
+    ) -> Result<Arc<dyn DnsProvider>> {
+        if let Some(provider) = self.built.get(&kind) {
+            return Ok(Arc::clone(provider));
+        }
+        let provider = build(kind, config).await?;
+        self.built.insert(kind, Arc::clone(&provider));
+        Ok(provider)
+    }
+
+    pub fn all(&self) -> Vec<Arc<dyn DnsProvider>> {
+        self.built.values().cloned().collect()
+    }
+}
+
+/// Checks external credentials every [`RELOAD_EVERY`] and reloads them all
+/// on SIGHUP (where there is one) or a `reload` over the control socket or
+/// pipe, so a rotated token is picked up without restarting.
+pub async fn watch_credentials(
+    providers: Vec<Arc<dyn DnsProvider>>,
+    state: Arc<State>,
+) -> Result<()> {
+    #[cfg(unix)]
+    let mut hangup = signal(SignalKind::hangup())?;
+    loop {
+        #[cfg(unix)]
+        let hangup = hangup.recv();
+        #[cfg(not(unix))]
+        let hangup = std::future::pending::<Option<()>>();
+        let force = tokio::select! {
+            _ = hangup => {
+                log::info!("SIGHUP received, reloading credentials");
+                true
+            }
+            _ = state.reload_requested() => {
+                log::info!("Reload requested, reloading credentials");
+                true
+            }
+            _ = tokio::time::sleep(RELOAD_EVERY) => false,
+        };
+        for provider in &providers {
+            if let Err(e) = provider.reload_credentials(force).await {
+                log::warn!("Failed to reload credentials: {:#}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::OnceCell;
+
+use super::{DnsProvider, Record, RecordContent, RecordSpec, RecordType};
+use crate::config::PorkbunConfig;
+use crate::error::CfbindError;
+use crate::http;
+use crate::secret::Secret;
+
+const API_BASE: &str = "https://api.porkbun.com/api/json/v3";
+/// Porkbun's shortest TTL, and its default.
+const MIN_TTL: u32 = 600;
+
+#[derive(Debug, Deserialize)]
+struct Status {
+    status: String,
+    message: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Domain {
+    domain: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DomainsResponse {
+    #[serde(default)]
+    domains: Vec<Domain>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PorkbunRecord {
+    id: String,
+    name: String,
+    content: String,
+    ttl: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordsResponse {
+    #[serde(default)]
+    records: Vec<PorkbunRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateResponse {
+    id: Value,
+}
+
+/// Porkbun names records relative to the domain, with an empty name for the apex.
+fn subdomain<'a>(name: &'a str, domain: &str) -> &'a str {
+    if name == domain {
+        ""
+    } else {
+        name.strip_suffix(domain)
+            .and_then(|n| n.strip_suffix('.'))
+            .unwrap_or(name)
+    }
+}
+
+fn to_record(record: PorkbunRecord, record_type: RecordType) -> Result<Record> {
+    Ok(Record {
+        id: record.id,
+        name: record.name,
+        content: RecordContent::parse(record_type, &record.content)?,
+        ttl: record
+            .ttl
+            .and_then(|ttl| ttl.parse().ok())
+            .unwrap_or(MIN_TTL),
+        proxied: false,
+    })
+}
+
+/// The Porkbun v3 API. Every call is a `POST` carrying the key pair in its body.
+pub struct Porkbun {
+    client: Client,
+    api_key: Secret,
+    secret_api_key: Secret,
+    domains: OnceCell<Vec<String>>,
+}
+
+impl Porkbun {
+    pub fn new(config: &PorkbunConfig) -> Self {
+        Porkbun {
+            client: http::client(),
+            api_key: config.api_key.clone(),
+            secret_api_key: config.secret_api_key.clone(),
+            domains: OnceCell::new(),
+        }
+    }
+
+    /// Calls `path` with `body` plus the credentials.
+    async fn call<T: DeserializeOwned>(&self, path: &str, mut body: Value) -> Result<T> {
+        body["apikey"] = json!(self.api_key.expose());
+        body["secretapikey"] = json!(self.secret_api_key.expose());
+        let response = self
+            .client
+            .post(format!("{}{}", API_BASE, path))
+            .json(&body)
+            .send()
+            .await
+            .map_err(CfbindError::from)?;
+        let code = response.status();
+        let text = response.text().await.map_err(CfbindError::from)?;
+        let status: Status =
+            serde_json::from_str(&text).map_err(|e| CfbindError::Parse(e.into()))?;
+        if status.status != "SUCCESS" {
+            let message = status.message.unwrap_or_else(|| code.to_string());
+            let error = anyhow!("Porkbun: {}", message);
+            return Err(if message.contains("API key") || code.as_u16() == 403 {
+                CfbindError::Auth(error)
+            } else if code.as_u16() == 429 {
+                CfbindError::RateLimited(error)
+            } else {
+                CfbindError::Api(error)
+            }
+            .into());
+        }
+        Ok(serde_json::from_str(&text).map_err(|e| CfbindError::Parse(e.into()))?)
+    }
+
+    /// Every domain of the account, listed once.
+    async fn domains(&self) -> Result<&Vec<String>> {
+        self.domains
+            .get_or_try_init(|| async {
+                let response: DomainsResponse = self.call("/domain/listAll", json!({})).await?;
+                let domains = response.domains.into_iter().map(|d| d.domain).collect();
+                Ok::<_, anyhow::Error>(domains)
+            })
+            .await
+    }
+
+    /// The most specific domain of the account containing `name`.
+    async fn find_domain(&self, name: &str) -> Result<String> {
+        let bare = name.strip_prefix("*.").unwrap_or(name);
+        self.domains()
+            .await?
+            .iter()
+            .filter(|d| bare == d.as_str() || bare.ends_with(&format!(".{}", d)))
+            .max_by_key(|d| d.len())
+            .cloned()
+            .ok_or_else(|| CfbindError::ZoneNotFound(name.to_string()).into())
+    }
+
+    async fn records(
+        &self,
+        domain: &str,
+        name: &str,
+        record_type: RecordType,
+    ) -> Result<Vec<Record>> {
+        let path = format!(
+            "/dns/retrieveByNameType/{}/{}/{}",
+            domain,
+            record_type,
+            subdomain(name, domain)
+        );
+        let response: RecordsResponse = self.call(&path, json!({})).await?;
+        response
+            .records
+            .into_iter()
+            .map(|r| to_record(r, record_type))
+            .collect()
+    }
+
+    fn body(spec: &RecordSpec, domain: &str) -> Value {
+        json!({
+            "name": subdomain(&spec.name, domain),
+            "type": spec.content.record_type().to_string(),
+            "content": spec.content.to_string(),
+            "ttl": spec.ttl.unwrap_or(MIN_TTL).max(MIN_TTL).to_string(),
+        })
+    }
+
+    fn written(id: String, spec: &RecordSpec) -> Record {
+        Record {
+            id,
+            name: spec.name.clone(),
+            content: spec.content.clone(),
+            ttl: spec.ttl.unwrap_or(MIN_TTL).max(MIN_TTL),
+            proxied: false,
+        }
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Porkbun {
+    async fn get_record(&self, name: &str, record_type: RecordType) -> Result<Option<Record>> {
+        let domain = self.find_domain(name).await?;
+        Ok(self
+            .records(&domain, name, record_type)
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    async fn upsert_record(&self, spec: &RecordSpec) -> Result<Record> {
+        let domain = self.find_domain(&spec.name).await?;
+        let existing = self
+            .records(&domain, &spec.name, spec.content.record_type())
+            .await?;
+        match existing.into_iter().next() {
+            Some(record) => self.update_record(&record, spec).await,
+            None => self.create_record(spec).await,
+        }
+    }
+
+    async fn delete_record(&self, record: &Record) -> Result<()> {
+        let domain = self.find_domain(&record.name).await?;
+        let _: Status = self
+            .call(&format!("/dns/delete/{}/{}", domain, record.id), json!({}))
+            .await?;
+        Ok(())
+    }
+
+    async fn list_records(&self, name: &str, record_type: RecordType) -> Result<Vec<Record>> {
+        let domain = self.find_domain(name).await?;
+        self.records(&domain, name, record_type).await
+    }
+
+    async fn create_record(&self, spec: &RecordSpec) -> Result<Record> {
+        let domain = self.find_domain(&spec.name).await?;
+        let created: CreateResponse = self
+            .call(
+                &format!("/dns/create/{}", domain),
+                Self::body(spec, &domain),
+            )
+            .await?;
+        // The ID comes back as a number.
+        let id = match created.id {
+            Value::String(id) => id,
+            id => id.to_string(),
+        };
+        log::info!("Porkbun record created: {} -> {}", spec.name, spec.content);
+        Ok(Self::written(id, spec))
+    }
+
+    async fn update_record(&self, record: &Record, spec: &RecordSpec) -> Result<Record> {
+        let domain = self.find_domain(&spec.name).await?;
+        let _: Status = self
+            .call(
+                &format!("/dns/edit/{}/{}", domain, record.id),
+                Self::body(spec, &domain),
+            )
+            .await?;
+        log::info!("Porkbun record updated: {} -> {}", spec.name, spec.content);
+        Ok(Self::written(record.id.clone(), spec))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(ttl: Option<u32>) -> RecordSpec {
+        RecordSpec {
+            name: "home.example.com".to_string(),
+            content: RecordContent::A("192.0.2.1".parse().unwrap()),
+            ttl,
+            proxied: false,
+            comment: None,
+        }
+    }
+
+    #[test]
+    fn names_records_relative_to_the_domain() {
+        assert_eq!(subdomain("example.com", "example.com"), "");
+        assert_eq!(subdomain("home.example.com", "example.com"), "home");
+        assert_eq!(subdomain("*.example.com", "example.com"), "*");
+    }
+
+    #[test]
+    fn reads_records_from_the_response() {
+        let response: RecordsResponse = serde_json::from_value(json!({
+            "status": "SUCCESS",
+            "records": [
+                { "id": "106926659", "name": "home.example.com", "type": "A", "content": "192.0.2.1", "ttl": "900", "prio": "0" },
+                { "id": "106926660", "name": "home.example.com", "type": "A", "content": "192.0.2.2" }
+            ]
+        }))
+        .unwrap();
+        let records: Vec<Record> = response
+            .records
+            .into_iter()
+            .map(|r| to_record(r, RecordType::A).unwrap())
+            .collect();
+        assert_eq!(records[0].id, "106926659");
+        assert_eq!(records[0].ttl, 900);
+        assert_eq!(
+            records[1].content,
+            RecordContent::A("192.0.2.2".parse().unwrap())
+        );
+        assert_eq!(records[1].ttl, MIN_TTL);
+    }
+
+    #[test]
+    fn reads_an_error_status() {
+        let status: Status = serde_json::from_value(json!({
+            "status": "ERROR",
+            "message": "Invalid API key. (002)"
+        }))
+        .unwrap();
+        assert_eq!(status.status, "ERROR");
+        assert_eq!(status.message.as_deref(), Some("Invalid API key. (002)"));
+    }
+
+    #[test]
+    fn raises_short_ttls_to_the_minimum() {
+        let body = Porkbun::body(&spec(Some(60)), "example.com");
+        assert_eq!(body["name"], "home");
+        assert_eq!(body["type"], "A");
+        assert_eq!(body["ttl"], MIN_TTL.to_string());
+        let body = Porkbun::body(&spec(Some(3600)), "example.com");
+        assert_eq!(body["ttl"], "3600");
+        assert_eq!(Porkbun::written("1".to_string(), &spec(None)).ttl, MIN_TTL);
+    }
+}
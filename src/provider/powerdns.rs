@@ -0,0 +1,532 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use reqwest::{Client, Method, Response};
+use serde::{Deserialize, Serialize};
+
+use super::{quote_txt, unquote_txt, DnsProvider, Record, RecordContent, RecordSpec, RecordType};
+use crate::config::PowerDnsConfig;
+use crate::error::CfbindError;
+use crate::http;
+use crate::secret::Secret;
+
+const DEFAULT_TTL: u32 = 300;
+
+#[derive(Debug, Deserialize)]
+struct Zone {
+    id: String,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ZoneDetails {
+    #[serde(default)]
+    rrsets: Vec<RrSet>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct RrSet {
+    name: String,
+    #[serde(rename = "type")]
+    record_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
+    #[serde(skip_deserializing)]
+    changetype: &'static str,
+    #[serde(default)]
+    records: Vec<RrRecord>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct RrRecord {
+    content: String,
+    #[serde(default)]
+    disabled: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Patch {
+    rrsets: Vec<RrSet>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    error: String,
+}
+
+/// PowerDNS keeps names absolute, with the trailing dot.
+fn canonical(name: &str) -> String {
+    format!("{}.", name.trim_end_matches('.'))
+}
+
+/// `content` the way PowerDNS stores it.
+fn to_content(content: &RecordContent) -> String {
+    match content {
+        RecordContent::A(_) | RecordContent::Aaaa(_) => content.to_string(),
+        RecordContent::Cname(host) => canonical(host),
+        RecordContent::Txt(text) => quote_txt(text),
+    }
+}
+
+fn from_content(record_type: RecordType, content: &str) -> Result<RecordContent> {
+    match record_type {
+        RecordType::Txt => Ok(RecordContent::Txt(unquote_txt(content))),
+        _ => RecordContent::parse(record_type, content),
+    }
+}
+
+fn to_records(name: &str, rrset: &RrSet, record_type: RecordType) -> Result<Vec<Record>> {
+    rrset
+        .records
+        .iter()
+        .filter(|r| !r.disabled)
+        .map(|r| {
+            Ok(Record {
+                id: r.content.clone(),
+                name: name.to_string(),
+                content: from_content(record_type, &r.content)?,
+                ttl: rrset.ttl.unwrap_or(DEFAULT_TTL),
+                proxied: false,
+            })
+        })
+        .collect()
+}
+
+/// Turns a rejected request into an error carrying PowerDNS's own message.
+async fn check(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let message = match response.json::<ApiError>().await {
+        Ok(body) => body.error,
+        Err(_) => status.to_string(),
+    };
+    let error = anyhow!("PowerDNS answered {}: {}", status, message);
+    Err(match status.as_u16() {
+        401 | 403 => CfbindError::Auth(error),
+        404 => CfbindError::RecordNotFound(error),
+        _ => CfbindError::Api(error),
+    }
+    .into())
+}
+
+/// The PowerDNS Authoritative Server HTTP API. Writes replace whole RRsets
+/// with `PATCH`, one request per zone.
+pub struct PowerDns {
+    client: Client,
+    base: String,
+    api_key: Secret,
+}
+
+impl PowerDns {
+    pub fn new(config: &PowerDnsConfig) -> Self {
+        PowerDns {
+            client: http::client(),
+            base: format!(
+                "{}/api/v1/servers/{}",
+                config.url.trim_end_matches('/'),
+                config.server_id
+            ),
+            api_key: config.api_key.clone(),
+        }
+    }
+
+    fn request(&self, method: Method, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, format!("{}{}", self.base, path))
+            .header("X-API-Key", self.api_key.expose())
+    }
+
+    /// Finds the most specific zone containing `name` by walking up its labels.
+    async fn find_zone(&self, name: &str) -> Result<Zone> {
+        let name = name.strip_prefix("*.").unwrap_or(name);
+        let labels: Vec<&str> = name.trim_end_matches('.').split('.').collect();
+        for i in 0..labels.len().saturating_sub(1) {
+            let candidate = canonical(&labels[i..].join("."));
+            let response = self
+                .request(Method::GET, "/zones")
+                .query(&[("zone", candidate.as_str())])
+                .send()
+                .await?;
+            let zones: Vec<Zone> = check(response).await?.json().await?;
+            if let Some(zone) = zones.into_iter().find(|z| z.name == candidate) {
+                return Ok(zone);
+            }
+        }
+        Err(CfbindError::ZoneNotFound(name.to_string()).into())
+    }
+
+    /// The RRset of `record_type` at `name`, if the zone has one.
+    async fn rrset(
+        &self,
+        zone: &Zone,
+        name: &str,
+        record_type: RecordType,
+    ) -> Result<Option<RrSet>> {
+        let response = self
+            .request(Method::GET, &format!("/zones/{}", zone.id))
+            .query(&[
+                ("rrsets", "true"),
+                ("rrset_name", canonical(name).as_str()),
+                ("rrset_type", record_type.to_string().as_str()),
+            ])
+            .send()
+            .await?;
+        let details: ZoneDetails = check(response).await?.json().await?;
+        // Older servers ignore the filters and send the whole zone.
+        let name = canonical(name);
+        let record_type = record_type.to_string();
+        Ok(details
+            .rrsets
+            .into_iter()
+            .find(|r| r.name == name && r.record_type == record_type))
+    }
+
+    async fn patch(&self, zone: &Zone, rrsets: Vec<RrSet>) -> Result<()> {
+        let response = self
+            .request(Method::PATCH, &format!("/zones/{}", zone.id))
+            .json(&Patch { rrsets })
+            .send()
+            .await?;
+        check(response).await?;
+        Ok(())
+    }
+
+    /// Replaces the RRset at `name` with `records`, or deletes it when empty.
+    async fn replace(
+        &self,
+        zone: &Zone,
+        name: &str,
+        record_type: RecordType,
+        ttl: u32,
+        records: Vec<RrRecord>,
+    ) -> Result<()> {
+        self.patch(zone, vec![rrset(name, record_type, ttl, records)])
+            .await
+    }
+}
+
+fn rrset(name: &str, record_type: RecordType, ttl: u32, records: Vec<RrRecord>) -> RrSet {
+    RrSet {
+        name: canonical(name),
+        record_type: record_type.to_string(),
+        ttl: Some(ttl),
+        changetype: if records.is_empty() {
+            "DELETE"
+        } else {
+            "REPLACE"
+        },
+        records,
+    }
+}
+
+fn written(spec: &RecordSpec, ttl: u32) -> Record {
+    Record {
+        id: to_content(&spec.content),
+        name: spec.name.clone(),
+        content: spec.content.clone(),
+        ttl,
+        proxied: false,
+    }
+}
+
+#[async_trait]
+impl DnsProvider for PowerDns {
+    async fn get_record(&self, name: &str, record_type: RecordType) -> Result<Option<Record>> {
+        Ok(self
+            .list_records(name, record_type)
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    async fn upsert_record(&self, spec: &RecordSpec) -> Result<Record> {
+        Ok(self
+            .upsert_records(std::slice::from_ref(spec), &[None])
+            .await?
+            .remove(0))
+    }
+
+    async fn upsert_records(
+        &self,
+        specs: &[RecordSpec],
+        _existing: &[Option<Record>],
+    ) -> Result<Vec<Record>> {
+        // One PATCH per zone; PowerDNS applies all of its RRsets or none.
+        let mut groups: Vec<(Zone, Vec<RrSet>)> = Vec::new();
+        let mut records = Vec::with_capacity(specs.len());
+        for spec in specs {
+            let zone = self.find_zone(&spec.name).await?;
+            let ttl = spec.ttl.unwrap_or(DEFAULT_TTL);
+            let set = rrset(
+                &spec.name,
+                spec.content.record_type(),
+                ttl,
+                vec![RrRecord {
+                    content: to_content(&spec.content),
+                    disabled: false,
+                }],
+            );
+            match groups.iter_mut().find(|(z, _)| z.id == zone.id) {
+                Some((_, sets)) => sets.push(set),
+                None => groups.push((zone, vec![set])),
+            }
+            records.push(written(spec, ttl));
+        }
+        for (zone, sets) in groups {
+            log::info!("Writing {} RRsets to {}", sets.len(), zone.name);
+            self.patch(&zone, sets).await?;
+        }
+        Ok(records)
+    }
+
+    async fn delete_record(&self, record: &Record) -> Result<()> {
+        let record_type = record.content.record_type();
+        let zone = self.find_zone(&record.name).await?;
+        let Some(set) = self.rrset(&zone, &record.name, record_type).await? else {
+            return Ok(());
+        };
+        let content = to_content(&record.content);
+        let ttl = set.ttl.unwrap_or(DEFAULT_TTL);
+        let rest = set
+            .records
+            .into_iter()
+            .filter(|r| r.content != content)
+            .collect();
+        self.replace(&zone, &record.name, record_type, ttl, rest)
+            .await
+    }
+
+    async fn list_records(&self, name: &str, record_type: RecordType) -> Result<Vec<Record>> {
+        let zone = self.find_zone(name).await?;
+        match self.rrset(&zone, name, record_type).await? {
+            Some(set) => to_records(name, &set, record_type),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn create_record(&self, spec: &RecordSpec) -> Result<Record> {
+        let record_type = spec.content.record_type();
+        let zone = self.find_zone(&spec.name).await?;
+        let ttl = spec.ttl.unwrap_or(DEFAULT_TTL);
+        let mut records = match self.rrset(&zone, &spec.name, record_type).await? {
+            Some(set) => set.records,
+            None => Vec::new(),
+        };
+        records.push(RrRecord {
+            content: to_content(&spec.content),
+            disabled: false,
+        });
+        self.replace(&zone, &spec.name, record_type, ttl, records)
+            .await?;
+        Ok(written(spec, ttl))
+    }
+
+    async fn update_record(&self, record: &Record, spec: &RecordSpec) -> Result<Record> {
+        let record_type = spec.content.record_type();
+        let zone = self.find_zone(&spec.name).await?;
+        let ttl = spec.ttl.unwrap_or(DEFAULT_TTL);
+        let mut records: Vec<RrRecord> = match self.rrset(&zone, &spec.name, record_type).await? {
+            Some(set) => set.records,
+            None => Vec::new(),
+        };
+        records.retain(|r| r.content != record.id);
+        records.push(RrRecord {
+            content: to_content(&spec.content),
+            disabled: false,
+        });
+        self.replace(&zone, &spec.name, record_type, ttl, records)
+            .await?;
+        Ok(written(spec, ttl))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    const ZONES: &str = "/api/v1/servers/localhost/zones";
+
+    /// A mock server holding the zone `example.com.`.
+    async fn server() -> (MockServer, PowerDns) {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(ZONES))
+            .and(header("X-API-Key", "key"))
+            .and(query_param("zone", "example.com."))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!([{ "id": "example.com.", "name": "example.com." }])),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(ZONES))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([])))
+            .mount(&server)
+            .await;
+        let powerdns = PowerDns::new(&PowerDnsConfig {
+            url: format!("{}/", server.uri()),
+            api_key: Secret::new("key"),
+            server_id: "localhost".to_string(),
+        });
+        (server, powerdns)
+    }
+
+    fn zone_path() -> String {
+        format!("{}/example.com.", ZONES)
+    }
+
+    fn spec(name: &str, content: RecordContent) -> RecordSpec {
+        RecordSpec {
+            name: name.to_string(),
+            content,
+            ttl: None,
+            proxied: false,
+            comment: None,
+        }
+    }
+
+    #[test]
+    fn stores_content_the_way_powerdns_does() {
+        assert_eq!(
+            to_content(&RecordContent::Cname("target.example.net".to_string())),
+            "target.example.net."
+        );
+        let text = RecordContent::Txt(r#"a "b" c"#.to_string());
+        assert_eq!(to_content(&text), r#""a \"b\" c""#);
+        assert_eq!(
+            from_content(RecordType::Txt, &to_content(&text)).unwrap(),
+            text
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_the_enabled_records_of_an_rrset() {
+        let (server, powerdns) = server().await;
+        Mock::given(method("GET"))
+            .and(path(zone_path()))
+            .and(query_param("rrset_name", "txt.example.com."))
+            .and(query_param("rrset_type", "TXT"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "rrsets": [{
+                    "name": "txt.example.com.",
+                    "type": "TXT",
+                    "ttl": 120,
+                    "records": [
+                        { "content": "\"off\"", "disabled": true },
+                        { "content": "\"say \\\"hi\\\"\"", "disabled": false }
+                    ]
+                }]
+            })))
+            .mount(&server)
+            .await;
+        let records = powerdns
+            .list_records("txt.example.com", RecordType::Txt)
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].content,
+            RecordContent::Txt(r#"say "hi""#.to_string())
+        );
+        assert_eq!(records[0].ttl, 120);
+    }
+
+    #[tokio::test]
+    async fn skips_rrsets_of_other_names() {
+        let (server, powerdns) = server().await;
+        // As sent by servers that ignore the rrset filters.
+        Mock::given(method("GET"))
+            .and(path(zone_path()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "rrsets": [{
+                    "name": "other.example.com.",
+                    "type": "A",
+                    "records": [{ "content": "192.0.2.9", "disabled": false }]
+                }]
+            })))
+            .mount(&server)
+            .await;
+        let record = powerdns
+            .get_record("home.example.com", RecordType::A)
+            .await
+            .unwrap();
+        assert!(record.is_none());
+    }
+
+    #[tokio::test]
+    async fn writes_a_zone_in_one_patch() {
+        let (server, powerdns) = server().await;
+        Mock::given(method("PATCH"))
+            .and(path(zone_path()))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let specs = [
+            spec(
+                "a.example.com",
+                RecordContent::A("192.0.2.1".parse().unwrap()),
+            ),
+            spec(
+                "b.example.com",
+                RecordContent::A("192.0.2.1".parse().unwrap()),
+            ),
+        ];
+        let records = powerdns
+            .upsert_records(&specs, &[None, None])
+            .await
+            .unwrap();
+        assert_eq!(records[1].name, "b.example.com");
+        assert_eq!(records[1].ttl, DEFAULT_TTL);
+
+        let requests = server.received_requests().await.unwrap();
+        let patch = requests
+            .iter()
+            .find(|r| r.method.as_str() == "PATCH")
+            .unwrap();
+        let body: Value = patch.body_json().unwrap();
+        assert_eq!(body["rrsets"][0]["name"], "a.example.com.");
+        assert_eq!(body["rrsets"][1]["changetype"], "REPLACE");
+        assert_eq!(body["rrsets"][1]["records"][0]["content"], "192.0.2.1");
+    }
+
+    #[tokio::test]
+    async fn reports_the_servers_error_message() {
+        let (server, powerdns) = server().await;
+        Mock::given(method("PATCH"))
+            .and(path(zone_path()))
+            .respond_with(ResponseTemplate::new(422).set_body_json(
+                json!({ "error": "RRset home.example.com. IN A: Conflicts with CNAME" }),
+            ))
+            .mount(&server)
+            .await;
+        let error = powerdns
+            .upsert_record(&spec(
+                "home.example.com",
+                RecordContent::A("192.0.2.1".parse().unwrap()),
+            ))
+            .await
+            .unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(CfbindError::Api(_))));
+        assert!(format!("{:#}", error).contains("Conflicts with CNAME"));
+    }
+
+    #[tokio::test]
+    async fn fails_for_a_name_outside_every_zone() {
+        let (_server, powerdns) = server().await;
+        let error = powerdns
+            .get_record("home.example.net", RecordType::A)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(CfbindError::ZoneNotFound(_))
+        ));
+    }
+}
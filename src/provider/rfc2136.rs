@@ -0,0 +1,331 @@
+use std::net::IpAddr;
+use std::process::Stdio;
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_resolver::proto::rr::{RData, RecordType as DnsRecordType};
+use hickory_resolver::TokioAsyncResolver;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use super::{quote_txt, DnsProvider, Record, RecordContent, RecordSpec, RecordType};
+use crate::config::Rfc2136Config;
+use crate::error::CfbindError;
+use crate::secret::Secret;
+
+const DEFAULT_TTL: u32 = 300;
+
+fn dns_type(record_type: RecordType) -> DnsRecordType {
+    match record_type {
+        RecordType::A => DnsRecordType::A,
+        RecordType::Aaaa => DnsRecordType::AAAA,
+        RecordType::Cname => DnsRecordType::CNAME,
+        RecordType::Txt => DnsRecordType::TXT,
+    }
+}
+
+/// `name` as an absolute name, so nsupdate doesn't append an origin.
+fn absolute(name: &str) -> String {
+    format!("{}.", name.trim_end_matches('.'))
+}
+
+/// `content` in zone file syntax.
+fn rdata(content: &RecordContent) -> String {
+    match content {
+        RecordContent::A(_) | RecordContent::Aaaa(_) => content.to_string(),
+        RecordContent::Cname(host) => absolute(host),
+        RecordContent::Txt(text) => quote_txt(text),
+    }
+}
+
+/// Records have no IDs in DNS; the name, type and content identify one.
+fn to_record(name: &str, content: RecordContent, ttl: u32) -> Record {
+    Record {
+        id: format!("{} {} {}", name, content.record_type(), content),
+        name: name.to_string(),
+        content,
+        ttl,
+        proxied: false,
+    }
+}
+
+/// Standard dynamic updates against a primary nameserver. Records are read
+/// straight from the primary and written by piping an update script into
+/// `nsupdate`, TSIG-signed with the configured key.
+pub struct Rfc2136 {
+    server: String,
+    port: u16,
+    zone: Option<String>,
+    key_name: String,
+    key_algorithm: String,
+    key_secret: Secret,
+    nsupdate: String,
+}
+
+impl Rfc2136 {
+    pub fn new(config: &Rfc2136Config) -> Self {
+        Rfc2136 {
+            server: config.server.clone(),
+            port: config.port,
+            zone: config.zone.clone(),
+            key_name: config.key_name.clone(),
+            key_algorithm: config.key_algorithm.clone(),
+            key_secret: config.key_secret.clone(),
+            nsupdate: config.nsupdate.clone(),
+        }
+    }
+
+    async fn server_ip(&self) -> Result<IpAddr> {
+        if let Ok(ip) = self.server.parse() {
+            return Ok(ip);
+        }
+        tokio::net::lookup_host((self.server.as_str(), self.port))
+            .await
+            .map_err(|e| CfbindError::Network(e.into()))?
+            .next()
+            .map(|addr| addr.ip())
+            .with_context(|| format!("Nameserver {} did not resolve", self.server))
+    }
+
+    /// The records of `record_type` at `name`, as the primary serves them.
+    async fn lookup(&self, name: &str, record_type: RecordType) -> Result<Vec<Record>> {
+        let group =
+            NameServerConfigGroup::from_ips_clear(&[self.server_ip().await?], self.port, true);
+        let mut opts = ResolverOpts::default();
+        opts.cache_size = 0;
+        let resolver =
+            TokioAsyncResolver::tokio(ResolverConfig::from_parts(None, vec![], group), opts);
+        let lookup = match resolver.lookup(absolute(name), dns_type(record_type)).await {
+            Ok(lookup) => lookup,
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+                return Ok(Vec::new())
+            }
+            Err(e) => return Err(CfbindError::Network(e.into()).into()),
+        };
+        let mut records = Vec::new();
+        for answer in lookup.record_iter() {
+            let content = match answer.data() {
+                Some(RData::A(a)) => RecordContent::A(a.0),
+                Some(RData::AAAA(aaaa)) => RecordContent::Aaaa(aaaa.0),
+                Some(RData::CNAME(cname)) => {
+                    RecordContent::Cname(cname.0.to_utf8().trim_end_matches('.').to_string())
+                }
+                Some(RData::TXT(txt)) => RecordContent::Txt(
+                    txt.txt_data()
+                        .iter()
+                        .map(|part| String::from_utf8_lossy(part))
+                        .collect(),
+                ),
+                // The CNAME chain followed to the records, or anything else.
+                _ => continue,
+            };
+            if content.record_type() == record_type {
+                records.push(to_record(name, content, answer.ttl()));
+            }
+        }
+        Ok(records)
+    }
+
+    /// The nsupdate input sending `updates` as one signed update message.
+    fn script(&self, updates: &[String]) -> String {
+        let mut script = format!("server {} {}\n", self.server, self.port);
+        if let Some(zone) = &self.zone {
+            script.push_str(&format!("zone {}\n", absolute(zone)));
+        }
+        // Given on stdin rather than with -y, so the secret stays out of the process list.
+        script.push_str(&format!(
+            "key {}:{} {}\n",
+            self.key_algorithm,
+            self.key_name,
+            self.key_secret.expose()
+        ));
+        for update in updates {
+            script.push_str(update);
+            script.push('\n');
+        }
+        script.push_str("send\n");
+        script
+    }
+
+    /// Sends `updates` as one signed update message.
+    async fn update(&self, updates: &[String]) -> Result<()> {
+        let script = self.script(updates);
+        let mut child = Command::new(&self.nsupdate)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run {}", self.nsupdate))?;
+        let mut stdin = child.stdin.take().context("nsupdate has no stdin")?;
+        stdin.write_all(script.as_bytes()).await?;
+        drop(stdin);
+        let output = child.wait_with_output().await?;
+        if output.status.success() {
+            return Ok(());
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        let error = anyhow!("nsupdate failed: {}", stderr);
+        // BADKEY, BADSIG and BADTIME come back as NOTAUTH, a denied update as REFUSED.
+        if stderr.contains("NOTAUTH") || stderr.contains("REFUSED") || stderr.contains("tsig") {
+            return Err(CfbindError::Auth(error).into());
+        }
+        if stderr.contains("timed out") || stderr.contains("could not reach") {
+            return Err(CfbindError::Network(error).into());
+        }
+        Err(CfbindError::Api(error).into())
+    }
+
+    fn ttl(spec: &RecordSpec) -> u32 {
+        spec.ttl.unwrap_or(DEFAULT_TTL)
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Rfc2136 {
+    async fn get_record(&self, name: &str, record_type: RecordType) -> Result<Option<Record>> {
+        Ok(self.lookup(name, record_type).await?.into_iter().next())
+    }
+
+    async fn upsert_record(&self, spec: &RecordSpec) -> Result<Record> {
+        let name = absolute(&spec.name);
+        let record_type = spec.content.record_type();
+        let ttl = Self::ttl(spec);
+        self.update(&[
+            format!("update delete {} {}", name, record_type),
+            format!(
+                "update add {} {} {} {}",
+                name,
+                ttl,
+                record_type,
+                rdata(&spec.content)
+            ),
+        ])
+        .await?;
+        Ok(to_record(&spec.name, spec.content.clone(), ttl))
+    }
+
+    async fn delete_record(&self, record: &Record) -> Result<()> {
+        let record_type = record.content.record_type();
+        self.update(&[format!(
+            "update delete {} {} {}",
+            absolute(&record.name),
+            record_type,
+            rdata(&record.content)
+        )])
+        .await
+    }
+
+    async fn list_records(&self, name: &str, record_type: RecordType) -> Result<Vec<Record>> {
+        self.lookup(name, record_type).await
+    }
+
+    async fn create_record(&self, spec: &RecordSpec) -> Result<Record> {
+        let record_type = spec.content.record_type();
+        let ttl = Self::ttl(spec);
+        self.update(&[format!(
+            "update add {} {} {} {}",
+            absolute(&spec.name),
+            ttl,
+            record_type,
+            rdata(&spec.content)
+        )])
+        .await?;
+        Ok(to_record(&spec.name, spec.content.clone(), ttl))
+    }
+
+    async fn update_record(&self, record: &Record, spec: &RecordSpec) -> Result<Record> {
+        let record_type = spec.content.record_type();
+        let ttl = Self::ttl(spec);
+        // Both in one message, so the set is never seen without either.
+        self.update(&[
+            format!(
+                "update delete {} {} {}",
+                absolute(&record.name),
+                record.content.record_type(),
+                rdata(&record.content)
+            ),
+            format!(
+                "update add {} {} {} {}",
+                absolute(&spec.name),
+                ttl,
+                record_type,
+                rdata(&spec.content)
+            ),
+        ])
+        .await?;
+        Ok(to_record(&spec.name, spec.content.clone(), ttl))
+    }
+
+    /// Only the primary, and only on the standard port, where verification looks.
+    async fn nameservers(&self, _name: &str) -> Result<Vec<String>> {
+        if self.port != 53 {
+            return Ok(Vec::new());
+        }
+        Ok(vec![self.server.clone()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rfc2136(zone: Option<&str>) -> Rfc2136 {
+        Rfc2136::new(&Rfc2136Config {
+            server: "ns1.example.com".to_string(),
+            port: 53,
+            zone: zone.map(str::to_string),
+            key_name: "cfbind".to_string(),
+            key_algorithm: "hmac-sha256".to_string(),
+            key_secret: Secret::new("c2VjcmV0LWtleQ=="),
+            nsupdate: "nsupdate".to_string(),
+        })
+    }
+
+    #[test]
+    fn writes_one_signed_message() {
+        let updates = [
+            "update delete home.example.com. A".to_string(),
+            "update add home.example.com. 300 A 192.0.2.1".to_string(),
+        ];
+        assert_eq!(
+            rfc2136(Some("example.com")).script(&updates),
+            "server ns1.example.com 53\n\
+             zone example.com.\n\
+             key hmac-sha256:cfbind c2VjcmV0LWtleQ==\n\
+             update delete home.example.com. A\n\
+             update add home.example.com. 300 A 192.0.2.1\n\
+             send\n"
+        );
+    }
+
+    #[test]
+    fn leaves_the_zone_to_nsupdate_when_unset() {
+        let script = rfc2136(None).script(&[]);
+        assert!(!script.contains("zone"));
+        assert!(script.ends_with("send\n"));
+    }
+
+    #[test]
+    fn writes_content_in_zone_file_syntax() {
+        assert_eq!(
+            rdata(&RecordContent::A("192.0.2.1".parse().unwrap())),
+            "192.0.2.1"
+        );
+        assert_eq!(
+            rdata(&RecordContent::Cname("target.example.net".to_string())),
+            "target.example.net."
+        );
+        assert_eq!(
+            rdata(&RecordContent::Txt(r#"say "hi" \o/"#.to_string())),
+            r#""say \"hi\" \\o/""#
+        );
+    }
+
+    #[test]
+    fn names_records_absolutely() {
+        assert_eq!(absolute("home.example.com"), "home.example.com.");
+        assert_eq!(absolute("home.example.com."), "home.example.com.");
+    }
+}
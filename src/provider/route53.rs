@@ -0,0 +1,223 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use aws_config::timeout::TimeoutConfig;
+use aws_config::{BehaviorVersion, Region};
+use aws_sdk_route53::types::{
+    Change, ChangeAction, ChangeBatch, ResourceRecord, ResourceRecordSet, RrType,
+};
+use aws_sdk_route53::Client;
+
+use super::{DnsProvider, Record, RecordContent, RecordSpec, RecordType};
+use crate::config::Route53Config;
+use crate::error::CfbindError;
+use crate::http;
+
+const DEFAULT_TTL: u32 = 300;
+
+fn fqdn(name: &str) -> String {
+    format!("{}.", name.trim_end_matches('.'))
+}
+
+/// Route53 returns the wildcard label octal-escaped, as `\052`.
+fn unescape(name: &str) -> String {
+    name.replace("\\052", "*")
+}
+
+fn rr_type(record_type: RecordType) -> RrType {
+    match record_type {
+        RecordType::A => RrType::A,
+        RecordType::Aaaa => RrType::Aaaa,
+        RecordType::Cname => RrType::Cname,
+        RecordType::Txt => RrType::Txt,
+    }
+}
+
+/// Route53 wants TXT values as quoted strings.
+fn to_value(content: &RecordContent) -> String {
+    match content {
+        RecordContent::Txt(text) => format!("\"{}\"", text.replace('"', "\\\"")),
+        content => content.to_string(),
+    }
+}
+
+fn from_value(record_type: RecordType, value: &str) -> Result<RecordContent> {
+    match record_type {
+        RecordType::Txt => {
+            let text = value.strip_prefix('"').and_then(|v| v.strip_suffix('"'));
+            Ok(RecordContent::Txt(
+                text.unwrap_or(value).replace("\\\"", "\""),
+            ))
+        }
+        record_type => RecordContent::parse(record_type, value),
+    }
+}
+
+pub struct Route53 {
+    client: Client,
+}
+
+impl Route53 {
+    pub async fn new(config: &Route53Config) -> Result<Self> {
+        let timeouts = TimeoutConfig::builder()
+            .connect_timeout(http::connect_timeout())
+            .operation_timeout(http::timeout())
+            .build();
+        let mut loader = aws_config::defaults(BehaviorVersion::latest()).timeout_config(timeouts);
+        if let Some(region) = &config.region {
+            loader = loader.region(Region::new(region.clone()));
+        }
+        if let Some(profile) = &config.profile {
+            loader = loader.profile_name(profile);
+        }
+        let sdk_config = loader.load().await;
+        Ok(Route53 {
+            client: Client::new(&sdk_config),
+        })
+    }
+
+    /// Finds the most specific hosted zone containing `name` by walking up its labels.
+    async fn hosted_zone_id(&self, name: &str) -> Result<String> {
+        // A wildcard label can never be a zone apex.
+        let name = name.strip_prefix("*.").unwrap_or(name);
+        let labels: Vec<&str> = name.trim_end_matches('.').split('.').collect();
+        for i in 0..labels.len().saturating_sub(1) {
+            let candidate = fqdn(&labels[i..].join("."));
+            let response = self
+                .client
+                .list_hosted_zones_by_name()
+                .dns_name(&candidate)
+                .max_items(1)
+                .send()
+                .await?;
+            if let Some(zone) = response
+                .hosted_zones()
+                .iter()
+                .find(|z| z.name() == candidate)
+            {
+                return Ok(zone.id().trim_start_matches("/hostedzone/").to_string());
+            }
+        }
+        Err(CfbindError::ZoneNotFound(name.to_string()).into())
+    }
+
+    async fn change(&self, action: ChangeAction, zone_id: &str, record: &Record) -> Result<()> {
+        let record_set = ResourceRecordSet::builder()
+            .name(fqdn(&record.name))
+            .r#type(rr_type(record.content.record_type()))
+            .ttl(record.ttl as i64)
+            .resource_records(
+                ResourceRecord::builder()
+                    .value(to_value(&record.content))
+                    .build()?,
+            )
+            .build()?;
+        let batch = ChangeBatch::builder()
+            .changes(
+                Change::builder()
+                    .action(action)
+                    .resource_record_set(record_set)
+                    .build()?,
+            )
+            .build()?;
+        self.client
+            .change_resource_record_sets()
+            .hosted_zone_id(zone_id)
+            .change_batch(batch)
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Route53 {
+    async fn get_record(&self, name: &str, record_type: RecordType) -> Result<Option<Record>> {
+        let zone_id = self.hosted_zone_id(name).await?;
+        let response = self
+            .client
+            .list_resource_record_sets()
+            .hosted_zone_id(&zone_id)
+            .start_record_name(fqdn(name))
+            .start_record_type(rr_type(record_type))
+            .max_items(1)
+            .send()
+            .await?;
+        let record_set = match response.resource_record_sets().first() {
+            Some(rrs)
+                if unescape(rrs.name()) == fqdn(name) && *rrs.r#type() == rr_type(record_type) =>
+            {
+                rrs
+            }
+            _ => return Ok(None),
+        };
+        let value = record_set
+            .resource_records()
+            .first()
+            .map(|r| r.value())
+            .context("Record set has no values")?;
+        Ok(Some(Record {
+            id: format!("{}/{}", zone_id, record_type),
+            name: name.trim_end_matches('.').to_string(),
+            content: from_value(record_type, value)?,
+            ttl: record_set.ttl().unwrap_or_default() as u32,
+            proxied: false,
+        }))
+    }
+
+    async fn upsert_record(&self, spec: &RecordSpec) -> Result<Record> {
+        let zone_id = self.hosted_zone_id(&spec.name).await?;
+        let record = Record {
+            id: format!("{}/{}", zone_id, spec.content.record_type()),
+            name: spec.name.clone(),
+            content: spec.content.clone(),
+            ttl: spec.ttl.unwrap_or(DEFAULT_TTL),
+            proxied: false,
+        };
+        self.change(ChangeAction::Upsert, &zone_id, &record).await?;
+        log::info!(
+            "Route53 record upserted: {} -> {}",
+            record.name,
+            record.content
+        );
+        Ok(record)
+    }
+
+    async fn delete_record(&self, record: &Record) -> Result<()> {
+        let zone_id = self.hosted_zone_id(&record.name).await?;
+        self.change(ChangeAction::Delete, &zone_id, record).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_txt_values() {
+        let text = RecordContent::Txt(r#"v=spf1 "quoted""#.to_string());
+        assert_eq!(to_value(&text), r#""v=spf1 \"quoted\"""#);
+        assert_eq!(from_value(RecordType::Txt, &to_value(&text)).unwrap(), text);
+    }
+
+    #[test]
+    fn reads_values_of_each_type() {
+        assert_eq!(
+            from_value(RecordType::A, "192.0.2.1").unwrap(),
+            RecordContent::A("192.0.2.1".parse().unwrap())
+        );
+        assert_eq!(
+            from_value(RecordType::Cname, "target.example.net.").unwrap(),
+            RecordContent::Cname("target.example.net".to_string())
+        );
+        assert_eq!(
+            from_value(RecordType::Txt, "unquoted").unwrap(),
+            RecordContent::Txt("unquoted".to_string())
+        );
+    }
+
+    #[test]
+    fn unescapes_wildcard_names() {
+        assert_eq!(unescape("\\052.example.com."), "*.example.com.");
+        assert_eq!(fqdn("*.example.com"), unescape("\\052.example.com."));
+    }
+}
@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use sentry::integrations::anyhow::capture_anyhow;
+use sentry::protocol::Event;
+use sentry::ClientInitGuard;
+
+use crate::config::SentryConfig;
+use crate::secret;
+use crate::state::Failure;
+
+/// Consecutive failures of one domain, of the same kind, before they are reported.
+pub const REPORT_AFTER: u32 = 3;
+
+/// Starts the Sentry client; panics are captured from here on. Events are
+/// flushed when the returned guard is dropped.
+pub fn init(config: &SentryConfig) -> ClientInitGuard {
+    sentry::init((
+        config.dsn.as_str(),
+        sentry::ClientOptions {
+            release: sentry::release_name!(),
+            environment: config.environment.clone().map(Into::into),
+            before_send: Some(Arc::new(scrub_event)),
+            ..Default::default()
+        },
+    ))
+}
+
+/// Takes credentials out of an event's messages before it is sent.
+fn scrub_event(mut event: Event<'static>) -> Option<Event<'static>> {
+    if let Some(message) = &event.message {
+        event.message = Some(secret::scrub(message));
+    }
+    for exception in event.exception.values.iter_mut() {
+        if let Some(value) = &exception.value {
+            exception.value = Some(secret::scrub(value));
+        }
+    }
+    Some(event)
+}
+
+/// Reports a domain that keeps failing, tagged with what it fails at so
+/// detection and provider problems can be alerted on separately. A no-op
+/// unless [`init`] was called.
+pub fn repeated_failure(domain: &str, failure: Failure, failures: u32, error: &anyhow::Error) {
+    sentry::with_scope(
+        |scope| {
+            scope.set_tag("domain", domain);
+            scope.set_tag("failure", failure);
+            scope.set_extra("consecutive_failures", failures.into());
+        },
+        || capture_anyhow(error),
+    );
+}
+
+/// Reports an IP address that keeps changing, as its own warning-level issue.
+pub fn flapping(message: &str) {
+    sentry::capture_message(message, sentry::Level::Warning);
+}
@@ -0,0 +1,56 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::process::Output;
+
+use tokio::process::Command;
+
+/// A public address standing in for the echo services when asking which
+/// way traffic leaves.
+pub const INTERNET_V4: IpAddr = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+pub const INTERNET_V6: IpAddr =
+    IpAddr::V6(Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111));
+
+/// The way traffic to an address leaves this host.
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub interface: String,
+    /// The next hop, usually the router; `None` on a point-to-point link.
+    pub gateway: Option<IpAddr>,
+    /// The local address the traffic is sent from.
+    pub source: Option<IpAddr>,
+}
+
+/// The route to `target`, as `ip route get` reports it. That follows policy
+/// routing too, as set up by `wg-quick`, and split default routes such as
+/// OpenVPN's `def1`. `None` where iproute2 isn't available.
+pub async fn get(target: IpAddr) -> Option<Route> {
+    let output = Command::new("ip")
+        .args(["route", "get", &target.to_string()])
+        .output()
+        .await;
+    parse(output.ok()?)
+}
+
+/// [`get`] for config loading, which runs before there is anything to block.
+pub fn get_blocking(target: IpAddr) -> Option<Route> {
+    let output = std::process::Command::new("ip")
+        .args(["route", "get", &target.to_string()])
+        .output();
+    parse(output.ok()?)
+}
+
+fn parse(output: Output) -> Option<Route> {
+    if !output.status.success() {
+        return None;
+    }
+    let route = String::from_utf8_lossy(&output.stdout);
+    let words: Vec<&str> = route.split_whitespace().collect();
+    let after = |key: &str| {
+        let at = words.iter().position(|word| *word == key)?;
+        words.get(at + 1).copied()
+    };
+    Some(Route {
+        interface: after("dev")?.to_string(),
+        gateway: after("via").and_then(|via| via.parse().ok()),
+        source: after("src").and_then(|src| src.parse().ok()),
+    })
+}
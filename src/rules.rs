@@ -0,0 +1,188 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{Datelike, Local, Timelike};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+
+use crate::config::Config;
+use crate::provider::{Record, RecordContent, RecordSpec};
+use crate::route;
+
+/// Script operations allowed per decision, so a runaway loop fails the sync
+/// instead of hanging it.
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// A Rhai script consulted before each write, for policies too specific for
+/// the config file. It sees the pending change as variables and answers with
+/// `true` or nothing to write as planned, `false` to leave the record alone,
+/// or a map overriding any of `update`, `content`, `comment`, `ttl` and
+/// `proxied`.
+pub struct Rules {
+    engine: Engine,
+    ast: AST,
+}
+
+impl Rules {
+    /// The `rules` script of `config`, if it sets one.
+    pub fn from_config(config: &Config) -> Result<Option<Arc<Rules>>> {
+        match &config.rules {
+            Some(path) => Ok(Some(Arc::new(Rules::load(path)?))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Rules> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| log::info!("rules: {}", text));
+        engine.on_debug(|text, _, _| log::debug!("rules: {}", text));
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .with_context(|| format!("Failed to load update rules {}", path.display()))?;
+        Ok(Rules { engine, ast })
+    }
+
+    /// The write to make instead of `spec`, or `None` when the rules hold it back.
+    pub async fn decide(
+        &self,
+        spec: RecordSpec,
+        existing: Option<&Record>,
+    ) -> Result<Option<RecordSpec>> {
+        let now = Local::now();
+        let mut time = Map::new();
+        time.insert("hour".into(), (now.hour() as i64).into());
+        time.insert("minute".into(), (now.minute() as i64).into());
+        // 1 is Monday, 7 is Sunday.
+        time.insert(
+            "weekday".into(),
+            (now.weekday().number_from_monday() as i64).into(),
+        );
+        time.insert("date".into(), now.format("%Y-%m-%d").to_string().into());
+        time.insert("timestamp".into(), now.timestamp().into());
+
+        let interface = match spec.content.ip() {
+            Some(ip) if ip.is_ipv4() => route::get(route::INTERNET_V4).await,
+            Some(_) => route::get(route::INTERNET_V6).await,
+            None => None,
+        };
+
+        let mut scope = Scope::new();
+        scope.push_constant("domain", spec.name.clone());
+        scope.push_constant("record_type", spec.content.record_type().to_string());
+        scope.push_constant("content", spec.content.to_string());
+        scope.push_constant("current", optional(existing.map(|r| r.content.to_string())));
+        scope.push_constant("current_ttl", optional(existing.map(|r| r.ttl as i64)));
+        scope.push_constant("proxied", spec.proxied);
+        scope.push_constant("now", time);
+        scope.push_constant("interface", optional(interface.map(|r| r.interface)));
+
+        let answer: Dynamic = self
+            .engine
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| anyhow!("Update rules failed for {}: {}", spec.name, e))?;
+        apply(spec, answer)
+    }
+}
+
+fn optional<T: Into<Dynamic>>(value: Option<T>) -> Dynamic {
+    value.map_or(Dynamic::UNIT, Into::into)
+}
+
+fn apply(mut spec: RecordSpec, answer: Dynamic) -> Result<Option<RecordSpec>> {
+    if answer.is_unit() {
+        return Ok(Some(spec));
+    }
+    if let Ok(update) = answer.as_bool() {
+        return Ok(update.then_some(spec));
+    }
+    let Some(map) = answer.try_cast::<Map>() else {
+        bail!("Update rules must answer true, false or a map");
+    };
+    for (key, value) in map {
+        let kind = value.type_name();
+        let invalid = || anyhow!("Update rules answered {} as a {}", key, kind);
+        match key.as_str() {
+            "update" => {
+                if !value.as_bool().map_err(|_| invalid())? {
+                    return Ok(None);
+                }
+            }
+            "content" => {
+                let content = RecordContent::infer(&value.into_string().map_err(|_| invalid())?);
+                if content.record_type() != spec.content.record_type() {
+                    bail!(
+                        "Update rules answered {} content {:?} for a {} record",
+                        content.record_type(),
+                        content.to_string(),
+                        spec.content.record_type()
+                    );
+                }
+                spec.content = content;
+            }
+            "comment" => {
+                spec.comment = Some(value.into_string().map_err(|_| invalid())?);
+            }
+            "ttl" => {
+                let ttl = value.as_int().map_err(|_| invalid())?;
+                spec.ttl = Some(u32::try_from(ttl).map_err(|_| invalid())?);
+            }
+            "proxied" => spec.proxied = value.as_bool().map_err(|_| invalid())?,
+            _ => bail!("Update rules answered unknown key {:?}", key.as_str()),
+        }
+    }
+    Ok(Some(spec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> RecordSpec {
+        RecordSpec {
+            name: "home.example.com".to_string(),
+            content: RecordContent::A("192.0.2.1".parse().unwrap()),
+            ttl: None,
+            proxied: false,
+            comment: None,
+        }
+    }
+
+    fn answer(script: &str) -> Result<Option<RecordSpec>> {
+        apply(spec(), Engine::new().eval::<Dynamic>(script).unwrap())
+    }
+
+    #[test]
+    fn true_or_nothing_writes_as_planned() {
+        assert_eq!(answer("true").unwrap(), Some(spec()));
+        assert_eq!(answer("()").unwrap(), Some(spec()));
+    }
+
+    #[test]
+    fn false_holds_the_write_back() {
+        assert_eq!(answer("false").unwrap(), None);
+        assert_eq!(answer("#{ update: false }").unwrap(), None);
+    }
+
+    #[test]
+    fn a_map_overrides_the_write() {
+        let spec =
+            answer(r#"#{ content: "192.0.2.9", ttl: 300, proxied: true, comment: "rules" }"#)
+                .unwrap()
+                .unwrap();
+        assert_eq!(spec.content, RecordContent::A("192.0.2.9".parse().unwrap()));
+        assert_eq!(spec.ttl, Some(300));
+        assert!(spec.proxied);
+        assert_eq!(spec.comment.as_deref(), Some("rules"));
+    }
+
+    #[test]
+    fn rejects_invalid_answers() {
+        // Content of another record type.
+        assert!(answer(r#"#{ content: "2001:db8::1" }"#).is_err());
+        assert!(answer("#{ ttl: -1 }").is_err());
+        assert!(answer(r#"#{ ttl: "300" }"#).is_err());
+        assert!(answer("#{ priority: 10 }").is_err());
+        assert!(answer("42").is_err());
+    }
+}
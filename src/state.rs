@@ -0,0 +1,646 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{broadcast, Notify};
+
+use crate::cgnat;
+use crate::config::{Config, DEFAULT_FLAP_THRESHOLD};
+use crate::ip;
+use crate::metrics;
+use crate::mirror::Mirrors;
+use crate::output::EventFormat;
+use crate::plugin::Notifiers;
+use crate::provider::{Record, RecordContent};
+use crate::reporting;
+use crate::store::{self, Store, WriteResult};
+
+const HISTORY_LEN: usize = 200;
+
+/// Window over which IP changes are counted for flap detection.
+const FLAP_WINDOW: chrono::Duration = chrono::Duration::hours(1);
+
+/// Events buffered for each subscriber before the slowest starts missing some.
+const FEED_CAPACITY: usize = 256;
+
+/// How long a published record is trusted before it is read back from the provider.
+const RECONFIRM_AFTER: chrono::Duration = chrono::Duration::hours(1);
+
+/// `~/.local/state/cfbind/state.json` or the platform equivalent.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::state_dir()
+        .or_else(dirs::data_dir)
+        .map(|dir| dir.join("cfbind").join("state.json"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Unchanged,
+    Updated,
+    /// A write failed verification and the previous content was restored.
+    RolledBack,
+    Error,
+    /// The detected IP changed unusually often; not tied to one domain.
+    Flapping,
+    /// The public IP was detected; only streamed, not kept in the history.
+    Detected,
+    /// A cycle was skipped while updates are paused; only streamed.
+    Skipped,
+    /// A write was confirmed resolvable; only streamed.
+    Verified,
+    /// A write could not be confirmed resolvable in time; only streamed.
+    VerifyFailed,
+}
+
+/// What a failed sync failed at. Each is retried on its own schedule and
+/// counted and reported apart, since they call for different fixes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Failure {
+    /// The public IP could not be detected, usually the local link is down.
+    Detection,
+    /// The DNS provider refused the request or could not be reached.
+    Provider,
+}
+
+impl Failure {
+    pub fn of(error: &anyhow::Error) -> Failure {
+        if ip::is_detection_failure(error) {
+            Failure::Detection
+        } else {
+            Failure::Provider
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Detection => write!(f, "detection"),
+            Failure::Provider => write!(f, "provider"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub timestamp: DateTime<Utc>,
+    pub domain: String,
+    pub kind: EventKind,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DomainStatus {
+    pub content: Option<String>,
+    pub last_check: Option<DateTime<Utc>>,
+    pub last_update: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    /// When the domain last synced without an error.
+    #[serde(default)]
+    pub last_success: Option<DateTime<Utc>>,
+    /// Whether the last write was confirmed resolvable; unset when not verified.
+    pub verified: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    pub paused: bool,
+    pub current_ip: Option<IpAddr>,
+    /// Whether the last attempt to detect the IP succeeded; unset before the first.
+    pub detection_ok: Option<bool>,
+    /// Failed syncs since startup, by what they failed at.
+    pub failures: BTreeMap<Failure, u64>,
+    pub domains: BTreeMap<String, DomainStatus>,
+}
+
+/// A record as last written or read back, and when the provider last confirmed it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Published {
+    record: Record,
+    confirmed: DateTime<Utc>,
+}
+
+/// The part of [`Inner`] written to the state file.
+#[derive(Default, Serialize, Deserialize)]
+struct Saved {
+    current_ip: Option<IpAddr>,
+    #[serde(default)]
+    domains: BTreeMap<String, DomainStatus>,
+    #[serde(default)]
+    published: BTreeMap<String, Published>,
+    /// Kept across restarts, so maintenance isn't cut short by one.
+    #[serde(default)]
+    paused: bool,
+}
+
+#[derive(Default)]
+struct Inner {
+    current_ip: Option<IpAddr>,
+    domains: BTreeMap<String, DomainStatus>,
+    published: BTreeMap<String, Published>,
+    history: VecDeque<Event>,
+    ip_changes: VecDeque<DateTime<Utc>>,
+    flapping: bool,
+    detection_ok: Option<bool>,
+    failures: BTreeMap<Failure, u64>,
+}
+
+fn load(path: &Path) -> Result<Saved> {
+    let raw = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&raw)?)
+}
+
+fn remove_healthcheck(file: &Path) {
+    if let Err(e) = std::fs::remove_file(file) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            log::warn!(
+                "Failed to remove healthcheck file {}: {:#}",
+                file.display(),
+                e
+            );
+        }
+    }
+}
+
+/// The status file: [`Status`] and when it was written.
+#[derive(Serialize)]
+struct StatusFile<'a> {
+    timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    status: &'a Status,
+}
+
+fn write_status(path: &Path, status: &Status) -> Result<()> {
+    let file = StatusFile {
+        timestamp: Utc::now(),
+        status,
+    };
+    // Replaced by a rename, so a reader never sees half a file.
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(&file)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn save(path: &Path, saved: &Saved) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Write a sibling file and rename it so a crash never leaves half a file.
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(saved)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Every event as it happens, for remote watchers.
+struct Feed(broadcast::Sender<Event>);
+
+impl Default for Feed {
+    fn default() -> Self {
+        Feed(broadcast::channel(FEED_CAPACITY).0)
+    }
+}
+
+/// Runtime state shared between the updaters and anything observing or
+/// controlling them.
+#[derive(Default)]
+pub struct State {
+    feed: Feed,
+    paused: AtomicBool,
+    triggers: Mutex<Vec<Arc<Notify>>>,
+    reload: Notify,
+    inner: Mutex<Inner>,
+    store: Option<Store>,
+    path: Option<PathBuf>,
+    flap_threshold: u32,
+    healthcheck: Option<PathBuf>,
+    textfile: Option<PathBuf>,
+    status_file: Option<PathBuf>,
+    events: Option<EventFormat>,
+    mirrors: Option<Arc<Mirrors>>,
+    notifiers: Option<Arc<Notifiers>>,
+}
+
+impl State {
+    /// State restored from the state file, which also logs to the history
+    /// database when `[history]` is configured.
+    pub fn open(config: &Config) -> Result<Arc<State>> {
+        let store = match &config.history {
+            Some(history) => Some(Store::open(&store::path(Some(history))?)?),
+            None => None,
+        };
+        let path = config.state_file.clone().or_else(default_path);
+        let mut inner = Inner::default();
+        let mut paused = false;
+        if let Some(path) = path.as_ref().filter(|p| p.exists()) {
+            match load(path) {
+                Ok(saved) => {
+                    inner.current_ip = saved.current_ip;
+                    inner.domains = saved.domains;
+                    inner.published = saved.published;
+                    paused = saved.paused;
+                }
+                Err(e) => log::warn!("Ignoring state file {}: {:#}", path.display(), e),
+            }
+        }
+        // A file left over from an earlier run must not report this one healthy.
+        if let Some(file) = &config.healthcheck_file {
+            remove_healthcheck(file);
+        }
+        Ok(Arc::new(State {
+            paused: AtomicBool::new(paused),
+            inner: Mutex::new(inner),
+            store,
+            path,
+            flap_threshold: config.flap_threshold.unwrap_or(DEFAULT_FLAP_THRESHOLD),
+            healthcheck: config.healthcheck_file.clone(),
+            textfile: config.metrics.as_ref().and_then(|m| m.textfile.clone()),
+            status_file: config.status_file.clone(),
+            events: config.events,
+            mirrors: Mirrors::new(config),
+            notifiers: Notifiers::new(config),
+            ..State::default()
+        }))
+    }
+
+    /// Touches the healthcheck file while every domain's last sync succeeded
+    /// and removes it otherwise, for `HEALTHCHECK CMD test -f <file>`.
+    fn update_healthcheck(&self, inner: &Inner) {
+        let Some(file) = &self.healthcheck else {
+            return;
+        };
+        if inner.domains.values().any(|d| d.last_error.is_some()) {
+            remove_healthcheck(file);
+        } else if let Err(e) = std::fs::write(file, Utc::now().to_rfc3339()) {
+            log::warn!(
+                "Failed to write healthcheck file {}: {:#}",
+                file.display(),
+                e
+            );
+        }
+    }
+
+    fn persist(&self, inner: &Inner) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let saved = Saved {
+            current_ip: inner.current_ip,
+            domains: inner.domains.clone(),
+            published: inner.published.clone(),
+            paused: self.is_paused(),
+        };
+        if let Err(e) = save(path, &saved) {
+            log::warn!("Failed to write state file {}: {:#}", path.display(), e);
+        }
+    }
+
+    /// The record last published for `domain`, if the provider confirmed it recently.
+    pub fn published(&self, domain: &str) -> Option<Record> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .published
+            .get(domain)
+            .filter(|p| Utc::now() - p.confirmed < RECONFIRM_AFTER)
+            .map(|p| p.record.clone())
+    }
+
+    /// Remembers `record` as what the provider currently serves for `domain`.
+    pub fn set_published(&self, domain: &str, record: Record) {
+        let mut inner = self.inner.lock().unwrap();
+        let published = Published {
+            record,
+            confirmed: Utc::now(),
+        };
+        inner.published.insert(domain.to_string(), published);
+        self.persist(&inner);
+    }
+
+    /// Drops the cached record so the next cycle reads it from the provider.
+    pub fn forget_published(&self, domain: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.published.remove(domain).is_some() {
+            self.persist(&inner);
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Pauses or resumes updating; a resumed instance syncs right away.
+    pub fn set_paused(&self, paused: bool) {
+        if self.paused.swap(paused, Ordering::SeqCst) == paused {
+            return;
+        }
+        log::info!("Updates {}", if paused { "paused" } else { "resumed" });
+        self.persist(&self.inner.lock().unwrap());
+        if !paused {
+            self.trigger_update();
+        }
+    }
+
+    /// A wake-up for one updater loop, for [`State::wait`].
+    pub fn subscribe_trigger(&self) -> Arc<Notify> {
+        let trigger = Arc::new(Notify::new());
+        self.triggers.lock().unwrap().push(Arc::clone(&trigger));
+        trigger
+    }
+
+    /// Wakes every updater for its next cycle. One that is busy syncing keeps
+    /// the wake-up and starts the next cycle as soon as it waits.
+    pub fn trigger_update(&self) {
+        for trigger in self.triggers.lock().unwrap().iter() {
+            trigger.notify_one();
+        }
+    }
+
+    /// Asks for file and Vault credentials to be read again, as SIGHUP does.
+    pub fn trigger_reload(&self) {
+        self.reload.notify_one();
+    }
+
+    /// Waits for [`State::trigger_reload`].
+    pub async fn reload_requested(&self) {
+        self.reload.notified().await;
+    }
+
+    /// Sleeps until the next cycle is due or an update is triggered.
+    pub async fn wait(&self, interval: Duration, trigger: &Notify) {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = trigger.notified() => {}
+        }
+    }
+
+    /// Writes `event` to stdout when events are streamed, and hands it to
+    /// the notifier plugins and any subscribers.
+    fn emit(&self, event: &Event) {
+        if let Some(notifiers) = &self.notifiers {
+            notifiers.send(event);
+        }
+        // Fails only when nobody is watching.
+        let _ = self.feed.0.send(event.clone());
+        let Some(EventFormat::Ndjson) = self.events else {
+            return;
+        };
+        let Ok(line) = serde_json::to_string(event) else {
+            return;
+        };
+        // One write per line, so events from concurrent updaters don't interleave.
+        let _ = writeln!(std::io::stdout().lock(), "{}", line);
+    }
+
+    /// Every event from now on, as it happens.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.feed.0.subscribe()
+    }
+
+    /// Streams an event that is not kept in the history or status.
+    pub fn notify(&self, domain: &str, kind: EventKind, message: String) {
+        self.emit(&Event {
+            timestamp: Utc::now(),
+            domain: domain.to_string(),
+            kind,
+            message,
+        });
+    }
+
+    pub fn set_current_ip(&self, ip: IpAddr) {
+        let previous = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.detection_ok = Some(true);
+            inner.current_ip.replace(ip)
+        };
+        self.notify("-", EventKind::Detected, ip.to_string());
+        if let Some(previous) = previous.filter(|&previous| previous != ip) {
+            self.track_flapping(previous, ip);
+        }
+        if previous != Some(ip) {
+            tokio::spawn(async move {
+                if let Some(warning) = cgnat::check(ip).await {
+                    log::warn!("{}", warning);
+                }
+            });
+        }
+        let Some(store) = &self.store else {
+            return;
+        };
+        // After a restart, compare against the last address on disk instead.
+        let previous = match previous {
+            Some(previous) => Some(previous),
+            None => store.last_ip().unwrap_or_else(|e| {
+                log::warn!("Failed to read the history database: {:#}", e);
+                None
+            }),
+        };
+        if previous != Some(ip) {
+            if let Err(e) = store.ip_changed(previous, ip) {
+                log::warn!(
+                    "Failed to record IP change in the history database: {:#}",
+                    e
+                );
+            }
+        }
+    }
+
+    /// Raises one alert when the IP changes more than `flap_threshold` times
+    /// within an hour, and clears it once the rate drops again.
+    fn track_flapping(&self, previous: IpAddr, ip: IpAddr) {
+        let now = Utc::now();
+        let mut inner = self.inner.lock().unwrap();
+        inner.ip_changes.push_back(now);
+        while inner
+            .ip_changes
+            .front()
+            .is_some_and(|&t| now - t > FLAP_WINDOW)
+        {
+            inner.ip_changes.pop_front();
+        }
+        let changes = inner.ip_changes.len() as u32;
+        if self.flap_threshold == 0 || changes <= self.flap_threshold {
+            if inner.flapping {
+                log::info!("IP address is stable again");
+                inner.flapping = false;
+            }
+            return;
+        }
+        if inner.flapping {
+            return;
+        }
+        inner.flapping = true;
+        let message = format!(
+            "IP changed {} times in the last hour ({} -> {}), check the modem or ISP",
+            changes, previous, ip
+        );
+        log::error!("{}", message);
+        reporting::flapping(&message);
+        let event = Event {
+            timestamp: now,
+            domain: "-".to_string(),
+            kind: EventKind::Flapping,
+            message,
+        };
+        self.emit(&event);
+        if inner.history.len() == HISTORY_LEN {
+            inner.history.pop_front();
+        }
+        inner.history.push_back(event);
+    }
+
+    /// Logs the result of a DNS write to the history database, if there is one.
+    pub fn record_write(
+        &self,
+        domain: &str,
+        old: Option<&RecordContent>,
+        new: &RecordContent,
+        result: WriteResult,
+        error: Option<&str>,
+    ) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        let old = old.map(|content| content.to_string());
+        if let Err(e) = store.write(domain, old.as_deref(), &new.to_string(), result, error) {
+            log::warn!("Failed to record write in the history database: {:#}", e);
+        }
+    }
+
+    pub fn record(&self, domain: &str, kind: EventKind, message: String) {
+        if let Some(mirrors) = &self.mirrors {
+            if matches!(kind, EventKind::Updated | EventKind::Unchanged) {
+                mirrors.sync(domain, &message);
+            }
+        }
+        let event = Event {
+            timestamp: Utc::now(),
+            domain: domain.to_string(),
+            kind,
+            message,
+        };
+        let mut inner = self.inner.lock().unwrap();
+        let status = inner.domains.entry(domain.to_string()).or_default();
+        status.last_check = Some(event.timestamp);
+        match kind {
+            EventKind::Unchanged => {
+                status.content = Some(event.message.clone());
+                status.last_success = Some(event.timestamp);
+                status.last_error = None;
+            }
+            EventKind::Updated => {
+                status.content = Some(event.message.clone());
+                status.last_update = Some(event.timestamp);
+                status.last_success = Some(event.timestamp);
+                status.last_error = None;
+                status.verified = None;
+            }
+            EventKind::RolledBack => {
+                status.content = Some(event.message.clone());
+                status.last_update = Some(event.timestamp);
+                status.last_error = Some("Verification failed, change rolled back".to_string());
+            }
+            EventKind::Error => status.last_error = Some(event.message.clone()),
+            EventKind::Flapping
+            | EventKind::Detected
+            | EventKind::Skipped
+            | EventKind::Verified
+            | EventKind::VerifyFailed => {}
+        }
+        self.emit(&event);
+        if inner.history.len() == HISTORY_LEN {
+            inner.history.pop_front();
+        }
+        inner.history.push_back(event);
+        self.update_healthcheck(&inner);
+    }
+
+    /// Counts a failed sync, or a failed detection covered by a queued IP.
+    pub fn failed(&self, failure: Failure) {
+        let mut inner = self.inner.lock().unwrap();
+        *inner.failures.entry(failure).or_default() += 1;
+        if failure == Failure::Detection {
+            inner.detection_ok = Some(false);
+        }
+    }
+
+    pub fn set_verified(&self, domain: &str, verified: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .domains
+            .entry(domain.to_string())
+            .or_default()
+            .verified = Some(verified);
+    }
+
+    pub fn status(&self) -> Status {
+        let inner = self.inner.lock().unwrap();
+        Status {
+            paused: self.is_paused(),
+            current_ip: inner.current_ip,
+            detection_ok: inner.detection_ok,
+            failures: inner.failures.clone(),
+            domains: inner.domains.clone(),
+        }
+    }
+
+    /// Waits for the internal resolvers to be updated and the notifier
+    /// plugins to be told, before a one-shot command exits.
+    pub async fn settle(&self) {
+        if let Some(mirrors) = &self.mirrors {
+            mirrors.settle().await;
+        }
+        if let Some(notifiers) = &self.notifiers {
+            notifiers.settle().await;
+        }
+    }
+
+    /// Called once an updater or group has finished a cycle, to export
+    /// metrics and the status file.
+    pub fn cycle_finished(&self) {
+        if self.textfile.is_none() && self.status_file.is_none() {
+            return;
+        }
+        let status = self.status();
+        if let Some(textfile) = &self.textfile {
+            if let Err(e) = metrics::write_textfile(textfile, &status) {
+                log::warn!("Failed to write metrics: {:#}", e);
+            }
+        }
+        if let Some(path) = &self.status_file {
+            if let Err(e) = write_status(path, &status) {
+                log::warn!("Failed to write status file {}: {:#}", path.display(), e);
+            }
+        }
+    }
+
+    pub fn history(&self) -> Vec<Event> {
+        self.inner.lock().unwrap().history.iter().cloned().collect()
+    }
+}
+
+/// Pauses updating on SIGUSR1 and resumes it on SIGUSR2, for maintenance
+/// scripts that know the PID but not the API token.
+#[cfg(unix)]
+pub async fn watch_signals(state: Arc<State>) -> Result<()> {
+    let mut pause = signal(SignalKind::user_defined1())?;
+    let mut resume = signal(SignalKind::user_defined2())?;
+    loop {
+        tokio::select! {
+            _ = pause.recv() => state.set_paused(true),
+            _ = resume.recv() => state.set_paused(false),
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+use crate::config::HistoryConfig;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS ip_changes (
+    id        INTEGER PRIMARY KEY,
+    timestamp TEXT NOT NULL,
+    old       TEXT,
+    new       TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS writes (
+    id        INTEGER PRIMARY KEY,
+    timestamp TEXT NOT NULL,
+    domain    TEXT NOT NULL,
+    old       TEXT,
+    new       TEXT NOT NULL,
+    result    TEXT NOT NULL,
+    error     TEXT
+);
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteResult {
+    Updated,
+    RolledBack,
+    Failed,
+}
+
+impl WriteResult {
+    fn as_str(self) -> &'static str {
+        match self {
+            WriteResult::Updated => "updated",
+            WriteResult::RolledBack => "rolled_back",
+            WriteResult::Failed => "failed",
+        }
+    }
+}
+
+/// `~/.local/share/cfbind/history.db` or the platform equivalent.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("cfbind").join("history.db"))
+}
+
+/// The configured database path, falling back to [`default_path`].
+pub fn path(config: Option<&HistoryConfig>) -> Result<PathBuf> {
+    match config.and_then(|c| c.path.clone()) {
+        Some(path) => Ok(path),
+        None => default_path().context("Could not determine the data directory"),
+    }
+}
+
+/// Timestamps are stored as fixed-width UTC strings so they sort and compare as text.
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IpChange {
+    pub timestamp: String,
+    pub old: Option<String>,
+    pub new: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Write {
+    pub timestamp: String,
+    pub domain: String,
+    pub old: Option<String>,
+    pub new: String,
+    pub result: String,
+    pub error: Option<String>,
+}
+
+/// Durable log of address changes and DNS writes, kept in SQLite.
+pub struct Store {
+    conn: Mutex<Connection>,
+}
+
+impl Store {
+    pub fn open(path: &Path) -> Result<Store> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open history database {}", path.display()))?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Store {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// The most recently recorded address, if any.
+    pub fn last_ip(&self) -> Result<Option<IpAddr>> {
+        let conn = self.conn.lock().unwrap();
+        let ip: Option<String> = conn
+            .query_row(
+                "SELECT new FROM ip_changes ORDER BY id DESC LIMIT 1",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(ip.and_then(|ip| ip.parse().ok()))
+    }
+
+    pub fn ip_changed(&self, old: Option<IpAddr>, new: IpAddr) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO ip_changes (timestamp, old, new) VALUES (?1, ?2, ?3)",
+            params![
+                timestamp(Utc::now()),
+                old.map(|ip| ip.to_string()),
+                new.to_string()
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn write(
+        &self,
+        domain: &str,
+        old: Option<&str>,
+        new: &str,
+        result: WriteResult,
+        error: Option<&str>,
+    ) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO writes (timestamp, domain, old, new, result, error)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                timestamp(Utc::now()),
+                domain,
+                old,
+                new,
+                result.as_str(),
+                error
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// IP changes at or after `since`, oldest first.
+    pub fn ip_changes(&self, since: Option<DateTime<Utc>>) -> Result<Vec<IpChange>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT timestamp, old, new FROM ip_changes
+             WHERE ?1 IS NULL OR timestamp >= ?1 ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![since.map(timestamp)], |row| {
+            Ok(IpChange {
+                timestamp: row.get(0)?,
+                old: row.get(1)?,
+                new: row.get(2)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Writes at or after `since`, optionally for one domain only, oldest first.
+    pub fn writes(&self, since: Option<DateTime<Utc>>, domain: Option<&str>) -> Result<Vec<Write>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT timestamp, domain, old, new, result, error FROM writes
+             WHERE (?1 IS NULL OR timestamp >= ?1) AND (?2 IS NULL OR domain = ?2)
+             ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![since.map(timestamp), domain], |row| {
+            Ok(Write {
+                timestamp: row.get(0)?,
+                domain: row.get(1)?,
+                old: row.get(2)?,
+                new: row.get(3)?,
+                result: row.get(4)?,
+                error: row.get(5)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
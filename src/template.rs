@@ -0,0 +1,186 @@
+use anyhow::{bail, Context, Result};
+use chrono::{SecondsFormat, Utc};
+
+use crate::route;
+
+/// Names a record name may use as `{{name}}`.
+const NAME_VARIABLES: [&str; 2] = ["hostname", "interface"];
+
+/// Names a record comment may use as `{{name}}`.
+const COMMENT_VARIABLES: [&str; 4] = ["hostname", "username", "timestamp", "version"];
+
+/// The machine's host name as the system reports it, through
+/// `gethostname(2)` or `GetComputerNameExW` on Windows.
+pub fn hostname() -> Option<String> {
+    gethostname::gethostname()
+        .into_string()
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// `value` as one DNS label: lowercase letters, digits and inner hyphens, at
+/// most 63 characters. Anything else becomes a hyphen, so `Kiosk_07` is
+/// `kiosk-07`.
+pub fn label(value: &str) -> String {
+    let mapped: String = value
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' => c,
+            'A'..='Z' => c.to_ascii_lowercase(),
+            _ => '-',
+        })
+        .collect();
+    let mut label = String::new();
+    for c in mapped.trim_matches('-').chars() {
+        if !(c == '-' && label.ends_with('-')) {
+            label.push(c);
+        }
+    }
+    label.truncate(63);
+    label.trim_end_matches('-').to_string()
+}
+
+fn username() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+fn name_value(variable: &str) -> Result<String> {
+    let value = match variable {
+        "hostname" => {
+            let name = hostname().context("Could not determine the host name")?;
+            // Only the first label of a fully qualified name.
+            label(name.split('.').next().unwrap_or_default())
+        }
+        "interface" => {
+            let route = route::get_blocking(route::INTERNET_V4)
+                .or_else(|| route::get_blocking(route::INTERNET_V6))
+                .context("Could not determine the interface traffic leaves through")?;
+            label(&route.interface)
+        }
+        _ => unreachable!("only called with NAME_VARIABLES"),
+    };
+    if value.is_empty() {
+        bail!("The {} has no characters usable in a record name", variable);
+    }
+    Ok(value)
+}
+
+fn comment_value(variable: &str) -> Result<String> {
+    Ok(match variable {
+        "hostname" => hostname().unwrap_or_else(|| "unknown".to_string()),
+        "username" => username(),
+        "timestamp" => Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        "version" => env!("CARGO_PKG_VERSION").to_string(),
+        _ => unreachable!("only called with COMMENT_VARIABLES"),
+    })
+}
+
+/// Fills in the `{{name}}` variables of `template`, each one of `variables`,
+/// with what `value` gives for it. `what` names the template in errors.
+fn fill(
+    template: &str,
+    what: &str,
+    variables: &[&str],
+    value: impl Fn(&str) -> Result<String>,
+) -> Result<String> {
+    let mut filled = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        filled.push_str(&rest[..start]);
+        let Some((variable, after)) = rest[start + 2..].split_once("}}") else {
+            bail!("Unclosed variable in {}", what);
+        };
+        let variable = variable.trim();
+        if !variables.contains(&variable) {
+            bail!(
+                "Unknown variable {:?} in {}, expected one of {}",
+                variable,
+                what,
+                variables.join(", ")
+            );
+        }
+        filled.push_str(&value(variable)?);
+        rest = after;
+    }
+    filled.push_str(rest);
+    Ok(filled)
+}
+
+/// Fills in the `{{name}}` variables of a record name for this machine, so
+/// `{{hostname}}.dyn.example.com` becomes `nas.dyn.example.com` on host
+/// `nas`. Names without variables are returned as they are.
+pub fn expand(name: &str) -> Result<String> {
+    let what = format!("record name {}", name);
+    fill(name, &what, &NAME_VARIABLES, name_value)
+}
+
+/// Rejects a comment template [`comment`] couldn't fill in.
+pub fn check_comment(template: &str) -> Result<()> {
+    fill(template, "comment", &COMMENT_VARIABLES, |_| {
+        Ok(String::new())
+    })?;
+    Ok(())
+}
+
+/// Fills a comment template in as of now, e.g.
+/// `"cfbind {{version}} on {{hostname}}"`. Templates are checked when the
+/// config loads; one that still doesn't fill in is written as it is.
+pub fn comment(template: &str) -> String {
+    fill(template, "comment", &COMMENT_VARIABLES, comment_value)
+        .unwrap_or_else(|_| template.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn label_keeps_letters_digits_and_single_hyphens() {
+        assert_eq!(label("Kiosk_07"), "kiosk-07");
+        assert_eq!(label("--lab..pc 3--"), "lab-pc-3");
+        assert_eq!(label("éé"), "");
+    }
+
+    #[test]
+    fn label_is_at_most_63_characters() {
+        let long = format!("{}-{}", "a".repeat(62), "b".repeat(10));
+        assert_eq!(label(&long), "a".repeat(62));
+    }
+
+    #[test]
+    fn expand_leaves_plain_names_alone() {
+        assert_eq!(expand("home.example.com").unwrap(), "home.example.com");
+    }
+
+    #[test]
+    fn expand_fills_in_the_host_name() {
+        let expanded = expand("{{ hostname }}.dyn.example.com").unwrap();
+        assert!(!expanded.contains('{'));
+        assert!(expanded.ends_with(".dyn.example.com"));
+    }
+
+    #[test]
+    fn expand_rejects_unknown_and_unclosed_variables() {
+        assert!(expand("{{user}}.example.com").is_err());
+        assert!(expand("{{hostname.example.com").is_err());
+    }
+
+    #[test]
+    fn comment_fills_in_the_version() {
+        assert_eq!(
+            comment("cfbind {{ version }}"),
+            format!("cfbind {}", env!("CARGO_PKG_VERSION"))
+        );
+        assert_eq!(comment("managed by cfbind"), "managed by cfbind");
+    }
+
+    #[test]
+    fn check_comment_takes_only_comment_variables() {
+        assert!(check_comment("{{username}} at {{timestamp}}").is_ok());
+        assert!(check_comment("{{interface}}").is_err());
+        assert!(check_comment("{{version").is_err());
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use crossterm::event::{self, Event as TermEvent, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::state::{EventKind, State};
+
+/// Log lines kept for the log pane.
+const LOG_LINES: usize = 100;
+
+static LOG: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// The logger's target while the TUI runs: lines go to the log pane, as
+/// writing them to stderr would draw over the screen.
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut log = LOG.lock().unwrap();
+        for line in String::from_utf8_lossy(buf).lines() {
+            if log.len() == LOG_LINES {
+                log.pop_front();
+            }
+            log.push_back(line.to_string());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct App {
+    state: Arc<State>,
+    domains: Vec<String>,
+    scroll: u16,
+}
+
+fn ago(timestamp: Option<DateTime<Utc>>) -> String {
+    let Some(timestamp) = timestamp else {
+        return "-".to_string();
+    };
+    let secs = (Utc::now() - timestamp).num_seconds().max(0);
+    match secs {
+        0..=59 => format!("{}s ago", secs),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+impl App {
+    fn draw(&self, frame: &mut Frame) {
+        let status = self.state.status();
+        let history = self.state.history();
+        let [header, table, log, messages, help] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Length(self.domains.len() as u16 + 3),
+            Constraint::Min(5),
+            Constraint::Length(6),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let detected = status
+            .current_ip
+            .map_or_else(|| "detecting...".to_string(), |ip| ip.to_string());
+        let mut title = format!("Detected IP: {}", detected);
+        if status.paused {
+            title.push_str("  [paused]");
+        }
+        frame.render_widget(
+            Paragraph::new(title).block(Block::bordered().title("cfbind")),
+            header,
+        );
+
+        let rows = self.domains.iter().map(|name| {
+            let domain = status.domains.get(name).cloned().unwrap_or_default();
+            let content = domain.content.clone().unwrap_or_else(|| "-".to_string());
+            let matches = status
+                .current_ip
+                .is_some_and(|ip| domain.content.as_deref() == Some(ip.to_string().as_str()));
+            let (state, color) = match (&domain.last_error, matches) {
+                (Some(_), _) => ("error", Color::Red),
+                (None, true) => ("in sync", Color::Green),
+                (None, false) => ("pending", Color::Yellow),
+            };
+            Row::new(vec![
+                name.clone(),
+                content,
+                detected.clone(),
+                state.to_string(),
+                ago(domain.last_update),
+            ])
+            .style(Style::default().fg(color))
+        });
+        let widths = [
+            Constraint::Percentage(30),
+            Constraint::Percentage(20),
+            Constraint::Percentage(20),
+            Constraint::Percentage(10),
+            Constraint::Percentage(20),
+        ];
+        let table_widget = Table::new(rows, widths)
+            .header(Row::new(vec!["Domain", "DNS", "Detected", "State", "Last change"]).bold())
+            .block(Block::bordered().title("Domains"));
+        frame.render_widget(table_widget, table);
+
+        let lines: Vec<Line> = history
+            .iter()
+            .map(|event| {
+                let line = Line::from(format!(
+                    "{} {:<24} {:?} {}",
+                    event.timestamp.format("%H:%M:%S"),
+                    event.domain,
+                    event.kind,
+                    event.message
+                ));
+                match event.kind {
+                    EventKind::Error | EventKind::RolledBack | EventKind::VerifyFailed => {
+                        line.red()
+                    }
+                    EventKind::Updated | EventKind::Verified => line.green(),
+                    EventKind::Flapping => line.yellow(),
+                    EventKind::Unchanged | EventKind::Detected | EventKind::Skipped => line,
+                }
+            })
+            .collect();
+        // Stick to the newest entries unless the user scrolled back.
+        let visible = log.height.saturating_sub(2);
+        let bottom = (lines.len() as u16).saturating_sub(visible);
+        let offset = bottom.saturating_sub(self.scroll);
+        frame.render_widget(
+            Paragraph::new(lines)
+                .scroll((offset, 0))
+                .block(Block::bordered().title("Events")),
+            log,
+        );
+
+        let logged = LOG.lock().unwrap();
+        let shown = logged
+            .len()
+            .saturating_sub(messages.height.saturating_sub(2) as usize);
+        let lines: Vec<Line> = logged
+            .iter()
+            .skip(shown)
+            .map(|line| Line::raw(line.clone()).dim())
+            .collect();
+        drop(logged);
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title("Log")),
+            messages,
+        );
+
+        frame.render_widget(
+            Paragraph::new("q quit  u update now  p pause/resume  ↑/↓ scroll").dim(),
+            help,
+        );
+    }
+
+    fn handle_key(&mut self, code: KeyCode) -> bool {
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('u') => self.state.trigger_update(),
+            KeyCode::Char('p') => self.state.set_paused(!self.state.is_paused()),
+            KeyCode::Up => self.scroll = self.scroll.saturating_add(1),
+            KeyCode::Down => self.scroll = self.scroll.saturating_sub(1),
+            _ => {}
+        }
+        true
+    }
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, app: &mut App) -> Result<()> {
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+        if event::poll(Duration::from_millis(500))? {
+            if let TermEvent::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !app.handle_key(key.code) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Blocks until the user quits; call from a blocking thread.
+pub fn run(state: Arc<State>, domains: Vec<String>) -> Result<()> {
+    let mut app = App {
+        state,
+        domains,
+        scroll: 0,
+    };
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut app);
+    ratatui::restore();
+    result
+}
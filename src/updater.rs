@@ -0,0 +1,1210 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Notify, Semaphore};
+
+use crate::config::{Ttl, VerifyConfig};
+use crate::diff;
+use crate::error::CfbindError;
+use crate::exit::{self, Status};
+use crate::ip;
+use crate::provider::{DnsProvider, Record, RecordContent, RecordSpec, RecordType};
+use crate::reporting;
+use crate::rules::Rules;
+use crate::secret;
+use crate::state::{EventKind, Failure, State};
+use crate::store::WriteResult;
+use crate::template;
+use crate::verify;
+use crate::window::Windows;
+
+/// First retry delay after the provider or echo service was unreachable;
+/// doubles on each further failure, up to the update interval.
+const RETRY_MIN: Duration = Duration::from_secs(5);
+
+/// Longest wait between attempts to detect the IP, however long the
+/// interval, so a local link coming back is noticed soon.
+const DETECTION_RETRY_MAX: Duration = Duration::from_secs(60);
+
+/// Longest wait after being rate limited; Cloudflare counts requests over
+/// five-minute windows.
+const RATE_LIMIT_MAX: Duration = Duration::from_secs(5 * 60);
+
+/// The backoff after a failed cycle: a failed detection is retried up to
+/// every minute, an unreachable provider up to every interval, rate limits
+/// later, and anything else waits for the next interval.
+fn next_retry(
+    previous: Option<Duration>,
+    interval: Duration,
+    error: &anyhow::Error,
+) -> Option<Duration> {
+    if Failure::of(error) == Failure::Detection {
+        let longest = DETECTION_RETRY_MAX.min(interval);
+        return Some(previous.map_or(RETRY_MIN, |d| (d * 2).min(longest)));
+    }
+    if CfbindError::is_rate_limited(error) {
+        let longest = RATE_LIMIT_MAX.max(interval);
+        return Some(previous.map_or(interval, |d| (d * 2).max(interval).min(longest)));
+    }
+    if exit::classify(error) != Status::Network {
+        return None;
+    }
+    Some(previous.map_or(RETRY_MIN, |d| (d * 2).min(interval)))
+}
+
+/// A spec that writes `record` back exactly as it was.
+fn restore_spec(record: &Record) -> RecordSpec {
+    RecordSpec {
+        name: record.name.clone(),
+        content: record.content.clone(),
+        ttl: Some(record.ttl),
+        proxied: record.proxied,
+        comment: None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Unchanged,
+    Updated,
+    /// A write was needed but skipped because of `--dry-run`.
+    WouldUpdate,
+}
+
+/// A write `check` found necessary, along with what it replaces.
+pub struct Pending {
+    pub spec: RecordSpec,
+    pub existing: Option<Record>,
+}
+
+impl Pending {
+    /// What the write would change, field by field.
+    pub fn diff(&self, color: bool) -> String {
+        diff::render(self.existing.as_ref(), &self.spec, color)
+    }
+}
+
+pub struct Updater {
+    provider: Arc<dyn DnsProvider>,
+    domain: String,
+    proxied: bool,
+    interval: Duration,
+    state: Arc<State>,
+    trigger: Arc<Notify>,
+    dry_run: bool,
+    ttl: Option<Ttl>,
+    comment: Option<String>,
+    /// Fixed content published instead of the detected address.
+    content: Option<RecordContent>,
+    verify: VerifyConfig,
+    limit: Option<Arc<Semaphore>>,
+    rules: Option<Arc<Rules>>,
+    windows: Option<Arc<Windows>>,
+    /// The latest detected address not yet confirmed published, used while
+    /// the echo service is unreachable.
+    queued: Mutex<Option<IpAddr>>,
+    via: Vec<IpAddr>,
+}
+
+impl Updater {
+    pub fn new(
+        provider: Arc<dyn DnsProvider>,
+        domain: String,
+        proxied: bool,
+        interval: Duration,
+        state: Arc<State>,
+    ) -> Self {
+        Updater {
+            provider,
+            domain,
+            proxied,
+            interval,
+            trigger: state.subscribe_trigger(),
+            state,
+            dry_run: false,
+            ttl: None,
+            comment: None,
+            content: None,
+            verify: VerifyConfig::default(),
+            limit: None,
+            rules: None,
+            windows: None,
+            queued: Mutex::new(None),
+            via: Vec::new(),
+        }
+    }
+
+    pub fn verify(mut self, verify: VerifyConfig) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Hold a permit from `limit` for the duration of each sync.
+    pub fn limit(mut self, limit: Arc<Semaphore>) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Let `rules` decide each write before it is made.
+    pub fn rules(mut self, rules: Option<Arc<Rules>>) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Hold or re-assert the record at the times `windows` give.
+    pub fn windows(mut self, windows: Option<Arc<Windows>>) -> Self {
+        self.windows = windows;
+        self
+    }
+
+    /// Only report what would change instead of writing records.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Write records with this TTL instead of leaving it to the provider.
+    pub fn ttl(mut self, ttl: Option<Ttl>) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Comment template rendered into every record this writes.
+    pub fn comment(mut self, comment: Option<String>) -> Self {
+        self.comment = comment;
+        self
+    }
+
+    /// Publish `content` instead of the detected address.
+    pub fn content(mut self, content: Option<RecordContent>) -> Self {
+        self.content = content;
+        self
+    }
+
+    /// Keep one record per WAN link instead of a single record; see [`Updater::sync_set`].
+    pub fn via(mut self, via: Vec<IpAddr>) -> Self {
+        self.via = via;
+        self
+    }
+
+    /// Whether a hold window is running.
+    fn holding(&self) -> bool {
+        self.windows.as_ref().is_some_and(|w| w.holding())
+    }
+
+    /// Whether a force window wants the record rewritten.
+    fn force_due(&self) -> bool {
+        self.windows.as_ref().is_some_and(|w| w.force_due())
+    }
+
+    /// Reports a write a hold window kept from happening.
+    fn held(&self, planned: &str) {
+        log::info!("A hold window keeps {} from changing", self.domain);
+        self.state.notify(
+            &self.domain,
+            EventKind::Skipped,
+            format!("held by a window ({})", planned),
+        );
+    }
+
+    /// Whether this updater manages a round-robin record set.
+    pub fn is_set(&self) -> bool {
+        !self.via.is_empty()
+    }
+
+    pub fn provider(&self) -> &Arc<dyn DnsProvider> {
+        &self.provider
+    }
+
+    pub async fn run(self) -> Result<()> {
+        let mut retry: Option<Duration> = None;
+        let mut failures = HashMap::new();
+        let mut synced = false;
+        loop {
+            if self.state.is_paused() {
+                log::debug!("Skipping {} while paused", self.domain);
+                self.state
+                    .notify(&self.domain, EventKind::Skipped, "paused".to_string());
+            } else if let Err(e) = self.sync().await {
+                if let Some(vpn) = e.downcast_ref::<ip::VpnActive>() {
+                    // Not a failure: the records are left alone on purpose.
+                    log::info!("Skipping {}: {}", self.domain, vpn);
+                    self.state
+                        .notify(&self.domain, EventKind::Skipped, vpn.to_string());
+                    self.state.cycle_finished();
+                    self.state.wait(self.interval, &self.trigger).await;
+                    continue;
+                }
+                log::error!("Failed to update {}: {:#}", self.domain, e);
+                self.state
+                    .record(&self.domain, EventKind::Error, secret::describe(&e));
+                if !synced && exit::classify(&e) == Status::Auth {
+                    // Credentials that never worked won't start working by retrying.
+                    return Err(e);
+                }
+                let failure = Failure::of(&e);
+                self.state.failed(failure);
+                let count = failures.entry(failure).or_insert(0);
+                *count += 1;
+                if *count == reporting::REPORT_AFTER {
+                    reporting::repeated_failure(&self.domain, failure, *count, &e);
+                }
+                retry = next_retry(retry, self.interval, &e);
+                if let Some(delay) = retry {
+                    log::warn!("Retrying {} in {}s", self.domain, delay.as_secs());
+                }
+            } else {
+                retry = None;
+                failures.clear();
+                synced = true;
+            }
+            self.state.cycle_finished();
+            self.state
+                .wait(retry.unwrap_or(self.interval), &self.trigger)
+                .await;
+        }
+    }
+
+    pub async fn sync(&self) -> Result<Outcome> {
+        let _permit = match &self.limit {
+            Some(limit) => Some(limit.acquire().await?),
+            None => None,
+        };
+        if self.is_set() {
+            return self.sync_set().await;
+        }
+        let Some(pending) = self.check().await? else {
+            return Ok(Outcome::Unchanged);
+        };
+        if self.dry_run {
+            log::info!("{}", pending.diff(false).trim_end());
+            return Ok(Outcome::WouldUpdate);
+        }
+        match self.provider.upsert_record(&pending.spec).await {
+            Ok(record) => self.finish(pending, record).await,
+            Err(e) => {
+                self.write_failed(&pending, &e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Reconciles the name's whole record set with the public IP of every
+    /// `via` link: matching records are kept, stale ones rewritten to missing
+    /// addresses, and the rest created or pruned. A record type with no
+    /// reachable link is left alone rather than emptied. Update rules and
+    /// hold/force windows gate each write as they do for a single record.
+    async fn sync_set(&self) -> Result<Outcome> {
+        if self.holding() {
+            self.held("record set");
+            return Ok(Outcome::Unchanged);
+        }
+        let mut addresses = Vec::new();
+        for local in &self.via {
+            match ip::detect_via(*local).await {
+                Ok(ip) => addresses.push(ip),
+                Err(e) => log::warn!("Could not detect the IP via {}: {:#}", local, e),
+            }
+        }
+        if addresses.is_empty() {
+            let reason = format!("no link of {} answered", self.domain);
+            return Err(ip::DetectionFailed(reason).into());
+        }
+        addresses.sort();
+        addresses.dedup();
+        self.state.set_current_ip(addresses[0]);
+        let proxied = self.proxied && self.provider.supports_proxy();
+        // A force window rewrites the matching records too.
+        let force = self.force_due();
+        if force {
+            log::info!(
+                "Re-asserting the records of {} in a force window",
+                self.domain
+            );
+        }
+        let mut changed = false;
+        let mut applied = Vec::new();
+        for record_type in [RecordType::A, RecordType::Aaaa] {
+            let desired: Vec<RecordContent> = addresses
+                .iter()
+                .map(|ip| RecordContent::from(*ip))
+                .filter(|content| content.record_type() == record_type)
+                .collect();
+            if desired.is_empty() {
+                continue;
+            }
+            let mut kept: Vec<RecordContent> = Vec::new();
+            let mut stale = Vec::new();
+            for record in self
+                .provider
+                .list_records(&self.domain, record_type)
+                .await?
+            {
+                if !force
+                    && desired.contains(&record.content)
+                    && !kept.contains(&record.content)
+                    && record.proxied == proxied
+                {
+                    kept.push(record.content);
+                } else {
+                    stale.push(record);
+                }
+            }
+            let mut missing = desired.into_iter().filter(|c| !kept.contains(c));
+            let mut stale = stale.into_iter();
+            loop {
+                let (old, new) = match (stale.next(), missing.next()) {
+                    (None, None) => break,
+                    pair => pair,
+                };
+                let spec = match new.map(|content| RecordSpec {
+                    name: self.domain.clone(),
+                    content,
+                    ttl: self.ttl.map(Ttl::seconds),
+                    proxied,
+                    comment: self.comment.as_deref().map(template::comment),
+                }) {
+                    Some(spec) => match self.ruled(spec, old.as_ref()).await? {
+                        Some(spec) => Some(spec),
+                        None => continue,
+                    },
+                    None => None,
+                };
+                changed = true;
+                if self.dry_run {
+                    log::warn!(
+                        "Dry run: not changing {} {} -> {}",
+                        self.domain,
+                        old.as_ref()
+                            .map_or("-".to_string(), |r| r.content.to_string()),
+                        spec.as_ref()
+                            .map_or("-".to_string(), |s| s.content.to_string())
+                    );
+                    continue;
+                }
+                let result = match (&old, &spec) {
+                    (Some(old), Some(spec)) => {
+                        self.provider.update_record(old, spec).await.map(Some)
+                    }
+                    (None, Some(spec)) => self.provider.create_record(spec).await.map(Some),
+                    (Some(old), None) => self.provider.delete_record(old).await.map(|_| None),
+                    (None, None) => unreachable!(),
+                };
+                let new_content = spec.as_ref().map(|s| &s.content);
+                let written = match result {
+                    Ok(written) => written,
+                    Err(e) => {
+                        if let Some(content) = new_content {
+                            self.state.record_write(
+                                &self.domain,
+                                old.as_ref().map(|r| &r.content),
+                                content,
+                                WriteResult::Failed,
+                                Some(&secret::describe(&e)),
+                            );
+                        }
+                        self.undo_set(applied).await;
+                        return Err(e);
+                    }
+                };
+                applied.push((old.clone(), written));
+                log::info!(
+                    "{}: {} -> {}",
+                    self.domain,
+                    old.as_ref()
+                        .map_or("-".to_string(), |r| r.content.to_string()),
+                    new_content.map_or("-".to_string(), |c| c.to_string())
+                );
+                if let Some(content) = new_content {
+                    self.state.record_write(
+                        &self.domain,
+                        old.as_ref().map(|r| &r.content),
+                        content,
+                        WriteResult::Updated,
+                        None,
+                    );
+                }
+            }
+        }
+        let contents: Vec<String> = addresses.iter().map(|ip| ip.to_string()).collect();
+        let message = contents.join(", ");
+        if !changed {
+            self.state
+                .record(&self.domain, EventKind::Unchanged, message);
+            return Ok(Outcome::Unchanged);
+        }
+        if self.dry_run {
+            return Ok(Outcome::WouldUpdate);
+        }
+        if let Some(windows) = &self.windows {
+            windows.forced();
+        }
+        self.state.record(&self.domain, EventKind::Updated, message);
+        Ok(Outcome::Updated)
+    }
+
+    /// Reverts the writes already made to a record set, newest first, after
+    /// a later one failed, so the set isn't left half on the old addresses.
+    async fn undo_set(&self, applied: Vec<(Option<Record>, Option<Record>)>) {
+        for (old, written) in applied.into_iter().rev() {
+            let result = match (&old, &written) {
+                (Some(old), Some(written)) => self
+                    .provider
+                    .update_record(written, &restore_spec(old))
+                    .await
+                    .map(|_| ()),
+                (Some(old), None) => self
+                    .provider
+                    .create_record(&restore_spec(old))
+                    .await
+                    .map(|_| ()),
+                (None, Some(written)) => self.provider.delete_record(written).await,
+                (None, None) => Ok(()),
+            };
+            let content = |r: &Option<Record>| {
+                r.as_ref()
+                    .map_or("-".to_string(), |r| r.content.to_string())
+            };
+            match result {
+                Ok(()) => log::warn!(
+                    "Reverted {} {} -> {} after a failed write",
+                    self.domain,
+                    content(&written),
+                    content(&old)
+                ),
+                Err(e) => log::error!(
+                    "Could not revert {} {} -> {}: {:#}",
+                    self.domain,
+                    content(&written),
+                    content(&old),
+                    e
+                ),
+            }
+        }
+    }
+
+    /// Compares the record with the detected IP, or the fixed content if one
+    /// is set; `None` when it is already up to date. In dry-run mode the
+    /// pending change is reported here.
+    pub async fn check(&self) -> Result<Option<Pending>> {
+        let content = match &self.content {
+            Some(content) => content.clone(),
+            None => {
+                let ip = self.detect().await?;
+                self.state.set_current_ip(ip);
+                RecordContent::from(ip)
+            }
+        };
+        let spec = self.spec(content);
+        if !self.force_due() && self.cached(&spec) {
+            return Ok(None);
+        }
+        let existing = self
+            .provider
+            .get_record(&spec.name, spec.content.record_type())
+            .await?;
+        self.plan(spec, existing).await
+    }
+
+    /// Detects the public IP, falling back to the queued one while the echo
+    /// service is unreachable.
+    async fn detect(&self) -> Result<IpAddr> {
+        match ip::detect().await {
+            Ok(ip) => {
+                *self.queued.lock().unwrap() = Some(ip);
+                Ok(ip)
+            }
+            // The queued IP may be stale; wait for the VPN to go down instead.
+            Err(e) if e.is::<ip::VpnActive>() => Err(e),
+            Err(e) => match *self.queued.lock().unwrap() {
+                Some(ip) => {
+                    log::warn!("Could not detect the IP ({:#}), retrying queued {}", e, ip);
+                    self.state.failed(Failure::Detection);
+                    Ok(ip)
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    fn spec(&self, content: RecordContent) -> RecordSpec {
+        // Cloudflare can't proxy TXT records.
+        let proxiable = content.record_type() != RecordType::Txt;
+        RecordSpec {
+            name: self.domain.clone(),
+            content,
+            ttl: self.ttl.map(Ttl::seconds),
+            proxied: self.proxied && proxiable && self.provider.supports_proxy(),
+            comment: self.comment.as_deref().map(template::comment),
+        }
+    }
+
+    /// Whether the recently published record already matches `spec`.
+    fn cached(&self, spec: &RecordSpec) -> bool {
+        match self.state.published(&spec.name) {
+            Some(record) if spec.matches(&record) => {
+                log::info!("{} is up to date ({}, cached)", spec.name, record.content);
+                self.state
+                    .record(&spec.name, EventKind::Unchanged, record.content.to_string());
+                self.queued.lock().unwrap().take();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// `spec` as the update rules would have it written, or `None` when they
+    /// hold it back.
+    async fn ruled(
+        &self,
+        spec: RecordSpec,
+        existing: Option<&Record>,
+    ) -> Result<Option<RecordSpec>> {
+        let Some(rules) = &self.rules else {
+            return Ok(Some(spec));
+        };
+        let planned = spec.content.clone();
+        let decided = rules.decide(spec, existing).await?;
+        if decided.is_none() {
+            log::info!("Update rules hold {} -> {} back", self.domain, planned);
+            self.state.notify(
+                &self.domain,
+                EventKind::Skipped,
+                format!("held back by the update rules ({})", planned),
+            );
+        }
+        Ok(decided)
+    }
+
+    /// Compares `spec` with the record the provider returned, and lets the
+    /// update rules have their say on any difference.
+    async fn plan(&self, spec: RecordSpec, existing: Option<Record>) -> Result<Option<Pending>> {
+        let force = self.force_due();
+        if !force && self.unchanged(&spec, existing.as_ref()) {
+            return Ok(None);
+        }
+        if self.holding() {
+            self.held(&spec.content.to_string());
+            return Ok(None);
+        }
+        let Some(spec) = self.ruled(spec, existing.as_ref()).await? else {
+            return Ok(None);
+        };
+        if self.rules.is_some() && !force && self.unchanged(&spec, existing.as_ref()) {
+            return Ok(None);
+        }
+        if self.dry_run {
+            log::warn!("Dry run: not writing {} -> {}", spec.name, spec.content);
+        }
+        if force && existing.as_ref().is_some_and(|r| spec.matches(r)) {
+            log::info!(
+                "Re-asserting {} ({}) in a force window",
+                spec.name,
+                spec.content
+            );
+        }
+        Ok(Some(Pending { spec, existing }))
+    }
+
+    /// Whether `existing` already matches `spec`, which is then recorded as up to date.
+    fn unchanged(&self, spec: &RecordSpec, existing: Option<&Record>) -> bool {
+        let Some(record) = existing.filter(|r| spec.matches(r)) else {
+            return false;
+        };
+        log::info!("{} is up to date ({})", spec.name, record.content);
+        self.state
+            .record(&spec.name, EventKind::Unchanged, record.content.to_string());
+        self.state.set_published(&spec.name, record.clone());
+        self.queued.lock().unwrap().take();
+        true
+    }
+
+    /// Records, verifies and if need be rolls back a write made for `pending`.
+    pub async fn finish(&self, pending: Pending, record: Record) -> Result<Outcome> {
+        let Pending { spec, existing } = pending;
+        if let Some(windows) = &self.windows {
+            windows.forced();
+        }
+        self.state
+            .record(&spec.name, EventKind::Updated, record.content.to_string());
+        self.state.set_published(&spec.name, record.clone());
+        self.queued.lock().unwrap().take();
+        self.state.record_write(
+            &spec.name,
+            existing.as_ref().map(|r| &r.content),
+            &record.content,
+            WriteResult::Updated,
+            None,
+        );
+        if self.verify_write(&spec).await == Some(false) && self.verify.rollback {
+            self.rollback(&spec, existing.as_ref(), "failed verification")
+                .await?;
+            bail!(
+                "{} -> {} failed verification and was rolled back",
+                spec.name,
+                spec.content
+            );
+        }
+        Ok(Outcome::Updated)
+    }
+
+    /// Logs a write for `pending` that the provider rejected.
+    pub fn write_failed(&self, pending: &Pending, error: &anyhow::Error) {
+        self.state.forget_published(&pending.spec.name);
+        self.state.record_write(
+            &pending.spec.name,
+            pending.existing.as_ref().map(|r| &r.content),
+            &pending.spec.content,
+            WriteResult::Failed,
+            Some(&secret::describe(error)),
+        );
+    }
+
+    /// Undoes whatever part of a failed multi-record write reached this
+    /// record, so a cycle never leaves some names on the new address and
+    /// the rest on the old one. Records the write never touched are left be.
+    pub async fn restore(&self, pending: &Pending) {
+        let spec = &pending.spec;
+        let current = match self
+            .provider
+            .get_record(&spec.name, spec.content.record_type())
+            .await
+        {
+            Ok(current) => current,
+            Err(e) => {
+                log::error!("Could not check {} for a rollback: {:#}", spec.name, e);
+                return;
+            }
+        };
+        let untouched = match (&current, &pending.existing) {
+            (None, None) => true,
+            (Some(current), Some(previous)) => {
+                current.content == previous.content && current.proxied == previous.proxied
+            }
+            _ => false,
+        };
+        if untouched {
+            return;
+        }
+        if let Err(e) = self
+            .rollback(
+                spec,
+                pending.existing.as_ref(),
+                "a failed write in the same cycle",
+            )
+            .await
+        {
+            log::error!("Could not roll {} back: {:#}", spec.name, e);
+        }
+    }
+
+    /// Puts the record back the way the pre-update read found it.
+    async fn rollback(
+        &self,
+        spec: &RecordSpec,
+        previous: Option<&Record>,
+        reason: &str,
+    ) -> Result<()> {
+        self.state.forget_published(&spec.name);
+        match previous {
+            Some(previous) => {
+                self.provider.upsert_record(&restore_spec(previous)).await?;
+                log::error!(
+                    "Rolled back {} to {} after {}",
+                    spec.name,
+                    previous.content,
+                    reason
+                );
+                self.state.record(
+                    &spec.name,
+                    EventKind::RolledBack,
+                    previous.content.to_string(),
+                );
+                self.state.record_write(
+                    &spec.name,
+                    Some(&spec.content),
+                    &previous.content,
+                    WriteResult::RolledBack,
+                    None,
+                );
+            }
+            None => {
+                if let Some(created) = self
+                    .provider
+                    .get_record(&spec.name, spec.content.record_type())
+                    .await?
+                {
+                    self.provider.delete_record(&created).await?;
+                }
+                log::error!("Removed newly created {} after {}", spec.name, reason);
+                self.state
+                    .record(&spec.name, EventKind::RolledBack, "-".to_string());
+                self.state.record_write(
+                    &spec.name,
+                    Some(&spec.content),
+                    &spec.content,
+                    WriteResult::RolledBack,
+                    Some("record deleted"),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the configured post-write checks; `None` when none apply.
+    async fn verify_write(&self, spec: &RecordSpec) -> Option<bool> {
+        if !self.verify.authoritative && !self.verify.doh {
+            return None;
+        }
+        if spec.proxied {
+            // Resolvers hand out the proxy's addresses, never the origin.
+            log::debug!("Not verifying proxied record {}", spec.name);
+            return None;
+        }
+        if spec.content.ip().is_none() {
+            log::debug!(
+                "Not verifying {} record {}",
+                spec.content.record_type(),
+                spec.name
+            );
+            return None;
+        }
+        let timeout = Duration::from_secs(self.verify.timeout);
+        let mut verified = true;
+        if self.verify.authoritative {
+            verified &= match self.verify_authoritative(spec, timeout).await {
+                Ok(served) => served,
+                Err(e) => {
+                    log::warn!("Could not verify {}: {:#}", spec.name, e);
+                    false
+                }
+            };
+        }
+        if self.verify.doh {
+            let url = self
+                .verify
+                .doh_url
+                .as_deref()
+                .unwrap_or(verify::DEFAULT_DOH_URL);
+            verified &= match verify::doh(url, &spec.name, &spec.content, timeout).await {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    log::warn!("Could not verify {} over DoH: {:#}", spec.name, e);
+                    false
+                }
+            };
+        }
+        if !verified {
+            log::warn!(
+                "{} -> {} was accepted but could not be verified within {}s",
+                spec.name,
+                spec.content,
+                self.verify.timeout
+            );
+        }
+        self.state.set_verified(&spec.name, verified);
+        let kind = if verified {
+            EventKind::Verified
+        } else {
+            EventKind::VerifyFailed
+        };
+        self.state
+            .notify(&spec.name, kind, spec.content.to_string());
+        Some(verified)
+    }
+
+    async fn verify_authoritative(&self, spec: &RecordSpec, timeout: Duration) -> Result<bool> {
+        let nameservers = self.provider.nameservers(&spec.name).await?;
+        if nameservers.is_empty() {
+            log::debug!("No authoritative nameservers known for {}", spec.name);
+            return Ok(true);
+        }
+        verify::authoritative(&nameservers, &spec.name, &spec.content, timeout).await
+    }
+}
+
+/// Hosts of one zone kept on the same IP. Each cycle detects the IP once,
+/// looks all records up together and hands every pending write to the
+/// provider in one call, so it can batch them.
+pub struct Group {
+    zone: String,
+    provider: Arc<dyn DnsProvider>,
+    updaters: Vec<Updater>,
+    interval: Duration,
+    state: Arc<State>,
+    trigger: Arc<Notify>,
+}
+
+impl Group {
+    /// `updaters` must share `provider`; their dry-run, verify and limit
+    /// settings are used as configured.
+    pub fn new(
+        zone: String,
+        provider: Arc<dyn DnsProvider>,
+        updaters: Vec<Updater>,
+        interval: Duration,
+        state: Arc<State>,
+    ) -> Self {
+        Group {
+            zone,
+            provider,
+            updaters,
+            interval,
+            trigger: state.subscribe_trigger(),
+            state,
+        }
+    }
+
+    pub async fn run(self) -> Result<()> {
+        let mut retry: Option<Duration> = None;
+        let mut failures = HashMap::new();
+        let mut synced = false;
+        loop {
+            if self.state.is_paused() {
+                log::debug!("Skipping {} while paused", self.zone);
+                for updater in &self.updaters {
+                    self.state
+                        .notify(&updater.domain, EventKind::Skipped, "paused".to_string());
+                }
+            } else if let Err(e) = self.sync().await {
+                if let Some(vpn) = e.downcast_ref::<ip::VpnActive>() {
+                    log::info!("Skipping hosts of {}: {}", self.zone, vpn);
+                    for updater in &self.updaters {
+                        self.state
+                            .notify(&updater.domain, EventKind::Skipped, vpn.to_string());
+                    }
+                    self.state.cycle_finished();
+                    self.state.wait(self.interval, &self.trigger).await;
+                    continue;
+                }
+                log::error!("Failed to update hosts of {}: {:#}", self.zone, e);
+                for updater in &self.updaters {
+                    self.state
+                        .record(&updater.domain, EventKind::Error, secret::describe(&e));
+                }
+                if !synced && exit::classify(&e) == Status::Auth {
+                    return Err(e);
+                }
+                let failure = Failure::of(&e);
+                self.state.failed(failure);
+                let count = failures.entry(failure).or_insert(0);
+                *count += 1;
+                if *count == reporting::REPORT_AFTER {
+                    reporting::repeated_failure(&self.zone, failure, *count, &e);
+                }
+                retry = next_retry(retry, self.interval, &e);
+                if let Some(delay) = retry {
+                    log::warn!("Retrying {} in {}s", self.zone, delay.as_secs());
+                }
+            } else {
+                retry = None;
+                failures.clear();
+                synced = true;
+            }
+            self.state.cycle_finished();
+            self.state
+                .wait(retry.unwrap_or(self.interval), &self.trigger)
+                .await;
+        }
+    }
+
+    async fn sync(&self) -> Result<()> {
+        let Some(first) = self.updaters.first() else {
+            return Ok(());
+        };
+        let _permit = match &first.limit {
+            Some(limit) => Some(limit.acquire().await?),
+            None => None,
+        };
+        let ip = first.detect().await?;
+        self.state.set_current_ip(ip);
+        let mut lookups = Vec::new();
+        for updater in &self.updaters {
+            let spec = updater.spec(RecordContent::from(ip));
+            if updater.force_due() || !updater.cached(&spec) {
+                lookups.push((updater, spec));
+            }
+        }
+        if lookups.is_empty() {
+            return Ok(());
+        }
+        let names: Vec<String> = lookups.iter().map(|(_, spec)| spec.name.clone()).collect();
+        let existing = self
+            .provider
+            .get_records(&names, RecordContent::from(ip).record_type())
+            .await?;
+        let mut pending = Vec::new();
+        for ((updater, spec), existing) in lookups.into_iter().zip(existing) {
+            if let Some(p) = updater.plan(spec, existing).await? {
+                pending.push((updater, p));
+            }
+        }
+        if first.dry_run {
+            for (_, p) in &pending {
+                log::info!("{}", p.diff(false).trim_end());
+            }
+            return Ok(());
+        }
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let specs: Vec<RecordSpec> = pending.iter().map(|(_, p)| p.spec.clone()).collect();
+        let existing: Vec<Option<Record>> =
+            pending.iter().map(|(_, p)| p.existing.clone()).collect();
+        let records = match self.provider.upsert_records(&specs, &existing).await {
+            Ok(records) => records,
+            Err(e) => {
+                // Part of the group may have been written; put it all back.
+                for (updater, p) in &pending {
+                    updater.write_failed(p, &e);
+                    updater.restore(p).await;
+                }
+                return Err(e);
+            }
+        };
+        for ((updater, p), record) in pending.into_iter().zip(records) {
+            if let Err(e) = updater.finish(p, record).await {
+                log::error!("Failed to update {}: {:#}", updater.domain, e);
+                self.state
+                    .record(&updater.domain, EventKind::Error, secret::describe(&e));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::config::{Config, IpFamily};
+
+    const IP: &str = "198.51.100.7";
+    const OLD: &str = "198.51.100.1";
+
+    /// Answers every detection with `IP`, so groups never reach the network.
+    struct FixedIp;
+
+    #[async_trait]
+    impl ip::IpSource for FixedIp {
+        fn name(&self) -> String {
+            "fixed".to_string()
+        }
+
+        async fn detect(&self, _family: Option<IpFamily>) -> Result<IpAddr> {
+            Ok(IP.parse()?)
+        }
+    }
+
+    /// An in-memory provider rejecting writes to the names in `failing`.
+    #[derive(Default)]
+    struct Fake {
+        records: Mutex<HashMap<String, Record>>,
+        failing: Vec<String>,
+        batches: Mutex<Vec<usize>>,
+    }
+
+    impl Fake {
+        fn with(names: &[&str], failing: &[&str]) -> Arc<Fake> {
+            let fake = Fake {
+                failing: failing.iter().map(|name| name.to_string()).collect(),
+                ..Fake::default()
+            };
+            for name in names {
+                fake.records
+                    .lock()
+                    .unwrap()
+                    .insert(name.to_string(), record(name, OLD));
+            }
+            Arc::new(fake)
+        }
+
+        fn content(&self, name: &str) -> Option<String> {
+            let records = self.records.lock().unwrap();
+            records.get(name).map(|r| r.content.to_string())
+        }
+    }
+
+    fn record(name: &str, ip: &str) -> Record {
+        Record {
+            id: name.to_string(),
+            name: name.to_string(),
+            content: RecordContent::A(ip.parse().unwrap()),
+            ttl: 1,
+            proxied: false,
+        }
+    }
+
+    #[async_trait]
+    impl DnsProvider for Fake {
+        async fn get_record(&self, name: &str, _record_type: RecordType) -> Result<Option<Record>> {
+            Ok(self.records.lock().unwrap().get(name).cloned())
+        }
+
+        async fn upsert_record(&self, spec: &RecordSpec) -> Result<Record> {
+            if self.failing.contains(&spec.name) {
+                bail!("{} rejected", spec.name);
+            }
+            let written = record(&spec.name, &spec.content.to_string());
+            self.records
+                .lock()
+                .unwrap()
+                .insert(spec.name.clone(), written.clone());
+            Ok(written)
+        }
+
+        async fn upsert_records(
+            &self,
+            specs: &[RecordSpec],
+            _existing: &[Option<Record>],
+        ) -> Result<Vec<Record>> {
+            self.batches.lock().unwrap().push(specs.len());
+            let mut records = Vec::with_capacity(specs.len());
+            for spec in specs {
+                records.push(self.upsert_record(spec).await?);
+            }
+            Ok(records)
+        }
+
+        async fn delete_record(&self, record: &Record) -> Result<()> {
+            self.records.lock().unwrap().remove(&record.name);
+            Ok(())
+        }
+    }
+
+    /// State kept in a file of its own, so tests don't share published records.
+    fn state(test: &str) -> Arc<State> {
+        let path =
+            std::env::temp_dir().join(format!("cfbind-{}-{}.json", test, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        State::open(&Config {
+            state_file: Some(path),
+            ..Config::default()
+        })
+        .unwrap()
+    }
+
+    fn group(provider: Arc<Fake>, names: &[&str], state: Arc<State>) -> Group {
+        ip::register(Arc::new(FixedIp));
+        let updaters = names
+            .iter()
+            .map(|name| {
+                Updater::new(
+                    provider.clone(),
+                    name.to_string(),
+                    false,
+                    Duration::from_secs(60),
+                    Arc::clone(&state),
+                )
+            })
+            .collect();
+        Group::new(
+            "example.com".to_string(),
+            provider,
+            updaters,
+            Duration::from_secs(60),
+            state,
+        )
+    }
+
+    #[tokio::test]
+    async fn group_writes_the_stale_hosts_in_one_call() {
+        let fake = Fake::with(&["a.example.com", "b.example.com"], &[]);
+        fake.records
+            .lock()
+            .unwrap()
+            .insert("b.example.com".to_string(), record("b.example.com", IP));
+        let names = ["a.example.com", "b.example.com", "c.example.com"];
+        group(fake.clone(), &names, state("group-writes"))
+            .sync()
+            .await
+            .unwrap();
+        assert_eq!(*fake.batches.lock().unwrap(), [2]);
+        for name in names {
+            assert_eq!(fake.content(name).as_deref(), Some(IP));
+        }
+    }
+
+    #[tokio::test]
+    async fn group_rolls_every_host_back_when_a_write_fails() {
+        let fake = Fake::with(&["a.example.com", "b.example.com"], &["b.example.com"]);
+        let names = ["a.example.com", "b.example.com", "c.example.com"];
+        let state = state("group-rollback");
+        let error = group(fake.clone(), &names, Arc::clone(&state))
+            .sync()
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "b.example.com rejected");
+        assert_eq!(fake.content("a.example.com").as_deref(), Some(OLD));
+        assert_eq!(fake.content("b.example.com").as_deref(), Some(OLD));
+        assert_eq!(fake.content("c.example.com"), None);
+        assert!(state.published("a.example.com").is_none());
+    }
+
+    #[tokio::test]
+    async fn group_writes_nothing_on_a_dry_run() {
+        let fake = Fake::with(&["a.example.com"], &[]);
+        let state = state("group-dry-run");
+        let mut group = group(fake.clone(), &["a.example.com"], state);
+        group.updaters = group
+            .updaters
+            .into_iter()
+            .map(|updater| updater.dry_run(true))
+            .collect();
+        group.sync().await.unwrap();
+        assert!(fake.batches.lock().unwrap().is_empty());
+        assert_eq!(fake.content("a.example.com").as_deref(), Some(OLD));
+    }
+
+    #[tokio::test]
+    async fn restore_puts_back_what_a_failed_cycle_wrote() {
+        let fake = Fake::with(&["a.example.com", "b.example.com"], &[]);
+        let state = state("restore");
+        let updater = |name: &str| {
+            Updater::new(
+                fake.clone(),
+                name.to_string(),
+                false,
+                Duration::from_secs(60),
+                Arc::clone(&state),
+            )
+        };
+        let rewritten = updater("a.example.com");
+        let spec = rewritten.spec(RecordContent::A(IP.parse().unwrap()));
+        fake.upsert_record(&spec).await.unwrap();
+        let pending = Pending {
+            spec,
+            existing: Some(record("a.example.com", OLD)),
+        };
+        rewritten.restore(&pending).await;
+        assert_eq!(fake.content("a.example.com").as_deref(), Some(OLD));
+
+        // A record the cycle created is removed again.
+        let created = updater("new.example.com");
+        let spec = created.spec(RecordContent::A(IP.parse().unwrap()));
+        fake.upsert_record(&spec).await.unwrap();
+        let pending = Pending {
+            spec,
+            existing: None,
+        };
+        created.restore(&pending).await;
+        assert_eq!(fake.content("new.example.com"), None);
+        assert_eq!(fake.content("b.example.com").as_deref(), Some(OLD));
+    }
+
+    #[test]
+    fn retries_failed_detections_sooner_than_the_interval() {
+        let error = anyhow::Error::new(ip::DetectionFailed("no answer".to_string()));
+        let interval = Duration::from_secs(300);
+        let first = next_retry(None, interval, &error);
+        assert_eq!(first, Some(RETRY_MIN));
+        let capped = next_retry(Some(DETECTION_RETRY_MAX), interval, &error);
+        assert_eq!(capped, Some(DETECTION_RETRY_MAX));
+    }
+}
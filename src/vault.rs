@@ -0,0 +1,96 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::config::{VaultAuth, VaultConfig};
+use crate::http;
+use crate::secret::Secret;
+
+/// Mounted into every pod by Kubernetes.
+const SERVICE_ACCOUNT_TOKEN: &str = "/var/run/secrets/kubernetes.io/serviceaccount/token";
+
+#[derive(Debug, Deserialize)]
+struct Auth {
+    client_token: Secret,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginResponse {
+    auth: Auth,
+}
+
+#[derive(Debug, Deserialize)]
+struct SecretData {
+    data: serde_json::Map<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SecretResponse {
+    data: SecretData,
+}
+
+fn url(config: &VaultConfig, path: &str) -> String {
+    format!("{}/v1/{}", config.address.trim_end_matches('/'), path)
+}
+
+async fn login(config: &VaultConfig, method: &str, body: Value) -> Result<Secret> {
+    let response: LoginResponse = http::client()
+        .post(url(config, &format!("auth/{}/login", method)))
+        .json(&body)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(response.auth.client_token)
+}
+
+/// A Vault token for the configured auth method. Logging in again for every
+/// read keeps nothing long-lived around between refreshes.
+async fn vault_token(config: &VaultConfig) -> Result<Secret> {
+    match &config.auth {
+        VaultAuth::Token { token } => match token {
+            Some(token) => Ok(token.clone()),
+            None => std::env::var("VAULT_TOKEN")
+                .map(Secret::new)
+                .context("No Vault token configured and VAULT_TOKEN is not set"),
+        },
+        VaultAuth::AppRole { role_id, secret_id } => {
+            let body = json!({ "role_id": role_id, "secret_id": secret_id.expose() });
+            login(config, "approle", body).await
+        }
+        VaultAuth::Kubernetes { role, jwt_file } => {
+            let jwt_file = jwt_file
+                .as_deref()
+                .unwrap_or(Path::new(SERVICE_ACCOUNT_TOKEN));
+            let jwt = std::fs::read_to_string(jwt_file)
+                .with_context(|| format!("Failed to read {}", jwt_file.display()))?;
+            let body = json!({ "role": role, "jwt": jwt.trim() });
+            login(config, "kubernetes", body).await
+        }
+    }
+}
+
+/// Reads the Cloudflare token from the configured KV v2 secret.
+pub async fn fetch_secret(config: &VaultConfig) -> Result<Secret> {
+    let token = vault_token(config)
+        .await
+        .context("Failed to log in to Vault")?;
+    let path = format!("{}/data/{}", config.mount, config.path);
+    let response: SecretResponse = http::client()
+        .get(url(config, &path))
+        .header("X-Vault-Token", token.expose())
+        .send()
+        .await?
+        .error_for_status()
+        .with_context(|| format!("Failed to read {} from Vault", path))?
+        .json()
+        .await?;
+    match response.data.data.get(&config.key) {
+        Some(Value::String(secret)) => Ok(Secret::new(secret.as_str())),
+        Some(_) => bail!("{} in Vault secret {} is not a string", config.key, path),
+        None => bail!("Vault secret {} has no {} field", path, config.key),
+    }
+}
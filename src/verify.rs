@@ -0,0 +1,176 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::TokioAsyncResolver;
+use serde::Deserialize;
+
+use crate::http;
+use crate::provider::{RecordContent, RecordType};
+
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+pub const DEFAULT_DOH_URL: &str = "https://cloudflare-dns.com/dns-query";
+
+/// Asks a single nameserver directly, bypassing any caching resolver.
+async fn query(server: IpAddr, name: &str, record_type: RecordType) -> Result<Vec<RecordContent>> {
+    let group = NameServerConfigGroup::from_ips_clear(&[server], 53, true);
+    let mut opts = ResolverOpts::default();
+    opts.cache_size = 0;
+    let resolver = TokioAsyncResolver::tokio(ResolverConfig::from_parts(None, vec![], group), opts);
+    let answers = match record_type {
+        RecordType::A => resolver
+            .ipv4_lookup(name)
+            .await?
+            .iter()
+            .map(|a| RecordContent::A(a.0))
+            .collect(),
+        RecordType::Aaaa => resolver
+            .ipv6_lookup(name)
+            .await?
+            .iter()
+            .map(|aaaa| RecordContent::Aaaa(aaaa.0))
+            .collect(),
+        RecordType::Txt => resolver
+            .txt_lookup(name)
+            .await?
+            .iter()
+            .map(|txt| {
+                let parts = txt.txt_data().iter();
+                RecordContent::Txt(parts.map(|part| String::from_utf8_lossy(part)).collect())
+            })
+            .collect(),
+        RecordType::Cname => bail!("CNAME records can't be verified"),
+    };
+    Ok(answers)
+}
+
+async fn resolve_nameserver(host: &str) -> Result<IpAddr> {
+    tokio::net::lookup_host((host, 53))
+        .await?
+        .next()
+        .map(|addr| addr.ip())
+        .with_context(|| format!("Nameserver {} did not resolve", host))
+}
+
+async fn served_everywhere(
+    servers: &[(String, IpAddr)],
+    name: &str,
+    expected: &RecordContent,
+) -> bool {
+    let mut ok = true;
+    for (host, ip) in servers {
+        match query(*ip, name, expected.record_type()).await {
+            Ok(answers) if answers.contains(expected) => {}
+            Ok(answers) => {
+                log::debug!("{} still serves {:?} for {}", host, answers, name);
+                ok = false;
+            }
+            Err(e) => {
+                log::debug!("Querying {} for {} failed: {:#}", host, name, e);
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+/// Polls every authoritative nameserver until all of them serve `expected`
+/// for `name`, giving up after `timeout`.
+pub async fn authoritative(
+    nameservers: &[String],
+    name: &str,
+    expected: &RecordContent,
+    timeout: Duration,
+) -> Result<bool> {
+    let mut servers = Vec::new();
+    for host in nameservers {
+        servers.push((host.clone(), resolve_nameserver(host).await?));
+    }
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        if served_everywhere(&servers, name, expected).await {
+            log::info!(
+                "{} -> {} is served by {}",
+                name,
+                expected,
+                nameservers.join(", ")
+            );
+            return Ok(true);
+        }
+        if tokio::time::Instant::now() + RETRY_DELAY > deadline {
+            return Ok(false);
+        }
+        log::warn!(
+            "{} -> {} is not visible on all authoritative nameservers yet, retrying",
+            name,
+            expected
+        );
+        tokio::time::sleep(RETRY_DELAY).await;
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DohAnswer {
+    data: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DohResponse {
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+/// Looks `name` up through a DNS-over-HTTPS JSON endpoint.
+pub async fn doh_query(
+    client: &reqwest::Client,
+    url: &str,
+    name: &str,
+    record_type: RecordType,
+) -> Result<Vec<IpAddr>> {
+    let response: DohResponse = client
+        .get(url)
+        .query(&[("name", name), ("type", &record_type.to_string())])
+        .header(reqwest::header::ACCEPT, "application/dns-json")
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    // CNAME hops show up as answers too; only keep the addresses.
+    Ok(response
+        .answer
+        .iter()
+        .filter_map(|a| a.data.parse().ok())
+        .collect())
+}
+
+/// Polls a DoH resolver until it returns `expected` for `name`, giving up after `timeout`.
+pub async fn doh(
+    url: &str,
+    name: &str,
+    expected: &RecordContent,
+    timeout: Duration,
+) -> Result<bool> {
+    let client = http::client();
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        match doh_query(&client, url, name, expected.record_type()).await {
+            Ok(answers) if answers.iter().any(|a| RecordContent::from(*a) == *expected) => {
+                log::info!("{} -> {} resolves over DoH", name, expected);
+                return Ok(true);
+            }
+            Ok(answers) => log::debug!("DoH still returns {:?} for {}", answers, name),
+            Err(e) => log::debug!("DoH lookup of {} failed: {:#}", name, e),
+        }
+        if tokio::time::Instant::now() + RETRY_DELAY > deadline {
+            return Ok(false);
+        }
+        log::warn!(
+            "{} -> {} does not resolve over DoH yet, retrying",
+            name,
+            expected
+        );
+        tokio::time::sleep(RETRY_DELAY).await;
+    }
+}
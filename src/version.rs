@@ -0,0 +1,90 @@
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::http;
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/lsgrep/cfbind/releases/latest";
+const CHECK_EVERY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    html_url: String,
+}
+
+/// Touched after each check, so restarts and one-shot runs share the daily limit.
+fn stamp_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("cfbind").join("version-check"))
+}
+
+/// `v1.2.3` as `[1, 2, 3]`, or `None` for anything that isn't plain numbers.
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version
+        .trim_start_matches('v')
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect()
+}
+
+/// Whether to look for new releases: `enabled` from the config wins, otherwise
+/// only when someone is watching the terminal.
+pub fn enabled(enabled: Option<bool>) -> bool {
+    enabled.unwrap_or_else(|| std::io::stderr().is_terminal())
+}
+
+/// Time left until the next check is due.
+fn due_in() -> Duration {
+    let checked = stamp_path()
+        .and_then(|path| std::fs::metadata(path).ok())
+        .and_then(|meta| meta.modified().ok());
+    let elapsed = checked.and_then(|t| SystemTime::now().duration_since(t).ok());
+    match elapsed {
+        Some(elapsed) => CHECK_EVERY.saturating_sub(elapsed),
+        None => Duration::ZERO,
+    }
+}
+
+async fn check() -> Result<()> {
+    let release: Release = http::client()
+        .get(LATEST_RELEASE_URL)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    if let Some(path) = stamp_path() {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, &release.tag_name)?;
+    }
+    let current = env!("CARGO_PKG_VERSION");
+    if let (Some(latest), Some(running)) =
+        (parse_version(&release.tag_name), parse_version(current))
+    {
+        if latest > running {
+            log::warn!(
+                "cfbind {} is available (running {}): {}",
+                release.tag_name.trim_start_matches('v'),
+                current,
+                release.html_url
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Checks for a newer release at most once a day for as long as it runs.
+pub async fn watch() {
+    tokio::time::sleep(due_in()).await;
+    loop {
+        if let Err(e) = check().await {
+            log::debug!("Version check failed: {:#}", e);
+        }
+        tokio::time::sleep(CHECK_EVERY).await;
+    }
+}
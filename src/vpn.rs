@@ -0,0 +1,38 @@
+use std::path::Path;
+
+use crate::route;
+
+/// Name prefixes of tunnel interfaces: OpenVPN and friends, WireGuard,
+/// macOS utun, Tailscale, ZeroTier and IPsec.
+const VPN_PREFIXES: [&str; 7] = ["tun", "tap", "wg", "utun", "tailscale", "zt", "ipsec"];
+
+/// `ARPHRD_NONE`, the link type of layer-3 tunnels such as tun and WireGuard.
+const ARPHRD_NONE: &str = "65534";
+
+/// Whether `interface` is a VPN tunnel: one of `extra`, a known tunnel name
+/// or a link without hardware addresses.
+pub fn is_vpn(interface: &str, extra: &[String]) -> bool {
+    if extra.iter().any(|name| name == interface) {
+        return true;
+    }
+    if VPN_PREFIXES
+        .iter()
+        .any(|prefix| interface.starts_with(prefix))
+    {
+        return true;
+    }
+    let link_type = Path::new("/sys/class/net").join(interface).join("type");
+    std::fs::read_to_string(link_type).is_ok_and(|t| t.trim() == ARPHRD_NONE)
+}
+
+/// The VPN interface that requests to the internet currently go through,
+/// if any; `v6` asks about IPv6 traffic instead of IPv4.
+pub async fn active(v6: bool, extra: &[String]) -> Option<String> {
+    let target = if v6 {
+        route::INTERNET_V6
+    } else {
+        route::INTERNET_V4
+    };
+    let interface = route::get(target).await?.interface;
+    is_vpn(&interface, extra).then_some(interface)
+}
@@ -0,0 +1,185 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+
+use crate::config::{WindowAction, WindowConfig};
+
+struct Window {
+    action: WindowAction,
+    start: NaiveTime,
+    end: NaiveTime,
+    days: Vec<Weekday>,
+    timezone: Option<Tz>,
+}
+
+fn time(value: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(value, "%H:%M")
+        .with_context(|| format!("Invalid window time {:?}, expected HH:MM", value))
+}
+
+impl Window {
+    fn new(config: &WindowConfig) -> Result<Window> {
+        let timezone = match &config.timezone {
+            Some(name) => Some(
+                name.parse::<Tz>()
+                    .map_err(|e| anyhow::anyhow!("Invalid time zone {:?}: {}", name, e))?,
+            ),
+            None => None,
+        };
+        Ok(Window {
+            action: config.action,
+            start: time(&config.start)?,
+            end: time(&config.end)?,
+            days: config.days.clone(),
+            timezone,
+        })
+    }
+
+    /// When the occurrence of the window that is running now started, in
+    /// the window's own time zone.
+    fn current(&self) -> Option<NaiveDateTime> {
+        let now = Utc::now();
+        let now = match self.timezone {
+            Some(tz) => now.with_timezone(&tz).naive_local(),
+            None => now.with_timezone(&Local).naive_local(),
+        };
+        let time = now.time();
+        let started = if self.start <= self.end {
+            (self.start <= time && time < self.end).then_some(now.date())
+        } else if time >= self.start {
+            Some(now.date())
+        } else if time < self.end {
+            now.date().pred_opt()
+        } else {
+            None
+        }?;
+        if !self.days.is_empty() && !self.days.contains(&started.weekday()) {
+            return None;
+        }
+        Some(started.and_time(self.start))
+    }
+}
+
+/// The windows that apply to one record.
+pub struct Windows {
+    windows: Vec<Window>,
+    /// The force window occurrence the record was last rewritten in.
+    forced: Mutex<Option<NaiveDateTime>>,
+}
+
+impl Windows {
+    /// `None` when `configs` is empty.
+    pub fn new(configs: &[WindowConfig]) -> Result<Option<Arc<Windows>>> {
+        if configs.is_empty() {
+            return Ok(None);
+        }
+        let windows = configs.iter().map(Window::new).collect::<Result<_>>()?;
+        Ok(Some(Arc::new(Windows {
+            windows,
+            forced: Mutex::new(None),
+        })))
+    }
+
+    /// Checks `configs` at startup, so a typo doesn't surface at the first window.
+    pub fn check(configs: &[WindowConfig]) -> Result<()> {
+        Windows::new(configs).map(|_| ())
+    }
+
+    /// Whether a hold window is running.
+    pub fn holding(&self) -> bool {
+        self.windows
+            .iter()
+            .any(|w| w.action == WindowAction::Hold && w.current().is_some())
+    }
+
+    /// The running force window occurrence the record wasn't rewritten in yet.
+    /// Holding wins over forcing.
+    fn due(&self) -> Option<NaiveDateTime> {
+        if self.holding() {
+            return None;
+        }
+        let forced = *self.forced.lock().unwrap();
+        self.windows
+            .iter()
+            .filter(|w| w.action == WindowAction::Force)
+            .filter_map(Window::current)
+            .find(|started| forced != Some(*started))
+    }
+
+    /// Whether the record should be rewritten even if it looks up to date.
+    pub fn force_due(&self) -> bool {
+        self.due().is_some()
+    }
+
+    /// Notes that the record was rewritten, so the running force window is done.
+    pub fn forced(&self) {
+        if let Some(started) = self.due() {
+            *self.forced.lock().unwrap() = Some(started);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(action: WindowAction, start: &str, end: &str) -> WindowConfig {
+        WindowConfig {
+            action,
+            start: start.to_string(),
+            end: end.to_string(),
+            days: Vec::new(),
+            timezone: None,
+        }
+    }
+
+    #[test]
+    fn parses_hours_and_minutes() {
+        assert_eq!(
+            time("07:30").unwrap(),
+            NaiveTime::from_hms_opt(7, 30, 0).unwrap()
+        );
+        assert!(time("7.30").is_err());
+        assert!(time("25:00").is_err());
+    }
+
+    #[test]
+    fn checks_times_and_time_zones() {
+        assert!(Windows::check(&[window(WindowAction::Hold, "22:00", "06:00")]).is_ok());
+        assert!(Windows::check(&[window(WindowAction::Hold, "22:00", "6")]).is_err());
+        let mut zoned = window(WindowAction::Force, "03:00", "04:00");
+        zoned.timezone = Some("Europe/Berlin".to_string());
+        assert!(Windows::check(&[zoned.clone()]).is_ok());
+        zoned.timezone = Some("Mars/Olympus".to_string());
+        assert!(Windows::check(&[zoned]).is_err());
+    }
+
+    #[test]
+    fn no_windows_means_none() {
+        assert!(Windows::new(&[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn an_empty_window_never_runs() {
+        let windows = Windows::new(&[window(WindowAction::Hold, "12:00", "12:00")])
+            .unwrap()
+            .unwrap();
+        assert!(!windows.holding());
+    }
+
+    #[test]
+    fn holding_wins_over_forcing() {
+        // Both span the whole day but the last minute.
+        let windows = Windows::new(&[
+            window(WindowAction::Hold, "00:00", "23:59"),
+            window(WindowAction::Force, "00:00", "23:59"),
+        ])
+        .unwrap()
+        .unwrap();
+        if windows.holding() {
+            assert!(!windows.force_due());
+        }
+    }
+}